@@ -1,18 +1,42 @@
 use anyhow::{Context, Result};
 use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
 use serde_json::{json, Value};
 use std::{
   collections::HashMap,
   env,
-  sync::{Arc, Mutex},
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+  },
+  time::{Duration, Instant},
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::WeakSender;
+use tokio::sync::{mpsc, watch};
+use tokio_tungstenite::{
+  accept_async,
+  tungstenite::{
+    protocol::{frame::coding::CloseCode, CloseFrame},
+    Message,
+  },
 };
-use tokio::net::TcpListener;
-use tokio::sync::mpsc;
-use tokio_tungstenite::{accept_async, tungstenite::Message};
 use tauri::Emitter;
 
+use bridge_debug::capture::{CaptureConfig, CaptureStatus, FieldRedactor};
+use bridge_debug::debug_http::{self, HttpRequest, HttpResponse};
+use bridge_debug::debug_hub::{self, ClientMode, DebugHub, Hop};
+use bridge_debug::debug_index::{self, DebugEndpoint};
+use bridge_debug::metrics::{self, Counter, Histogram, Metrics};
+use bridge_debug::Role;
+
+use crate::rate_limit::{Admission, ConnectionLimiter, RateLimitConfig, RateLimitCounters, SharedCounters};
+
 const APP_WS_PORT: u16 = 17342;
+/// Preferred debug port; taken by another process, the app falls back to an ephemeral one.
 const DEBUG_WS_PORT: u16 = 17888;
+/// How long `BridgeHandle::shutdown` waits for sidecars to receive their close frames.
+const SHUTDOWN_GRACE: Duration = Duration::from_millis(750);
 
 type ConnectionId = String;
 
@@ -20,31 +44,119 @@ type ConnectionId = String;
 struct ConnectionMeta {
   id: ConnectionId,
   browser: Option<String>,
+  /// Extension that launched the sidecar, as verified by the sidecar from its caller arguments.
+  extension_id: Option<String>,
   sender: mpsc::Sender<String>,
+  rate_limits: SharedCounters,
+  /// Unix timestamp (ms) at which the sidecar connected.
+  connected_at: i64,
+  traffic: Arc<TrafficCounters>,
+}
+
+/// Text messages exchanged with one sidecar.
+#[derive(Debug, Default)]
+struct TrafficCounters {
+  messages_in: AtomicU64,
+  bytes_in: AtomicU64,
+  messages_out: AtomicU64,
+  bytes_out: AtomicU64,
+}
+
+impl TrafficCounters {
+  fn record_in(&self, message: &str) {
+    self.messages_in.fetch_add(1, Ordering::Relaxed);
+    self.bytes_in.fetch_add(message.len() as u64, Ordering::Relaxed);
+  }
+
+  fn record_out(&self, message: &str) {
+    self.messages_out.fetch_add(1, Ordering::Relaxed);
+    self.bytes_out.fetch_add(message.len() as u64, Ordering::Relaxed);
+  }
+}
+
+/// One entry of the debug port's `/connections`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConnectionSummary {
+  connection_id: ConnectionId,
+  browser: Option<String>,
+  extension_id: Option<String>,
+  connected_at: i64,
+  messages_in: u64,
+  bytes_in: u64,
+  messages_out: u64,
+  bytes_out: u64,
+  /// Messages queued for the sidecar and not yet written to its socket.
+  queue_depth: usize,
+  rate_limits: RateLimitCounters,
+}
+
+impl ConnectionMeta {
+  fn summary(&self) -> ConnectionSummary {
+    ConnectionSummary {
+      connection_id: self.id.clone(),
+      browser: self.browser.clone(),
+      extension_id: self.extension_id.clone(),
+      connected_at: self.connected_at,
+      messages_in: self.traffic.messages_in.load(Ordering::Relaxed),
+      bytes_in: self.traffic.bytes_in.load(Ordering::Relaxed),
+      messages_out: self.traffic.messages_out.load(Ordering::Relaxed),
+      bytes_out: self.traffic.bytes_out.load(Ordering::Relaxed),
+      queue_depth: queue_depth(&self.sender),
+      rate_limits: self.rate_limits.lock().map(|guard| guard.clone()).unwrap_or_default(),
+    }
+  }
 }
 
 type ConnectionMap = Arc<Mutex<HashMap<ConnectionId, ConnectionMeta>>>;
 
+/// Messages waiting in `queue`.
+fn queue_depth(queue: &mpsc::Sender<String>) -> usize {
+  queue.max_capacity() - queue.capacity()
+}
+
+/// Set to `Some(reason)` once the app starts shutting down.
+type ShutdownSignal = watch::Receiver<Option<String>>;
+
 pub fn spawn(app: &tauri::AppHandle) -> BridgeHandle {
   let connections: ConnectionMap = Arc::new(Mutex::new(HashMap::new()));
   let (from_sidecar_tx, mut from_sidecar_rx) = mpsc::channel::<String>(256);
 
-  let hub = DebugHub::default();
+  let mut hub = DebugHub::new(Role::App);
+  if let Some(redactor) = FieldRedactor::from_env() {
+    hub = hub.with_redactor(Arc::new(redactor));
+  }
+  if env::var("BRIDGE_CAPTURE").map(|v| v == "1").unwrap_or(false) {
+    if let Err(err) = hub.start_capture(CaptureConfig::default()) {
+      eprintln!("[app] failed to start traffic capture: {err:#}");
+    }
+  }
   let incoming_hub = hub.clone();
   let app_handle = app.clone();
 
   tauri::async_runtime::spawn(async move {
     while let Some(msg) = from_sidecar_rx.recv().await {
-      incoming_hub.broadcast(&msg);
+      incoming_hub.broadcast(Hop::AppToFrontend, &msg);
       let _ = app_handle.emit("bridge://incoming", msg);
     }
   });
 
+  let (shutdown_tx, shutdown_rx) = watch::channel(None);
+  let limits = Arc::new(RateLimitConfig::from_env());
+  let limits_for_debug = limits.clone();
+  // Weak, so the debug port never keeps the frontend queue open
+  let frontend_queue = from_sidecar_tx.downgrade();
   let hub_for_sidecar = hub.clone();
   let connections_for_listener = connections.clone();
   tauri::async_runtime::spawn(async move {
-    if let Err(err) =
-      run_sidecar_listener(connections_for_listener, from_sidecar_tx, hub_for_sidecar).await
+    if let Err(err) = run_sidecar_listener(
+      connections_for_listener,
+      from_sidecar_tx,
+      hub_for_sidecar,
+      limits,
+      shutdown_rx,
+    )
+    .await
     {
       eprintln!("[app] sidecar listener exited: {err:#}");
     }
@@ -53,30 +165,64 @@ pub fn spawn(app: &tauri::AppHandle) -> BridgeHandle {
   let debug_enabled =
     cfg!(debug_assertions) || env::var("BRIDGE_DEBUG_WS").map(|v| v == "1").unwrap_or(false);
   if debug_enabled {
+    let port = env::var("BRIDGE_DEBUG_PORT")
+      .ok()
+      .and_then(|p| p.parse::<u16>().ok())
+      .unwrap_or(DEBUG_WS_PORT);
     let hub_for_debug = hub.clone();
     let connections_for_debug = connections.clone();
+    let shutdown_for_debug = shutdown_tx.subscribe();
     tauri::async_runtime::spawn(async move {
-      if let Err(err) = run_debug_listener(DEBUG_WS_PORT, hub_for_debug, connections_for_debug).await
+      if let Err(err) = run_debug_listener(
+        port,
+        hub_for_debug,
+        connections_for_debug,
+        frontend_queue,
+        limits_for_debug,
+        shutdown_for_debug,
+      )
+      .await
       {
         eprintln!("[app] debug listener exited: {err:#}");
       }
     });
   }
 
-  BridgeHandle::new(connections, hub)
+  BridgeHandle::new(connections, hub, shutdown_tx)
 }
 
 async fn run_sidecar_listener(
   connections: ConnectionMap,
   from_sidecar_tx: mpsc::Sender<String>,
   hub: DebugHub,
+  limits: Arc<RateLimitConfig>,
+  shutdown: ShutdownSignal,
 ) -> Result<()> {
   let listener = TcpListener::bind(("127.0.0.1", APP_WS_PORT))
     .await
     .with_context(|| format!("binding app ws on 127.0.0.1:{APP_WS_PORT}"))?;
+  serve_sidecars(listener, connections, from_sidecar_tx, hub, limits, shutdown).await
+}
+
+async fn serve_sidecars(
+  listener: TcpListener,
+  connections: ConnectionMap,
+  from_sidecar_tx: mpsc::Sender<String>,
+  hub: DebugHub,
+  limits: Arc<RateLimitConfig>,
+  mut shutdown: ShutdownSignal,
+) -> Result<()> {
+  // Ask every sidecar to forward its own debug events so the app hub shows all hops
+  let forward_debug = env::var("BRIDGE_DEBUG_FORWARD").map(|v| v == "1").unwrap_or(false);
 
   loop {
-    let (stream, _) = listener.accept().await?;
+    let stream = tokio::select! {
+      accepted = listener.accept() => accepted?.0,
+      Ok(()) = shutdown.changed() => {
+        eprintln!("[app] sidecar listener stopped for shutdown");
+        return Ok(());
+      }
+    };
     let ws_stream = accept_async(stream).await?;
     let (mut write, mut read) = ws_stream.split();
 
@@ -87,18 +233,92 @@ async fn run_sidecar_listener(
 
     let mut connection_id: Option<ConnectionId> = None;
     let mut browser: Option<String> = None;
+    let mut extension_id: Option<String> = None;
+    let mut limiter = ConnectionLimiter::new(limits.clone());
+    let connected_at = chrono::Utc::now().timestamp_millis();
+    let traffic = Arc::new(TrafficCounters::default());
+    let mut shutdown_clone = shutdown.clone();
+    // Reported in the offline presence; anything but a close frame counts as abrupt
+    let mut disconnect_reason = "abrupt".to_string();
+    // A sidecar that shuts down cleanly announces its own offline presence
+    let mut offline_reported = false;
+    // Whether this sidecar was asked to forward its debug events, by the app or a debug client
+    let mut forwarding = false;
 
     tokio::spawn(async move {
       loop {
+        let flush_at = limiter.next_flush(Instant::now());
         tokio::select! {
           Some(message) = to_sidecar_rx.recv() => {
+            if let Some(enabled) = forward_command(&message) {
+              forwarding = enabled;
+            }
+            traffic.record_out(&message);
+            let sending = Instant::now();
             if write.send(Message::Text(message)).await.is_err() {
               break;
             }
+            hub_clone.metrics().observe_since(Histogram::SendLatency, ("link", "sidecar"), sending);
+          }
+          Ok(()) = shutdown_clone.changed() => {
+            let reason = shutdown_clone.borrow().clone().unwrap_or_default();
+            // Deliver anything already queued (including the shutdown notice) before closing
+            while let Ok(message) = to_sidecar_rx.try_recv() {
+              if write.send(Message::Text(message)).await.is_err() {
+                break;
+              }
+            }
+            let frame = CloseFrame {
+              code: CloseCode::Away,
+              reason: reason.clone().into(),
+            };
+            let _ = write.send(Message::Close(Some(frame))).await;
+            disconnect_reason = reason;
+            break;
+          }
+          _ = tokio::time::sleep_until(flush_at.unwrap_or_else(Instant::now).into()), if flush_at.is_some() => {
+            let mut delivered = true;
+            for coalesced in limiter.flush(Instant::now()) {
+              hub_clone.broadcast_from(Hop::SidecarToApp, connection_id.as_deref(), browser.as_deref(), &coalesced);
+              if tx_clone.send(coalesced).await.is_err() {
+                delivered = false;
+                break;
+              }
+            }
+            if !delivered {
+              break;
+            }
           }
           incoming = read.next() => {
             match incoming {
               Some(Ok(Message::Text(txt))) => {
+                traffic.record_in(&txt);
+
+                // Events a sidecar forwards from its own hub only feed the debug stream, within
+                // their own budget, and only once this connection was asked for them
+                if debug_hub::is_debug_frame(&txt) {
+                  if forwarding && limiter.admit_forwarded(Instant::now()) {
+                    hub_clone.merge_forwarded(&txt);
+                  }
+                  continue;
+                }
+
+                // Throttle before parsing, logging and mirroring so a flood stays cheap
+                match limiter.admit(&txt, Instant::now()) {
+                  Admission::Allow => {}
+                  Admission::Drop | Admission::Coalesced => continue,
+                  Admission::Reject(notice) => {
+                    if let Some(notice) = notice {
+                      hub_clone.broadcast_from(Hop::AppToSidecar, connection_id.as_deref(), browser.as_deref(), &notice);
+                      traffic.record_out(&notice);
+                      if write.send(Message::Text(notice)).await.is_err() {
+                        break;
+                      }
+                    }
+                    continue;
+                  }
+                }
+
                 eprintln!("[app] Received WebSocket message: {}", &txt[..txt.len().min(200)]);
                 
                 // Try to extract connection metadata from presence messages
@@ -113,6 +333,7 @@ async fn run_sidecar_listener(
                         if let Some(conn_id) = payload.get("connectionId").and_then(|c| c.as_str()) {
                           connection_id = Some(conn_id.to_string());
                           browser = payload.get("browser").and_then(|b| b.as_str()).map(|s| s.to_string());
+                          extension_id = payload.get("extensionId").and_then(|e| e.as_str()).map(|s| s.to_string());
                           
                           // Register this connection
                           if let Ok(mut map) = connections_clone.lock() {
@@ -121,11 +342,25 @@ async fn run_sidecar_listener(
                               ConnectionMeta {
                                 id: conn_id.to_string(),
                                 browser: browser.clone(),
+                                extension_id: extension_id.clone(),
                                 sender: to_sidecar_tx.clone(),
+                                rate_limits: limiter.counters(),
+                                connected_at,
+                                traffic: traffic.clone(),
                               },
                             );
                             eprintln!("[app] Connection registered: {} ({:?})", conn_id, browser);
                           }
+
+                          if forward_debug {
+                            let request = forward_request(true);
+                            hub_clone.broadcast_from(Hop::AppToSidecar, connection_id.as_deref(), browser.as_deref(), &request);
+                            traffic.record_out(&request);
+                            if write.send(Message::Text(request)).await.is_err() {
+                              break;
+                            }
+                            forwarding = true;
+                          }
                         } else {
                           eprintln!("[app] No connectionId in payload");
                         }
@@ -140,7 +375,11 @@ async fn run_sidecar_listener(
                   eprintln!("[app] Connection already registered: {:?}", connection_id);
                 }
 
-                hub_clone.broadcast(&txt);
+                if is_offline_presence(&txt) {
+                  offline_reported = true;
+                }
+
+                hub_clone.broadcast_from(Hop::SidecarToApp, connection_id.as_deref(), browser.as_deref(), &txt);
                 if tx_clone.send(txt).await.is_err() {
                   break;
                 }
@@ -161,7 +400,7 @@ async fn run_sidecar_listener(
                   "payload": { "bytes": payload.len() }
                 })
                 .to_string();
-                hub_clone.broadcast(&info);
+                hub_clone.broadcast_from(Hop::App, connection_id.as_deref(), browser.as_deref(), &info);
                 if write.send(Message::Pong(payload)).await.is_err() {
                   break;
                 }
@@ -173,7 +412,7 @@ async fn run_sidecar_listener(
                   "payload": { "bytes": payload.len() }
                 })
                 .to_string();
-                hub_clone.broadcast(&info);
+                hub_clone.broadcast_from(Hop::App, connection_id.as_deref(), browser.as_deref(), &info);
               }
               Some(Ok(Message::Close(frame))) => {
                 let code = frame.as_ref().map(|f| u16::from(f.code));
@@ -185,13 +424,14 @@ async fn run_sidecar_listener(
                     Some(text)
                   }
                 });
+                disconnect_reason = reason.clone().unwrap_or_else(|| "closed".to_string());
                 let info = json!({
                   "v": 1,
                   "type": "debug.close",
                   "payload": { "code": code, "reason": reason }
                 })
                 .to_string();
-                hub_clone.broadcast(&info);
+                hub_clone.broadcast_from(Hop::App, connection_id.as_deref(), browser.as_deref(), &info);
                 break;
               }
               Some(Ok(Message::Frame(_))) => {
@@ -213,6 +453,9 @@ async fn run_sidecar_listener(
           map.remove(&conn_id);
           eprintln!("[app] Connection removed: {}", conn_id);
         }
+        if offline_reported {
+          return;
+        }
 
         let offline_payload = json!({
           "v": 1,
@@ -220,11 +463,13 @@ async fn run_sidecar_listener(
           "payload": {
             "sidecar": "offline",
             "connectionId": conn_id,
-            "browser": browser
+            "browser": browser,
+            "extensionId": extension_id,
+            "reason": disconnect_reason
           }
         })
         .to_string();
-        hub_clone.broadcast(&offline_payload);
+        hub_clone.broadcast(Hop::App, &offline_payload);
         let _ = tx_clone.send(offline_payload).await;
       }
     });
@@ -235,18 +480,65 @@ async fn run_sidecar_listener(
 pub struct BridgeHandle {
   connections: ConnectionMap,
   hub: DebugHub,
+  shutdown: Arc<watch::Sender<Option<String>>>,
 }
 
 impl BridgeHandle {
-  fn new(connections: ConnectionMap, hub: DebugHub) -> Self {
-    Self { connections, hub }
+  fn new(
+    connections: ConnectionMap,
+    hub: DebugHub,
+    shutdown: watch::Sender<Option<String>>,
+  ) -> Self {
+    Self {
+      connections,
+      hub,
+      shutdown: Arc::new(shutdown),
+    }
+  }
+
+  /// Announces `bridge.shutdown` to every sidecar, closes their sockets with a close frame and
+  /// waits briefly for the connection tasks to finish.
+  pub async fn shutdown(&self, reason: &str) {
+    if self.shutdown.borrow().is_some() {
+      return;
+    }
+
+    let notice = json!({
+      "v": 1,
+      "type": "bridge.shutdown",
+      "payload": {
+        "reason": reason,
+        "timestamp": chrono::Utc::now().timestamp_millis()
+      }
+    })
+    .to_string();
+    self.hub.broadcast(Hop::AppToSidecar, &notice);
+
+    let senders: Vec<mpsc::Sender<String>> = {
+      let connections = self.connections.lock().unwrap();
+      connections.values().map(|c| c.sender.clone()).collect()
+    };
+    eprintln!("[app] shutting down bridge ({reason}), notifying {} connections", senders.len());
+    for sender in senders {
+      let _ = sender.try_send(notice.clone());
+    }
+
+    let _ = self.shutdown.send(Some(reason.to_string()));
+
+    let deadline = Instant::now() + SHUTDOWN_GRACE;
+    while Instant::now() < deadline {
+      if self.connections.lock().map(|map| map.is_empty()).unwrap_or(true) {
+        break;
+      }
+      tokio::time::sleep(Duration::from_millis(20)).await;
+    }
   }
 
   pub async fn send(
     &self,
     message: String,
   ) -> std::result::Result<(), mpsc::error::SendError<String>> {
-    self.hub.broadcast(&message);
+    self.hub.broadcast(Hop::AppToSidecar, &message);
 
     // Try to extract connectionId and message type
     let (target_connection_id, msg_type) = if let Ok(envelope) = serde_json::from_str::<Value>(&message) {
@@ -275,10 +567,14 @@ impl BridgeHandle {
           vec![conn.sender.clone()]
         } else {
           eprintln!("[app] [{}] Target connection not found: {}", msg_type, target_id);
+          self.hub.metrics().increment(Counter::RoutingFailures, Some(("reason", "unknown_connection")));
           vec![]
         }
       } else {
         eprintln!("[app] [{}] No connectionId - broadcasting to {} connections", msg_type, connections.len());
+        if connections.is_empty() {
+          self.hub.metrics().increment(Counter::RoutingFailures, Some(("reason", "no_connections")));
+        }
         // Broadcast to all connections
         connections.values().map(|c| c.sender.clone()).collect()
       }
@@ -286,12 +582,30 @@ impl BridgeHandle {
 
     // Send messages without holding the lock
     for sender in senders {
-      let _ = sender.send(message.clone()).await;
+      if sender.send(message.clone()).await.is_err() {
+        self.hub.metrics().increment(Counter::RoutingFailures, Some(("reason", "connection_closed")));
+      }
     }
 
     Ok(())
   }
 
+  /// Applies a `debug.capture` payload to the app's own traffic capture.
+  pub fn capture(&self, payload: Value) -> Result<CaptureStatus> {
+    self.hub.apply_capture_command(payload)
+  }
+
+  pub fn rate_limit_stats(&self) -> HashMap<String, RateLimitCounters> {
+    let connections = self.connections.lock().unwrap();
+    connections
+      .values()
+      .map(|c| {
+        let counters = c.rate_limits.lock().map(|guard| guard.clone()).unwrap_or_default();
+        (c.id.clone(), counters)
+      })
+      .collect()
+  }
+
   pub fn get_connections(&self) -> Vec<(String, Option<String>)> {
     let connections = self.connections.lock().unwrap();
     connections
@@ -305,138 +619,527 @@ async fn run_debug_listener(
   port: u16,
   hub: DebugHub,
   connections: ConnectionMap,
+  frontend_queue: WeakSender<String>,
+  limits: Arc<RateLimitConfig>,
+  mut shutdown: ShutdownSignal,
 ) -> Result<()> {
-  let listener = TcpListener::bind(("127.0.0.1", port))
-    .await
-    .with_context(|| format!("binding debug ws on 127.0.0.1:{port}"))?;
+  let listener = match TcpListener::bind(("127.0.0.1", port)).await {
+    Ok(listener) => listener,
+    Err(err) => {
+      eprintln!("[app] debug port {port} unavailable ({err}), using an ephemeral port");
+      TcpListener::bind(("127.0.0.1", 0))
+        .await
+        .context("binding debug ws on an ephemeral port")?
+    }
+  };
+  let port = listener.local_addr()?.port();
+  eprintln!("[app] debug ws listening on ws://127.0.0.1:{port}/");
+  // Listed for as long as this listener runs
+  let _registration = debug_index::register(&DebugEndpoint::new("app", port, None, None))
+    .map_err(|err| eprintln!("[app] failed to register debug endpoint: {err:#}"))
+    .ok();
+  let secret: Option<Arc<str>> = env::var("BRIDGE_SECRET").ok().filter(|s| !s.is_empty()).map(Into::into);
+  let started = Instant::now();
+  let config = Arc::new(json!({
+    "appWsPort": APP_WS_PORT,
+    "debugPort": port,
+    "injectEnabled": secret.is_some(),
+    "forward": env::var("BRIDGE_DEBUG_FORWARD").is_ok_and(|v| v == "1"),
+    "rateLimits": &*limits,
+  }));
 
   loop {
-    let (stream, _) = listener.accept().await?;
-    let ws_stream = accept_async(stream).await?;
-    let (mut write, mut read) = ws_stream.split();
-    let mut rx = hub.register();
-    let hub_clone = hub.clone();
-    let connections_clone = connections.clone();
+    let stream = tokio::select! {
+      accepted = listener.accept() => accepted?.0,
+      Ok(()) = shutdown.changed() => return Ok(()),
+    };
+    // Served on its own task so a slow HTTP request or handshake never holds up the next accept
+    tokio::spawn(serve_debug_connection(
+      stream,
+      hub.clone(),
+      connections.clone(),
+      frontend_queue.clone(),
+      config.clone(),
+      secret.clone(),
+      started,
+    ));
+  }
+}
 
-    tokio::spawn(async move {
-      loop {
-        tokio::select! {
-          Some(outbound) = rx.recv() => {
-            if write.send(Message::Text(outbound)).await.is_err() {
-              break;
-            }
-          }
-          incoming = read.next() => {
-            match incoming {
-              Some(Ok(Message::Text(txt))) => {
-                hub_clone.broadcast(&txt);
-
-                // Route message to appropriate connection or broadcast
-                let senders: Vec<mpsc::Sender<String>> = if let Ok(envelope) = serde_json::from_str::<Value>(&txt) {
-                  let target_connection_id = envelope
-                    .get("payload")
-                    .and_then(|p| p.get("connectionId"))
-                    .and_then(|c| c.as_str())
-                    .map(|s| s.to_string());
-
-                  let connections_map = connections_clone.lock().unwrap();
-
-                  if let Some(target_id) = target_connection_id {
-                    if let Some(conn) = connections_map.get(&target_id) {
-                      vec![conn.sender.clone()]
-                    } else {
-                      vec![]
-                    }
-                  } else {
-                    // Broadcast to all
-                    connections_map.values().map(|c| c.sender.clone()).collect()
-                  }
-                } else {
-                  vec![]
-                };
+/// Answers one connection to the debug port: an HTTP request, or a debug client for as long as
+/// it stays connected. `config` is what `/config` reports about the listener.
+async fn serve_debug_connection(
+  stream: TcpStream,
+  hub: DebugHub,
+  connections: ConnectionMap,
+  frontend_queue: WeakSender<String>,
+  config: Arc<Value>,
+  secret: Option<Arc<str>>,
+  started: Instant,
+) {
+  // Plain HTTP requests get the introspection endpoints; upgrades continue as debug clients
+  let (stream, request) = match debug_http::read_request(stream).await {
+    Ok(read) => read,
+    Err(_) => return,
+  };
+  if let Some(request) = request {
+    let frontend_depth = frontend_queue.upgrade().map(|queue| queue_depth(&queue));
+    let response = debug_http_response(&hub, &connections, frontend_depth, started, (*config).clone(), &request);
+    if let Err(err) = debug_http::respond(stream, &request, response).await {
+      eprintln!("[app] debug http {} failed: {err:#}", request.path);
+    }
+    return;
+  }
+  let (ws_stream, options) = match debug_hub::accept_debug_client(Role::App, stream, secret.as_deref()).await {
+    Ok(accepted) => accepted,
+    Err(err) => {
+      // Discovery probes connect and hang up without a handshake; only log refusals
+      if debug_hub::is_refused_handshake(&err) {
+        eprintln!("[app] debug client rejected: {err:#}");
+        hub.metrics().increment(Counter::HandshakeRejections, None);
+      }
+      return;
+    }
+  };
+  let (mut write, mut read) = ws_stream.split();
+  let mut subscription = hub.register(&options);
 
-                // Send without holding the lock
-                for sender in senders {
-                  let _ = sender.send(txt.clone()).await;
-                }
-              }
-              Some(Ok(Message::Binary(bin))) => {
-                let payload = json!({
-                  "v": 1,
-                  "type": "debug.binary",
-                  "payload": { "bytes": bin.len() }
-                })
-                .to_string();
-                hub_clone.broadcast(&payload);
+  loop {
+    tokio::select! {
+      Some(outbound) = subscription.rx.recv() => {
+        if write.send(Message::Text(outbound.to_string())).await.is_err() {
+          break;
+        }
+      }
+      incoming = read.next() => {
+        match incoming {
+          Some(Ok(Message::Text(txt))) => {
+            let reply = debug_hub::handle_subscription_command(&hub, subscription.id, &txt)
+              .or_else(|| debug_hub::handle_capture_command(&hub, &txt, options.mode == ClientMode::Inject));
+            if let Some(reply) = reply {
+              if write.send(Message::Text(reply)).await.is_err() {
+                break;
               }
-              Some(Ok(Message::Ping(payload))) => {
-                let info = json!({
-                  "v": 1,
-                  "type": "debug.ping",
-                  "payload": { "bytes": payload.len() }
-                })
-                .to_string();
-                hub_clone.broadcast(&info);
-                if write.send(Message::Pong(payload)).await.is_err() {
-                  break;
+              continue;
+            }
+            // Toggling forwarding only changes what is observed, so observers may do it
+            if forward_command(&txt).is_some() {
+              hub.broadcast(Hop::AppToSidecar, &txt);
+              for sender in route_targets(&connections, hub.metrics(), &txt) {
+                if sender.send(txt.clone()).await.is_err() {
+                  hub.metrics().increment(Counter::RoutingFailures, Some(("reason", "connection_closed")));
                 }
               }
-              Some(Ok(Message::Pong(payload))) => {
-                let info = json!({
-                  "v": 1,
-                  "type": "debug.pong",
-                  "payload": { "bytes": payload.len() }
-                })
-                .to_string();
-                hub_clone.broadcast(&info);
-              }
-              Some(Ok(Message::Close(frame))) => {
-                let code = frame.as_ref().map(|f| u16::from(f.code));
-                let reason = frame.as_ref().and_then(|f| {
-                  let text = f.reason.to_string();
-                  if text.is_empty() {
-                    None
-                  } else {
-                    Some(text)
-                  }
-                });
-                let info = json!({
-                  "v": 1,
-                  "type": "debug.close",
-                  "payload": { "code": code, "reason": reason }
-                })
-                .to_string();
-                hub_clone.broadcast(&info);
+              continue;
+            }
+            if options.mode != ClientMode::Inject {
+              if write.send(Message::Text(debug_hub::read_only_notice())).await.is_err() {
                 break;
               }
-              Some(Ok(Message::Frame(_))) => { /* ignore */ }
-              Some(Err(err)) => {
-                eprintln!("[app] debug ws error: {err:#}");
-                break;
+              continue;
+            }
+            hub.broadcast_injected(subscription.id, &txt);
+
+            // Send without holding the lock
+            for sender in route_targets(&connections, hub.metrics(), &txt) {
+              if sender.send(txt.clone()).await.is_err() {
+                hub.metrics().increment(Counter::RoutingFailures, Some(("reason", "connection_closed")));
               }
-              None => break,
             }
           }
+          Some(Ok(Message::Binary(bin))) => {
+            let payload = json!({
+              "v": 1,
+              "type": "debug.binary",
+              "payload": { "bytes": bin.len() }
+            })
+            .to_string();
+            hub.broadcast(Hop::App, &payload);
+          }
+          Some(Ok(Message::Ping(payload))) => {
+            let info = json!({
+              "v": 1,
+              "type": "debug.ping",
+              "payload": { "bytes": payload.len() }
+            })
+            .to_string();
+            hub.broadcast(Hop::App, &info);
+            if write.send(Message::Pong(payload)).await.is_err() {
+              break;
+            }
+          }
+          Some(Ok(Message::Pong(payload))) => {
+            let info = json!({
+              "v": 1,
+              "type": "debug.pong",
+              "payload": { "bytes": payload.len() }
+            })
+            .to_string();
+            hub.broadcast(Hop::App, &info);
+          }
+          Some(Ok(Message::Close(frame))) => {
+            let code = frame.as_ref().map(|f| u16::from(f.code));
+            let reason = frame.as_ref().and_then(|f| {
+              let text = f.reason.to_string();
+              if text.is_empty() {
+                None
+              } else {
+                Some(text)
+              }
+            });
+            let info = json!({
+              "v": 1,
+              "type": "debug.close",
+              "payload": { "code": code, "reason": reason }
+            })
+            .to_string();
+            hub.broadcast(Hop::App, &info);
+            break;
+          }
+          Some(Ok(Message::Frame(_))) => { /* ignore */ }
+          Some(Err(err)) => {
+            eprintln!("[app] debug ws error: {err:#}");
+            break;
+          }
+          None => break,
         }
       }
-    });
+    }
+  }
+}
+
+/// Senders for the connection named by the envelope's `payload.connectionId`, or for every
+/// connection when it names none. Messages that reach nobody are counted as routing failures.
+fn route_targets(connections: &ConnectionMap, metrics: &Metrics, message: &str) -> Vec<mpsc::Sender<String>> {
+  let Ok(envelope) = serde_json::from_str::<Value>(message) else {
+    metrics.increment(Counter::RoutingFailures, Some(("reason", "unparseable")));
+    return vec![];
+  };
+  let target_connection_id = envelope
+    .get("payload")
+    .and_then(|p| p.get("connectionId"))
+    .and_then(|c| c.as_str());
+
+  let connections_map = connections.lock().unwrap();
+  match target_connection_id {
+    Some(target_id) => match connections_map.get(target_id) {
+      Some(conn) => vec![conn.sender.clone()],
+      None => {
+        metrics.increment(Counter::RoutingFailures, Some(("reason", "unknown_connection")));
+        vec![]
+      }
+    },
+    None if connections_map.is_empty() => {
+      metrics.increment(Counter::RoutingFailures, Some(("reason", "no_connections")));
+      vec![]
+    }
+    None => connections_map.values().map(|c| c.sender.clone()).collect(),
+  }
+}
+
+/// `debug.forward` turns a sidecar's forwarding of its own debug events on or off.
+fn forward_request(enabled: bool) -> String {
+  json!({
+    "v": 1,
+    "type": "debug.forward",
+    "payload": { "enabled": enabled }
+  })
+  .to_string()
+}
+
+/// Whether `message` is a `debug.forward` command, and if so whether it enables forwarding;
+/// a missing `enabled` means on, as in the sidecar.
+fn forward_command(message: &str) -> Option<bool> {
+  let envelope: Value = serde_json::from_str(message).ok()?;
+  if envelope.get("type")?.as_str()? != "debug.forward" {
+    return None;
   }
+  Some(envelope.pointer("/payload/enabled").and_then(Value::as_bool).unwrap_or(true))
 }
 
-#[derive(Clone, Default)]
-struct DebugHub {
-  peers: Arc<Mutex<Vec<mpsc::UnboundedSender<String>>>>,
+/// Whether `message` is a sidecar's own `presence.status` announcing it went offline.
+fn is_offline_presence(message: &str) -> bool {
+  let Ok(envelope) = serde_json::from_str::<Value>(message) else {
+    return false;
+  };
+  envelope.get("type").and_then(Value::as_str) == Some("presence.status")
+    && envelope.pointer("/payload/sidecar").and_then(Value::as_str) == Some("offline")
 }
 
-impl DebugHub {
-  fn broadcast(&self, message: &str) {
-    let mut peers = self.peers.lock().unwrap();
-    peers.retain(|tx| tx.send(message.to_owned()).is_ok());
+/// Serves `/health`, `/connections`, `/config` and `/metrics` on the debug port; `config` holds
+/// the listener's own settings, the hub adds its buffers and capture state.
+fn debug_http_response(
+  hub: &DebugHub,
+  connections: &ConnectionMap,
+  frontend_depth: Option<usize>,
+  started: Instant,
+  mut config: Value,
+  request: &HttpRequest,
+) -> HttpResponse {
+  let summaries: Vec<ConnectionSummary> = {
+    let connections = connections.lock().unwrap();
+    let mut summaries: Vec<ConnectionSummary> = connections.values().map(ConnectionMeta::summary).collect();
+    summaries.sort_by_key(|summary| summary.connected_at);
+    summaries
+  };
+  match request.path.as_str() {
+    "/health" => HttpResponse::json(&json!({
+      "status": "ok",
+      "uptimeMs": started.elapsed().as_millis(),
+      "connections": summaries.len(),
+    })),
+    "/connections" => HttpResponse::json(&summaries),
+    "/config" => {
+      let stats = hub.stats();
+      config["debugFormat"] = json!(env::var("BRIDGE_DEBUG_FORMAT").unwrap_or_else(|_| "annotated".to_string()));
+      config["historyDepth"] = json!(stats.history_depth);
+      config["peerBuffer"] = json!(stats.peer_buffer);
+      config["capture"] = json!(stats.capture);
+      HttpResponse::json(&config)
+    }
+    "/metrics" => {
+      let mut body = String::new();
+      hub.stats().write_prometheus(&mut body);
+      hub.metrics().write_prometheus(&mut body);
+      body.push_str("# HELP bridge_connections Sidecars currently connected.\n");
+      body.push_str("# TYPE bridge_connections gauge\n");
+      body.push_str(&format!("bridge_connections {}\n", summaries.len()));
+      body.push_str("# HELP bridge_outbox_depth Messages queued for each sidecar.\n");
+      body.push_str("# TYPE bridge_outbox_depth gauge\n");
+      for summary in &summaries {
+        body.push_str(&format!(
+          "bridge_outbox_depth{{connection=\"{}\"}} {}\n",
+          metrics::escape(&summary.connection_id),
+          summary.queue_depth
+        ));
+      }
+      if let Some(depth) = frontend_depth {
+        body.push_str("# HELP bridge_frontend_queue_depth Sidecar messages waiting to be emitted to the frontend.\n");
+        body.push_str("# TYPE bridge_frontend_queue_depth gauge\n");
+        body.push_str(&format!("bridge_frontend_queue_depth {depth}\n"));
+      }
+      HttpResponse::text(debug_http::PROMETHEUS_TEXT, body)
+    }
+    path => HttpResponse::not_found(path),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tokio::time::timeout;
+  use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+  use bridge_debug::debug_hub::{DebugClientOptions, DebugSubscription, FrameFormat};
+
+  type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+  struct Bridge {
+    url: String,
+    connections: ConnectionMap,
+    frontend: mpsc::Receiver<String>,
+    handle: BridgeHandle,
+  }
+
+  async fn bridge() -> Bridge {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let connections = ConnectionMap::default();
+    let (from_sidecar_tx, frontend) = mpsc::channel(256);
+    let hub = DebugHub::new(Role::App);
+    let (shutdown_tx, shutdown_rx) = watch::channel(None);
+    tokio::spawn(serve_sidecars(
+      listener,
+      connections.clone(),
+      from_sidecar_tx,
+      hub.clone(),
+      Arc::new(RateLimitConfig::default()),
+      shutdown_rx,
+    ));
+    Bridge {
+      url,
+      connections: connections.clone(),
+      frontend,
+      handle: BridgeHandle::new(connections, hub, shutdown_tx),
+    }
+  }
+
+  fn presence(state: &str, connection_id: &str) -> String {
+    json!({
+      "v": 1,
+      "type": "presence.status",
+      "payload": { "sidecar": state, "connectionId": connection_id, "browser": "chrome" }
+    })
+    .to_string()
+  }
+
+  async fn next_frontend(bridge: &mut Bridge) -> Option<Value> {
+    let message = timeout(Duration::from_millis(500), bridge.frontend.recv()).await.ok()??;
+    Some(serde_json::from_str(&message).unwrap())
+  }
+
+  /// Connects a sidecar and waits until the app has registered it.
+  async fn connect(bridge: &mut Bridge, connection_id: &str) -> Client {
+    let (mut client, _) = connect_async(bridge.url.as_str()).await.unwrap();
+    client.send(Message::Text(presence("online", connection_id))).await.unwrap();
+    let online = next_frontend(bridge).await.expect("online presence reaches the frontend");
+    assert_eq!(online["payload"]["connectionId"], connection_id);
+    client
+  }
+
+  async fn next_message(client: &mut Client) -> Message {
+    timeout(Duration::from_secs(2), client.next())
+      .await
+      .expect("message before the timeout")
+      .expect("socket still open")
+      .unwrap()
+  }
+
+  fn offline_reason(presence: &Value) -> &str {
+    assert_eq!(presence["type"], "presence.status");
+    assert_eq!(presence["payload"]["sidecar"], "offline");
+    presence["payload"]["reason"].as_str().unwrap()
+  }
+
+  #[tokio::test]
+  async fn shutdown_notifies_every_sidecar_then_closes_with_the_reason() {
+    let mut bridge = bridge().await;
+    let mut first = connect(&mut bridge, "c1").await;
+    let mut second = connect(&mut bridge, "c2").await;
+
+    bridge.handle.shutdown("app-quit").await;
+    assert!(bridge.connections.lock().unwrap().is_empty());
+
+    for client in [&mut first, &mut second] {
+      let Message::Text(notice) = next_message(client).await else {
+        panic!("expected the bridge.shutdown notice first");
+      };
+      let notice: Value = serde_json::from_str(&notice).unwrap();
+      assert_eq!(notice["type"], "bridge.shutdown");
+      assert_eq!(notice["payload"]["reason"], "app-quit");
+
+      let Message::Close(Some(frame)) = next_message(client).await else {
+        panic!("expected a close frame after the notice");
+      };
+      assert_eq!(frame.code, CloseCode::Away);
+      assert_eq!(frame.reason, "app-quit");
+    }
+
+    for _ in 0..2 {
+      let offline = next_frontend(&mut bridge).await.expect("offline presence per sidecar");
+      assert_eq!(offline_reason(&offline), "app-quit");
+    }
+  }
+
+  #[tokio::test]
+  async fn shutdown_gives_up_on_stuck_connections_after_the_grace() {
+    let bridge = bridge().await;
+    let (sender, _outbox) = mpsc::channel(1);
+    bridge.connections.lock().unwrap().insert(
+      "stuck".to_string(),
+      ConnectionMeta {
+        id: "stuck".to_string(),
+        browser: None,
+        extension_id: None,
+        sender,
+        rate_limits: ConnectionLimiter::new(Arc::new(RateLimitConfig::default())).counters(),
+        connected_at: 0,
+        traffic: Arc::default(),
+      },
+    );
+
+    let started = Instant::now();
+    bridge.handle.shutdown("app-quit").await;
+    let waited = started.elapsed();
+    assert!(waited >= SHUTDOWN_GRACE, "returned after {waited:?}");
+    assert!(waited < SHUTDOWN_GRACE + Duration::from_millis(500), "returned after {waited:?}");
+
+    // Shutting down twice neither waits nor notifies again
+    let started = Instant::now();
+    bridge.handle.shutdown("app-quit").await;
+    assert!(started.elapsed() < Duration::from_millis(50));
+  }
+
+  #[tokio::test]
+  async fn offline_presence_reports_how_the_sidecar_went_away() {
+    let mut bridge = bridge().await;
+
+    let mut client = connect(&mut bridge, "reasoned").await;
+    let frame = CloseFrame {
+      code: CloseCode::Normal,
+      reason: "native host exited".into(),
+    };
+    client.send(Message::Close(Some(frame))).await.unwrap();
+    let offline = next_frontend(&mut bridge).await.unwrap();
+    assert_eq!(offline_reason(&offline), "native host exited");
+
+    let mut client = connect(&mut bridge, "silent").await;
+    client.send(Message::Close(None)).await.unwrap();
+    let offline = next_frontend(&mut bridge).await.unwrap();
+    assert_eq!(offline_reason(&offline), "closed");
+
+    let client = connect(&mut bridge, "dropped").await;
+    drop(client);
+    let offline = next_frontend(&mut bridge).await.unwrap();
+    assert_eq!(offline["payload"]["connectionId"], "dropped");
+    assert_eq!(offline_reason(&offline), "abrupt");
+  }
+
+  #[tokio::test]
+  async fn a_sidecar_announcing_offline_is_not_reported_twice() {
+    let mut bridge = bridge().await;
+    let mut client = connect(&mut bridge, "c1").await;
+
+    let own = json!({
+      "v": 1,
+      "type": "presence.status",
+      "payload": { "sidecar": "offline", "connectionId": "c1", "browser": "chrome", "reason": "sigterm" }
+    });
+    client.send(Message::Text(own.to_string())).await.unwrap();
+    client.send(Message::Close(None)).await.unwrap();
+
+    let offline = next_frontend(&mut bridge).await.unwrap();
+    assert_eq!(offline_reason(&offline), "sigterm");
+    assert!(next_frontend(&mut bridge).await.is_none(), "the app synthesized a second offline presence");
+    assert!(bridge.connections.lock().unwrap().is_empty());
+  }
+
+  #[tokio::test]
+  async fn forwarded_debug_frames_are_dropped_until_forwarding_was_requested() {
+    let mut bridge = bridge().await;
+    let mut client = connect(&mut bridge, "c1").await;
+    let frame = r#"{"v":1,"type":"debug.frame","payload":{"source":"sidecar","seq":5,"direction":"in","hop":"extension->sidecar","connectionId":"c1","browser":"chrome","type":"tabs.list","timestamp":1,"monotonicUs":1,"bytes":23,"message":{"v":1,"type":"tabs.list"}}}"#;
+    let mut observer = bridge.handle.hub.register(&DebugClientOptions {
+      format: FrameFormat::Raw,
+      ..DebugClientOptions::default()
+    });
+    let merged = |observer: &mut DebugSubscription| {
+      std::iter::from_fn(|| observer.rx.try_recv().ok()).any(|message| &*message == r#"{"v":1,"type":"tabs.list"}"#)
+    };
+
+    client.send(Message::Text(frame.to_string())).await.unwrap();
+    // A message sent afterwards proves the frame was already handled
+    client.send(Message::Text(presence("online", "c1"))).await.unwrap();
+    assert!(next_frontend(&mut bridge).await.is_some());
+    assert!(!merged(&mut observer));
+
+    bridge.handle.send(forward_request(true)).await.unwrap();
+    match next_message(&mut client).await {
+      Message::Text(text) => assert_eq!(forward_command(&text), Some(true)),
+      other => panic!("expected debug.forward, got {other:?}"),
+    }
+    client.send(Message::Text(frame.to_string())).await.unwrap();
+    client.send(Message::Text(presence("online", "c1"))).await.unwrap();
+    assert!(next_frontend(&mut bridge).await.is_some());
+    assert!(merged(&mut observer));
+    // Forwarded frames only feed the debug stream
+    assert!(next_frontend(&mut bridge).await.is_none());
   }
 
-  fn register(&self) -> mpsc::UnboundedReceiver<String> {
-    let (tx, rx) = mpsc::unbounded_channel();
-    self.peers.lock().unwrap().push(tx);
-    rx
+  #[test]
+  fn only_offline_presence_counts_as_the_sidecar_reporting_itself() {
+    assert!(is_offline_presence(&presence("offline", "c1")));
+    assert!(!is_offline_presence(&presence("online", "c1")));
+    assert!(!is_offline_presence(r#"{"v":1,"type":"focus.result","payload":{"sidecar":"offline"}}"#));
+    assert!(!is_offline_presence("not json"));
   }
 }
@@ -1,6 +1,11 @@
 mod bridge_ws;
+mod rate_limit;
 
+use bridge_debug::capture::CaptureStatus;
+use bridge_debug::debug_index::{self, DebugEndpoint};
 use bridge_ws::BridgeHandle;
+use rate_limit::RateLimitCounters;
+use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tauri::{Manager, State};
@@ -39,6 +44,23 @@ async fn bridge_send(state: State<'_, BridgeState>, envelope: Value) -> Result<(
         .map_err(|err| format!("failed to deliver message to sidecar: {err}"))
 }
 
+#[tauri::command]
+fn bridge_rate_limits(state: State<'_, BridgeState>) -> HashMap<String, RateLimitCounters> {
+    state.0.rate_limit_stats()
+}
+
+/// Starts, stops or queries the app-side traffic capture; takes a `debug.capture` payload.
+#[tauri::command]
+fn bridge_capture(state: State<'_, BridgeState>, payload: Value) -> Result<CaptureStatus, String> {
+    state.0.capture(payload).map_err(|err| format!("{err:#}"))
+}
+
+/// Lists the debug sockets of the app and every sidecar that are currently reachable.
+#[tauri::command]
+async fn bridge_debug_endpoints() -> Vec<DebugEndpoint> {
+    debug_index::discover().await
+}
+
 async fn setup(app: tauri::AppHandle) -> Result<(), String> {
     println!("[bridge-app] async setup starting");
 
@@ -72,7 +94,14 @@ pub fn run() {
                 })
                 .build(),
         )
-        .invoke_handler(tauri::generate_handler![greet, test_command, bridge_send])
+        .invoke_handler(tauri::generate_handler![
+            greet,
+            test_command,
+            bridge_send,
+            bridge_rate_limits,
+            bridge_capture,
+            bridge_debug_endpoints
+        ])
         .setup(|app| {
             println!("[bridge-app] builder setup starting");
 
@@ -90,12 +119,22 @@ pub fn run() {
             Ok(())
         });
 
-    println!("[bridge-app] running event loop");
-    match builder.run(tauri::generate_context!()) {
-        Ok(_) => println!("[bridge-app] clean shutdown"),
+    let app = match builder.build(tauri::generate_context!()) {
+        Ok(app) => app,
         Err(err) => {
             eprintln!("[bridge-app] runtime error: {err:?}");
             std::process::exit(1);
         }
-    }
+    };
+
+    println!("[bridge-app] running event loop");
+    app.run(|app_handle, event| {
+        if let tauri::RunEvent::Exit = event {
+            // Tell sidecars we are going away instead of letting them discover a dropped socket
+            if let Some(bridge) = app_handle.try_state::<BridgeState>() {
+                tauri::async_runtime::block_on(bridge.0.shutdown("app-exit"));
+            }
+            println!("[bridge-app] clean shutdown");
+        }
+    });
 }
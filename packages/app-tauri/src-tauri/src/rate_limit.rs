@@ -0,0 +1,529 @@
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::HashMap,
+  env,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+/// Envelope types that are never limited; the connection handshake depends on them.
+const EXEMPT_TYPES: &[&str] = &["presence.status"];
+
+/// Minimum spacing between two `error.rate_limited` replies for the same type.
+const REJECT_NOTICE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Distinct envelope types tracked per connection; further types share the `other` bucket so a
+/// sidecar inventing types cannot grow the limiter without bound.
+const MAX_TYPES: usize = 256;
+const OVERFLOW_TYPE: &str = "other";
+/// Counter key for the `debug.frame`s a sidecar forwards from its own hub.
+const FORWARDED_TYPE: &str = "debug.frame";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Overflow {
+  /// Discard the message silently.
+  Drop,
+  /// Keep only the newest message of the type and deliver it once tokens are available.
+  Coalesce,
+  /// Discard the message and answer the sidecar with `error.rate_limited`.
+  Reject,
+}
+
+impl Overflow {
+  fn parse(value: &str) -> Option<Self> {
+    match value.trim().to_ascii_lowercase().as_str() {
+      "drop" => Some(Self::Drop),
+      "coalesce" | "latest" => Some(Self::Coalesce),
+      "reject" | "error" => Some(Self::Reject),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Rule {
+  pub rate: f64,
+  pub burst: f64,
+  pub overflow: Overflow,
+}
+
+impl Rule {
+  const fn new(rate: f64, burst: f64, overflow: Overflow) -> Self {
+    Self { rate, burst, overflow }
+  }
+
+  /// Parses `rate/burst[:overflow]`, e.g. `10/20:coalesce`.
+  fn parse(value: &str, fallback: Overflow) -> Option<Self> {
+    let (limits, overflow) = match value.split_once(':') {
+      Some((limits, overflow)) => (limits, Overflow::parse(overflow)?),
+      None => (value, fallback),
+    };
+    let (rate, burst) = match limits.split_once('/') {
+      Some((rate, burst)) => (rate.trim().parse().ok()?, burst.trim().parse().ok()?),
+      None => {
+        let rate: f64 = limits.trim().parse().ok()?;
+        (rate, rate * 2.0)
+      }
+    };
+    if rate <= 0.0 || burst < 1.0 {
+      return None;
+    }
+    Some(Self::new(rate, burst, overflow))
+  }
+}
+
+/// Token bucket limits applied to every sidecar connection.
+///
+/// Defaults can be overridden with `BRIDGE_RATE_LIMITS`, a comma separated list of
+/// `type=rate/burst[:drop|coalesce|reject]` entries. `*` sets the fallback for unlisted
+/// types and `connection` sets the per-connection budget shared by all types. `forwarded` is a
+/// separate budget for forwarded debug frames, which are always dropped when it runs out.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimitConfig {
+  pub connection: Rule,
+  pub forwarded: Rule,
+  pub default_rule: Rule,
+  pub rules: HashMap<String, Rule>,
+}
+
+impl Default for RateLimitConfig {
+  fn default() -> Self {
+    let mut rules = HashMap::new();
+    rules.insert("tabs.list".to_string(), Rule::new(5.0, 10.0, Overflow::Coalesce));
+    Self {
+      connection: Rule::new(200.0, 400.0, Overflow::Drop),
+      forwarded: Rule::new(500.0, 1000.0, Overflow::Drop),
+      default_rule: Rule::new(50.0, 100.0, Overflow::Reject),
+      rules,
+    }
+  }
+}
+
+impl RateLimitConfig {
+  pub fn from_env() -> Self {
+    let mut config = Self::default();
+    let Ok(spec) = env::var("BRIDGE_RATE_LIMITS") else {
+      return config;
+    };
+
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+      let Some((key, value)) = entry.split_once('=') else {
+        eprintln!("[app] ignoring malformed rate limit entry: {entry}");
+        continue;
+      };
+      let key = key.trim();
+      let fallback = match key {
+        "connection" => config.connection.overflow,
+        "forwarded" => Overflow::Drop,
+        "*" => config.default_rule.overflow,
+        other => config.rule_for(other).overflow,
+      };
+      let Some(rule) = Rule::parse(value, fallback) else {
+        eprintln!("[app] ignoring malformed rate limit entry: {entry}");
+        continue;
+      };
+      match key {
+        "connection" => config.connection = rule,
+        "forwarded" => config.forwarded = rule,
+        "*" => config.default_rule = rule,
+        other => {
+          config.rules.insert(other.to_string(), rule);
+        }
+      }
+    }
+
+    config
+  }
+
+  fn rule_for(&self, kind: &str) -> Rule {
+    self.rules.get(kind).copied().unwrap_or(self.default_rule)
+  }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+  rate: f64,
+  burst: f64,
+  tokens: f64,
+  updated: Instant,
+}
+
+impl TokenBucket {
+  fn new(rule: Rule, now: Instant) -> Self {
+    Self {
+      rate: rule.rate,
+      burst: rule.burst,
+      tokens: rule.burst,
+      updated: now,
+    }
+  }
+
+  fn refill(&mut self, now: Instant) {
+    let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+    self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+    self.updated = now;
+  }
+
+  fn ready(&mut self, now: Instant) -> bool {
+    self.refill(now);
+    self.tokens >= 1.0
+  }
+
+  fn take(&mut self) {
+    self.tokens -= 1.0;
+  }
+
+  /// Time until one token is available, assuming `refill` ran at `self.updated`.
+  fn wait(&self) -> Duration {
+    if self.tokens >= 1.0 {
+      Duration::ZERO
+    } else {
+      Duration::from_secs_f64((1.0 - self.tokens) / self.rate)
+    }
+  }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeCounters {
+  pub allowed: u64,
+  pub dropped: u64,
+  pub coalesced: u64,
+  pub rejected: u64,
+}
+
+/// Per-connection counters, shared with `BridgeHandle` so the UI can read them.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimitCounters {
+  pub by_type: HashMap<String, TypeCounters>,
+}
+
+impl RateLimitCounters {
+  fn entry(&mut self, kind: &str) -> &mut TypeCounters {
+    self.by_type.entry(kind.to_string()).or_default()
+  }
+}
+
+pub type SharedCounters = Arc<Mutex<RateLimitCounters>>;
+
+pub enum Admission {
+  Allow,
+  Drop,
+  Coalesced,
+  /// Message discarded; carries the `error.rate_limited` reply when one is due.
+  Reject(Option<String>),
+}
+
+#[derive(Deserialize)]
+struct EnvelopeType {
+  #[serde(rename = "type")]
+  kind: Option<String>,
+}
+
+/// Extracts the envelope type without building a full `serde_json::Value`.
+pub fn envelope_type(message: &str) -> Option<String> {
+  serde_json::from_str::<EnvelopeType>(message).ok()?.kind
+}
+
+/// Token bucket state for a single sidecar connection.
+pub struct ConnectionLimiter {
+  config: Arc<RateLimitConfig>,
+  connection: TokenBucket,
+  forwarded: TokenBucket,
+  types: HashMap<String, TokenBucket>,
+  pending: HashMap<String, String>,
+  last_notice: HashMap<String, Instant>,
+  counters: SharedCounters,
+}
+
+impl ConnectionLimiter {
+  pub fn new(config: Arc<RateLimitConfig>) -> Self {
+    let now = Instant::now();
+    Self {
+      connection: TokenBucket::new(config.connection, now),
+      forwarded: TokenBucket::new(config.forwarded, now),
+      config,
+      types: HashMap::new(),
+      pending: HashMap::new(),
+      last_notice: HashMap::new(),
+      counters: SharedCounters::default(),
+    }
+  }
+
+  pub fn counters(&self) -> SharedCounters {
+    self.counters.clone()
+  }
+
+  pub fn admit(&mut self, message: &str, now: Instant) -> Admission {
+    let kind = envelope_type(message).unwrap_or_else(|| "unknown".to_string());
+    if EXEMPT_TYPES.contains(&kind.as_str()) {
+      return Admission::Allow;
+    }
+    let tracked = self.types.len() < MAX_TYPES || self.types.contains_key(&kind);
+    let kind = if tracked { kind } else { OVERFLOW_TYPE.to_string() };
+
+    let rule = self.config.rule_for(&kind);
+    let bucket = self
+      .types
+      .entry(kind.clone())
+      .or_insert_with(|| TokenBucket::new(rule, now));
+
+    // Only spend tokens when both budgets allow it, and never jump ahead of a coalesced message.
+    let overflow = if self.pending.contains_key(&kind) {
+      Overflow::Coalesce
+    } else if !bucket.ready(now) {
+      rule.overflow
+    } else if !self.connection.ready(now) {
+      self.config.connection.overflow
+    } else {
+      bucket.take();
+      self.connection.take();
+      self.record(&kind, |c| c.allowed += 1);
+      return Admission::Allow;
+    };
+
+    match overflow {
+      Overflow::Drop => {
+        self.record(&kind, |c| c.dropped += 1);
+        Admission::Drop
+      }
+      Overflow::Coalesce => {
+        self.record(&kind, |c| c.coalesced += 1);
+        self.pending.insert(kind, message.to_string());
+        Admission::Coalesced
+      }
+      Overflow::Reject => {
+        self.record(&kind, |c| c.rejected += 1);
+        let due = self
+          .last_notice
+          .get(&kind)
+          .map_or(true, |at| now.saturating_duration_since(*at) >= REJECT_NOTICE_INTERVAL);
+        if !due {
+          return Admission::Reject(None);
+        }
+        self.last_notice.insert(kind.clone(), now);
+        let retry_after = self.types[&kind].wait().max(self.connection.wait());
+        Admission::Reject(Some(rate_limited_envelope(&kind, retry_after)))
+      }
+    }
+  }
+
+  /// Spends a token of the forwarded-frame budget, which leaves the connection budget to real
+  /// traffic. Returns whether the frame may be merged into the debug stream.
+  pub fn admit_forwarded(&mut self, now: Instant) -> bool {
+    let allowed = self.forwarded.ready(now);
+    if allowed {
+      self.forwarded.take();
+      self.record(FORWARDED_TYPE, |c| c.allowed += 1);
+    } else {
+      self.record(FORWARDED_TYPE, |c| c.dropped += 1);
+    }
+    allowed
+  }
+
+  /// Earliest instant at which a coalesced message can be delivered.
+  pub fn next_flush(&mut self, now: Instant) -> Option<Instant> {
+    if self.pending.is_empty() {
+      return None;
+    }
+    self.connection.refill(now);
+    let connection_wait = self.connection.wait();
+    let pending = &self.pending;
+    self
+      .types
+      .iter_mut()
+      .filter(|(kind, _)| pending.contains_key(*kind))
+      .map(|(_, bucket)| {
+        bucket.refill(now);
+        now + bucket.wait().max(connection_wait)
+      })
+      .min()
+  }
+
+  /// Releases coalesced messages whose budgets have recovered.
+  pub fn flush(&mut self, now: Instant) -> Vec<String> {
+    let mut ready = Vec::new();
+    let kinds: Vec<String> = self.pending.keys().cloned().collect();
+    for kind in kinds {
+      let Some(bucket) = self.types.get_mut(&kind) else {
+        continue;
+      };
+      if !bucket.ready(now) || !self.connection.ready(now) {
+        continue;
+      }
+      bucket.take();
+      self.connection.take();
+      if let Some(message) = self.pending.remove(&kind) {
+        self.record(&kind, |c| c.allowed += 1);
+        ready.push(message);
+      }
+    }
+    ready
+  }
+
+  fn record(&self, kind: &str, update: impl FnOnce(&mut TypeCounters)) {
+    if let Ok(mut counters) = self.counters.lock() {
+      update(counters.entry(kind));
+    }
+  }
+}
+
+fn rate_limited_envelope(kind: &str, retry_after: Duration) -> String {
+  serde_json::json!({
+    "v": 1,
+    "type": "error.rate_limited",
+    "payload": {
+      "type": kind,
+      "retryAfterMs": retry_after.as_millis() as u64
+    }
+  })
+  .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn limiter(rules: &[(&str, Rule)]) -> ConnectionLimiter {
+    let config = RateLimitConfig {
+      connection: Rule::new(1000.0, 1000.0, Overflow::Drop),
+      forwarded: Rule::new(1000.0, 1000.0, Overflow::Drop),
+      default_rule: Rule::new(1000.0, 1000.0, Overflow::Drop),
+      rules: rules.iter().map(|(kind, rule)| (kind.to_string(), *rule)).collect(),
+    };
+    ConnectionLimiter::new(Arc::new(config))
+  }
+
+  fn message(kind: &str, n: u32) -> String {
+    format!(r#"{{"v":1,"type":"{kind}","payload":{{"n":{n}}}}}"#)
+  }
+
+  fn allowed(admission: &Admission) -> bool {
+    matches!(admission, Admission::Allow)
+  }
+
+  #[test]
+  fn burst_is_spent_then_refilled_at_the_rate() {
+    let mut limiter = limiter(&[("a", Rule::new(10.0, 3.0, Overflow::Drop))]);
+    let start = Instant::now();
+
+    for n in 0..3 {
+      assert!(allowed(&limiter.admit(&message("a", n), start)), "burst message {n}");
+    }
+    assert!(matches!(limiter.admit(&message("a", 3), start), Admission::Drop));
+
+    // 10 per second: one token after 100 ms, not two
+    let later = start + Duration::from_millis(100);
+    assert!(allowed(&limiter.admit(&message("a", 4), later)));
+    assert!(matches!(limiter.admit(&message("a", 5), later), Admission::Drop));
+
+    // Refilling never exceeds the burst
+    let much_later = start + Duration::from_secs(60);
+    for n in 0..3 {
+      assert!(allowed(&limiter.admit(&message("a", 10 + n), much_later)));
+    }
+    assert!(matches!(limiter.admit(&message("a", 13), much_later), Admission::Drop));
+
+    let counters = limiter.counters().lock().unwrap().by_type["a"].clone();
+    assert_eq!((counters.allowed, counters.dropped), (7, 3));
+  }
+
+  #[test]
+  fn types_have_separate_buckets() {
+    let mut limiter = limiter(&[("a", Rule::new(1.0, 1.0, Overflow::Drop))]);
+    let now = Instant::now();
+
+    assert!(allowed(&limiter.admit(&message("a", 0), now)));
+    assert!(matches!(limiter.admit(&message("a", 1), now), Admission::Drop));
+    assert!(allowed(&limiter.admit(&message("b", 0), now)));
+    assert!(allowed(&limiter.admit(r#"{"v":1,"type":"presence.status"}"#, now)));
+  }
+
+  #[test]
+  fn connection_budget_is_shared_by_all_types() {
+    let mut limiter = limiter(&[]);
+    limiter.connection = TokenBucket::new(Rule::new(1.0, 2.0, Overflow::Drop), Instant::now());
+    let now = Instant::now();
+
+    assert!(allowed(&limiter.admit(&message("a", 0), now)));
+    assert!(allowed(&limiter.admit(&message("b", 0), now)));
+    assert!(matches!(limiter.admit(&message("c", 0), now), Admission::Drop));
+  }
+
+  #[test]
+  fn forwarded_frames_have_their_own_budget() {
+    let mut limiter = limiter(&[]);
+    let now = Instant::now();
+    limiter.connection = TokenBucket::new(Rule::new(1.0, 1.0, Overflow::Drop), now);
+    limiter.forwarded = TokenBucket::new(Rule::new(1.0, 2.0, Overflow::Drop), now);
+
+    assert!(limiter.admit_forwarded(now));
+    assert!(limiter.admit_forwarded(now));
+    assert!(!limiter.admit_forwarded(now));
+    // Forwarding never eats into the budget for real traffic
+    assert!(allowed(&limiter.admit(&message("a", 0), now)));
+
+    let counters = limiter.counters().lock().unwrap().by_type[FORWARDED_TYPE].clone();
+    assert_eq!((counters.allowed, counters.dropped), (2, 1));
+  }
+
+  #[test]
+  fn reject_notices_are_coalesced_per_interval() {
+    let mut limiter = limiter(&[("a", Rule::new(0.5, 1.0, Overflow::Reject))]);
+    let start = Instant::now();
+
+    assert!(allowed(&limiter.admit(&message("a", 0), start)));
+    let Admission::Reject(Some(notice)) = limiter.admit(&message("a", 1), start) else {
+      panic!("first rejection should carry a notice");
+    };
+    let notice: serde_json::Value = serde_json::from_str(&notice).unwrap();
+    assert_eq!(notice["type"], "error.rate_limited");
+    assert_eq!(notice["payload"]["type"], "a");
+    assert_eq!(notice["payload"]["retryAfterMs"], 2000);
+
+    for n in 2..5 {
+      assert!(matches!(limiter.admit(&message("a", n), start), Admission::Reject(None)));
+    }
+    // Still out of tokens a second later, but a new notice is due
+    let later = start + REJECT_NOTICE_INTERVAL;
+    assert!(matches!(limiter.admit(&message("a", 5), later), Admission::Reject(Some(_))));
+    assert_eq!(limiter.counters().lock().unwrap().by_type["a"].rejected, 5);
+  }
+
+  #[test]
+  fn coalesced_messages_keep_the_newest_and_flush_when_ready() {
+    let mut limiter = limiter(&[("tabs.list", Rule::new(10.0, 1.0, Overflow::Coalesce))]);
+    let start = Instant::now();
+
+    assert!(allowed(&limiter.admit(&message("tabs.list", 0), start)));
+    assert!(matches!(limiter.admit(&message("tabs.list", 1), start), Admission::Coalesced));
+    assert!(matches!(limiter.admit(&message("tabs.list", 2), start), Admission::Coalesced));
+    assert!(limiter.flush(start).is_empty());
+
+    let flush_at = limiter.next_flush(start).expect("a coalesced message is pending");
+    assert_eq!(flush_at, start + Duration::from_millis(100));
+    assert_eq!(limiter.flush(flush_at), vec![message("tabs.list", 2)]);
+    assert_eq!(limiter.next_flush(flush_at), None);
+  }
+
+  #[test]
+  fn distinct_types_beyond_the_cap_share_one_bucket() {
+    let mut limiter = limiter(&[]);
+    let now = Instant::now();
+
+    for n in 0..(MAX_TYPES as u32 + 100) {
+      limiter.admit(&message(&format!("flood.{n}"), n), now);
+    }
+
+    assert_eq!(limiter.types.len(), MAX_TYPES + 1);
+    let counters = limiter.counters().lock().unwrap().clone();
+    assert_eq!(counters.by_type.len(), MAX_TYPES + 1);
+    assert_eq!(counters.by_type[OVERFLOW_TYPE].allowed, 100);
+    // Types seen before the cap keep their own bucket
+    assert!(allowed(&limiter.admit(&message("flood.0", 0), now)));
+    assert_eq!(limiter.counters().lock().unwrap().by_type["flood.0"].allowed, 2);
+  }
+}
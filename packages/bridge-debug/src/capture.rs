@@ -0,0 +1,610 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::private;
+use crate::Role;
+
+const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_MAX_FILES: usize = 5;
+/// Frames queued for the writer thread before new frames are dropped.
+const QUEUE_DEPTH: usize = 4096;
+const REDACTED: &str = "[redacted]";
+/// Where in a `debug.frame` a `FieldRedactor` looks by default: the original message.
+const DEFAULT_REDACT_POINTERS: [&str; 2] = ["/payload/message", "/payload/text"];
+
+/// Rewrites a captured `debug.frame` before it is written to disk.
+///
+/// The frame's original envelope is under `payload.message` (or `payload.text` when it was not
+/// JSON).
+pub trait Redactor: Send + Sync {
+    fn redact(&self, frame: &mut Value);
+}
+
+/// Masks the value of every object key with one of the given names, at any depth below the
+/// frame's redacted pointers. The field `*` masks everything there, including non-JSON text.
+pub struct FieldRedactor {
+    fields: Vec<String>,
+    pointers: Vec<String>,
+}
+
+impl FieldRedactor {
+    /// Redacts the original message only.
+    pub fn new(fields: Vec<String>) -> Self {
+        Self {
+            fields,
+            pointers: DEFAULT_REDACT_POINTERS.map(str::to_string).to_vec(),
+        }
+    }
+
+    /// Redacts below these JSON pointers into the frame instead, e.g. `/payload` to also mask
+    /// the hub's metadata such as `connectionId`.
+    pub fn with_pointers(mut self, pointers: Vec<String>) -> Self {
+        self.pointers = pointers;
+        self
+    }
+
+    /// Reads the field list from `BRIDGE_CAPTURE_REDACT`, e.g. `url,title,favIconUrl` or `*`, and
+    /// the pointers from `BRIDGE_CAPTURE_REDACT_POINTERS`, e.g. `/payload`.
+    pub fn from_env() -> Option<Self> {
+        Self::from_vars(|name| env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        let fields = split_list(&var("BRIDGE_CAPTURE_REDACT")?);
+        if fields.is_empty() {
+            return None;
+        }
+        let redactor = Self::new(fields);
+        match var("BRIDGE_CAPTURE_REDACT_POINTERS").map(|pointers| split_list(&pointers)) {
+            Some(pointers) if !pointers.is_empty() => Some(redactor.with_pointers(pointers)),
+            _ => Some(redactor),
+        }
+    }
+
+    fn mask(&self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, inner) in map.iter_mut() {
+                    if self.fields.iter().any(|field| field == key) {
+                        *inner = Value::String(REDACTED.to_string());
+                    } else {
+                        self.mask(inner);
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|item| self.mask(item)),
+            _ => {}
+        }
+    }
+}
+
+impl Redactor for FieldRedactor {
+    fn redact(&self, frame: &mut Value) {
+        let everything = self.fields.iter().any(|field| field == "*");
+        for pointer in &self.pointers {
+            match frame.pointer_mut(pointer) {
+                Some(value) if everything => *value = Value::String(REDACTED.to_string()),
+                Some(value) => self.mask(value),
+                None => {}
+            }
+        }
+    }
+}
+
+/// Where and how much to capture. Defaults come from `BRIDGE_CAPTURE_DIR` (else `captures` in
+/// the per-user data dir), `BRIDGE_CAPTURE_MAX_BYTES` and `BRIDGE_CAPTURE_MAX_FILES`; a
+/// `debug.capture` command can override the other fields in its payload.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CaptureConfig {
+    /// Only ever set from the environment, so no bridge message can choose where files go.
+    #[serde(skip)]
+    pub dir: PathBuf,
+    /// Size at which the current file is closed and a new one started.
+    pub max_bytes: u64,
+    /// Number of files kept per capture session; older ones are deleted.
+    pub max_files: usize,
+    /// Extra keys to mask for this capture, on top of the hub's redactors.
+    pub redact: Vec<String>,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self::from_vars(|name| env::var(name).ok())
+    }
+}
+
+impl CaptureConfig {
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let number = |name| var(name).and_then(|v| v.trim().parse().ok());
+        Self {
+            dir: var("BRIDGE_CAPTURE_DIR")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .unwrap_or_else(|| private::data_dir().join("captures")),
+            max_bytes: number("BRIDGE_CAPTURE_MAX_BYTES").unwrap_or(DEFAULT_MAX_BYTES),
+            max_files: number("BRIDGE_CAPTURE_MAX_FILES")
+                .map(|files| files as usize)
+                .unwrap_or(DEFAULT_MAX_FILES),
+            redact: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureStatus {
+    pub enabled: bool,
+    pub path: Option<PathBuf>,
+    pub files: Vec<PathBuf>,
+    pub frames: u64,
+    pub bytes: u64,
+    /// Frames lost because the writer could not keep up.
+    pub dropped: u64,
+    pub error: Option<String>,
+}
+
+/// A running capture: frames are handed to a writer thread so the hub never blocks on disk.
+pub struct Capture {
+    tx: SyncSender<Arc<str>>,
+    status: Arc<Mutex<CaptureStatus>>,
+}
+
+impl Capture {
+    /// Starts writing to files named after `label`, e.g. `sidecar-<connectionId>`.
+    ///
+    /// The directory is made private to the current user and each file is created `0600`, since
+    /// captures hold whatever crossed the bridge.
+    pub fn start(role: Role, config: CaptureConfig, label: &str, mut redactors: Vec<Arc<dyn Redactor>>) -> Result<Self> {
+        private::create_private_dir(&config.dir).context("preparing the capture dir")?;
+        if !config.redact.is_empty() {
+            redactors.push(Arc::new(FieldRedactor::new(config.redact.clone())));
+        }
+
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let mut writer = RotatingWriter {
+            role,
+            prefix: format!("{}-{started}", sanitize(label)),
+            config,
+            file: None,
+            index: 0,
+            written: 0,
+            files: Vec::new(),
+        };
+        writer.rotate()?;
+
+        let status = Arc::new(Mutex::new(CaptureStatus {
+            enabled: true,
+            path: writer.files.last().cloned(),
+            files: writer.files.clone(),
+            ..CaptureStatus::default()
+        }));
+        let (tx, rx) = mpsc::sync_channel::<Arc<str>>(QUEUE_DEPTH);
+        let status_for_thread = status.clone();
+
+        thread::Builder::new()
+            .name("debug-capture".to_string())
+            .spawn(move || {
+                for frame in rx {
+                    let line = redact_line(&frame, &redactors);
+                    let result = writer.write_line(&line);
+                    let mut status = status_for_thread.lock().unwrap();
+                    match result {
+                        Ok(()) => {
+                            status.frames += 1;
+                            status.bytes += line.len() as u64 + 1;
+                            status.path = writer.files.last().cloned();
+                            status.files = writer.files.clone();
+                        }
+                        Err(err) => {
+                            eprintln!("[{}] capture write failed: {err:#}", role.name());
+                            status.error = Some(format!("{err:#}"));
+                        }
+                    }
+                }
+                writer.finish();
+            })
+            .context("spawning capture writer")?;
+
+        Ok(Self { tx, status })
+    }
+
+    pub fn record(&self, frame: Arc<str>) {
+        if let Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) = self.tx.try_send(frame) {
+            self.status.lock().unwrap().dropped += 1;
+        }
+    }
+
+    pub fn status(&self) -> CaptureStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Stops accepting frames; the writer thread flushes what is queued and exits.
+    pub fn stop(self) -> CaptureStatus {
+        let mut status = self.status();
+        status.enabled = false;
+        status
+    }
+}
+
+struct RotatingWriter {
+    role: Role,
+    config: CaptureConfig,
+    prefix: String,
+    file: Option<BufWriter<File>>,
+    index: usize,
+    written: u64,
+    files: Vec<PathBuf>,
+}
+
+impl RotatingWriter {
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.written > 0 && self.written + len > self.config.max_bytes {
+            self.rotate()?;
+        }
+        let file = self.file.as_mut().context("capture file closed")?;
+        file.write_all(line.as_bytes())?;
+        file.write_all(b"\n")?;
+        file.flush()?;
+        self.written += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        self.finish();
+        self.index += 1;
+        let path = self
+            .config
+            .dir
+            .join(format!("{}-{:03}.jsonl", self.prefix, self.index));
+        let file = private::create_private_file(&path)?;
+        eprintln!("[{}] capturing bridge traffic to {}", self.role.name(), path.display());
+        self.file = Some(BufWriter::new(file));
+        self.written = 0;
+        self.files.push(path);
+
+        while self.files.len() > self.config.max_files.max(1) {
+            let oldest = self.files.remove(0);
+            let _ = fs::remove_file(oldest);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) {
+        if let Some(mut file) = self.file.take() {
+            let _ = file.flush();
+        }
+    }
+}
+
+fn redact_line(frame: &str, redactors: &[Arc<dyn Redactor>]) -> String {
+    if redactors.is_empty() {
+        return frame.to_string();
+    }
+    match serde_json::from_str::<Value>(frame) {
+        Ok(mut value) => {
+            for redactor in redactors {
+                redactor.redact(&mut value);
+            }
+            value.to_string()
+        }
+        Err(_) => frame.to_string(),
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn sanitize(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    /// A throwaway directory under the system temp dir, removed on drop.
+    struct Scratch {
+        root: PathBuf,
+    }
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            let root = env::temp_dir().join(format!("bridge-capture-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            Self { root }
+        }
+
+        fn config(&self, max_bytes: u64, max_files: usize) -> CaptureConfig {
+            CaptureConfig {
+                dir: self.root.clone(),
+                max_bytes,
+                max_files,
+                redact: Vec::new(),
+            }
+        }
+
+        /// Capture files on disk, oldest first.
+        fn files(&self) -> Vec<PathBuf> {
+            let mut files: Vec<PathBuf> = fs::read_dir(&self.root)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .collect();
+            files.sort();
+            files
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn rotating_writer(config: CaptureConfig) -> RotatingWriter {
+        fs::create_dir_all(&config.dir).unwrap();
+        let mut writer = RotatingWriter {
+            role: Role::Sidecar,
+            config,
+            prefix: "test".to_string(),
+            file: None,
+            index: 0,
+            written: 0,
+            files: Vec::new(),
+        };
+        writer.rotate().unwrap();
+        writer
+    }
+
+    /// A 40 byte line, 41 with its newline.
+    fn line(n: usize) -> String {
+        format!("{n:0>40}")
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        fs::read_to_string(path).unwrap().lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn files_rotate_before_they_would_pass_max_bytes() {
+        let scratch = Scratch::new("rotate");
+        let mut writer = rotating_writer(scratch.config(100, 10));
+        for n in 0..5 {
+            writer.write_line(&line(n)).unwrap();
+        }
+        writer.finish();
+
+        let files = scratch.files();
+        assert_eq!(files, writer.files);
+        let contents: Vec<Vec<String>> = files.iter().map(|path| lines(path)).collect();
+        assert_eq!(contents, vec![vec![line(0), line(1)], vec![line(2), line(3)], vec![line(4)]]);
+        assert!(files[0].ends_with("test-001.jsonl"));
+    }
+
+    #[test]
+    fn a_line_larger_than_max_bytes_still_gets_written() {
+        let scratch = Scratch::new("oversized");
+        let mut writer = rotating_writer(scratch.config(10, 10));
+        writer.write_line(&line(0)).unwrap();
+        writer.write_line(&line(1)).unwrap();
+        writer.finish();
+
+        let contents: Vec<Vec<String>> = scratch.files().iter().map(|path| lines(path)).collect();
+        assert_eq!(contents, vec![vec![line(0)], vec![line(1)]]);
+    }
+
+    #[test]
+    fn only_the_newest_max_files_are_kept() {
+        let scratch = Scratch::new("prune");
+        let mut writer = rotating_writer(scratch.config(41, 2));
+        for n in 0..5 {
+            writer.write_line(&line(n)).unwrap();
+        }
+        writer.finish();
+
+        let files = scratch.files();
+        assert_eq!(files, writer.files);
+        assert_eq!(files.len(), 2);
+        assert_eq!(lines(&files[0]), vec![line(3)]);
+        assert_eq!(lines(&files[1]), vec![line(4)]);
+        assert!(files[1].ends_with("test-005.jsonl"));
+
+        // At least the file being written survives a limit of zero
+        let mut writer = rotating_writer(scratch.config(41, 0));
+        writer.write_line(&line(0)).unwrap();
+        writer.write_line(&line(1)).unwrap();
+        assert_eq!(writer.files.len(), 1);
+    }
+
+    #[test]
+    fn config_reads_the_environment_and_falls_back_to_defaults() {
+        let vars = HashMap::from([
+            ("BRIDGE_CAPTURE_DIR", "/var/tmp/captures"),
+            ("BRIDGE_CAPTURE_MAX_BYTES", " 2048 "),
+            ("BRIDGE_CAPTURE_MAX_FILES", "3"),
+        ]);
+        let config = CaptureConfig::from_vars(|name| vars.get(name).map(|v| v.to_string()));
+        assert_eq!(config.dir, PathBuf::from("/var/tmp/captures"));
+        assert_eq!(config.max_bytes, 2048);
+        assert_eq!(config.max_files, 3);
+
+        let vars = HashMap::from([
+            ("BRIDGE_CAPTURE_DIR", ""),
+            ("BRIDGE_CAPTURE_MAX_BYTES", "lots"),
+            ("BRIDGE_CAPTURE_MAX_FILES", "-1"),
+        ]);
+        let config = CaptureConfig::from_vars(|name| vars.get(name).map(|v| v.to_string()));
+        assert_eq!(config.dir, private::data_dir().join("captures"));
+        assert_eq!(config.max_bytes, DEFAULT_MAX_BYTES);
+        assert_eq!(config.max_files, DEFAULT_MAX_FILES);
+        assert!(config.redact.is_empty());
+    }
+
+    fn tabs_frame() -> Value {
+        json!({
+            "v": 1,
+            "type": "debug.frame",
+            "payload": {
+                "hop": "extension->sidecar",
+                "connectionId": "c1",
+                "type": "tabs.list",
+                "message": {
+                    "v": 1,
+                    "type": "tabs.list",
+                    "payload": {
+                        "tabs": [
+                            { "id": 1, "url": "https://example.com/secret", "title": "Inbox" },
+                            { "id": 2, "url": "https://example.org/", "title": "News" }
+                        ],
+                        "connectionId": "c1"
+                    }
+                }
+            }
+        })
+    }
+
+    fn redacted(redactor: &FieldRedactor, mut frame: Value) -> Value {
+        redactor.redact(&mut frame);
+        frame
+    }
+
+    #[test]
+    fn field_redactor_masks_listed_keys_in_the_message_only() {
+        let redactor = FieldRedactor::new(vec!["url".to_string(), "connectionId".to_string()]);
+        let frame = redacted(&redactor, tabs_frame());
+        let message = &frame["payload"]["message"];
+        for tab in message["payload"]["tabs"].as_array().unwrap() {
+            assert_eq!(tab["url"], REDACTED);
+            assert_ne!(tab["title"], REDACTED);
+        }
+        assert_eq!(message["payload"]["connectionId"], REDACTED);
+        assert_eq!(frame["payload"]["connectionId"], "c1");
+    }
+
+    #[test]
+    fn field_redactor_pointers_choose_where_it_looks() {
+        let redactor = FieldRedactor::new(vec!["connectionId".to_string()]).with_pointers(vec!["/payload".to_string()]);
+        let frame = redacted(&redactor, tabs_frame());
+        assert_eq!(frame["payload"]["connectionId"], REDACTED);
+        assert_eq!(frame["payload"]["message"]["payload"]["connectionId"], REDACTED);
+
+        let redactor = FieldRedactor::new(vec!["type".to_string()]).with_pointers(vec!["/payload/message/payload".to_string()]);
+        let frame = redacted(&redactor, tabs_frame());
+        assert_eq!(frame["payload"]["type"], "tabs.list");
+        assert_eq!(frame["payload"]["message"]["type"], "tabs.list");
+    }
+
+    #[test]
+    fn a_star_masks_the_whole_message_or_text() {
+        let redactor = FieldRedactor::new(vec!["*".to_string()]);
+        let frame = redacted(&redactor, tabs_frame());
+        assert_eq!(frame["payload"]["message"], REDACTED);
+        assert_eq!(frame["payload"]["type"], "tabs.list");
+
+        let text = json!({ "v": 1, "type": "debug.frame", "payload": { "text": "user typed a password" } });
+        assert_eq!(redacted(&redactor, text)["payload"]["text"], REDACTED);
+
+        // Named fields leave text alone, there are no keys to match
+        let text = json!({ "v": 1, "type": "debug.frame", "payload": { "text": "url=https://example.com" } });
+        let redactor = FieldRedactor::new(vec!["url".to_string()]);
+        assert_eq!(redacted(&redactor, text)["payload"]["text"], "url=https://example.com");
+    }
+
+    #[test]
+    fn field_redactor_reads_fields_and_pointers_from_the_environment() {
+        let vars = HashMap::from([
+            ("BRIDGE_CAPTURE_REDACT", "url, title,"),
+            ("BRIDGE_CAPTURE_REDACT_POINTERS", "/payload, /meta"),
+        ]);
+        let redactor = FieldRedactor::from_vars(|name| vars.get(name).map(|v| v.to_string())).unwrap();
+        assert_eq!(redactor.fields, ["url", "title"]);
+        assert_eq!(redactor.pointers, ["/payload", "/meta"]);
+
+        let vars = HashMap::from([("BRIDGE_CAPTURE_REDACT", "url"), ("BRIDGE_CAPTURE_REDACT_POINTERS", " ")]);
+        let redactor = FieldRedactor::from_vars(|name| vars.get(name).map(|v| v.to_string())).unwrap();
+        assert_eq!(redactor.pointers, DEFAULT_REDACT_POINTERS);
+
+        assert!(FieldRedactor::from_vars(|_| Some(" , ".to_string())).is_none());
+        assert!(FieldRedactor::from_vars(|_| None).is_none());
+    }
+
+    #[test]
+    fn redact_line_leaves_frames_alone_without_redactors_or_json() {
+        let redactor: Arc<dyn Redactor> = Arc::new(FieldRedactor::new(vec!["*".to_string()]));
+        let redactors = [redactor];
+        let line = tabs_frame().to_string();
+        assert_eq!(redact_line(&line, &[]), line);
+        assert_eq!(redact_line("not json", &redactors), "not json");
+        let masked: Value = serde_json::from_str(&redact_line(&line, &redactors)).unwrap();
+        assert_eq!(masked["payload"]["message"], REDACTED);
+    }
+
+    #[test]
+    fn captures_are_private_and_redacted_on_disk() {
+        let scratch = Scratch::new("private");
+        let mut config = scratch.config(DEFAULT_MAX_BYTES, 2);
+        config.redact = vec!["url".to_string()];
+        let capture = Capture::start(Role::App, config, "app", Vec::new()).unwrap();
+        capture.record(tabs_frame().to_string().into());
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while capture.status().frames == 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let status = capture.stop();
+        assert_eq!(status.frames, 1);
+        let path = status.path.unwrap();
+        let written: Value = serde_json::from_str(&lines(&path)[0]).unwrap();
+        let tab = &written["payload"]["message"]["payload"]["tabs"][0];
+        assert_eq!(tab["url"], REDACTED);
+        assert_eq!(tab["title"], "Inbox");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&scratch.root), 0o700);
+            assert_eq!(mode(&path), 0o600);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn an_existing_capture_dir_is_tightened_to_the_owner() {
+        use std::os::unix::fs::PermissionsExt;
+        let scratch = Scratch::new("loose");
+        fs::create_dir_all(&scratch.root).unwrap();
+        fs::set_permissions(&scratch.root, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let capture = Capture::start(Role::Sidecar, scratch.config(DEFAULT_MAX_BYTES, 1), "sidecar", Vec::new()).unwrap();
+        capture.stop();
+        let mode = fs::metadata(&scratch.root).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, 0o700);
+    }
+}
@@ -0,0 +1,294 @@
+use anyhow::{bail, Context as _, Result};
+use serde::Serialize;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+pub use tokio_tungstenite::tungstenite::http::StatusCode;
+
+/// Largest request head accepted on the debug port.
+const HEAD_LIMIT: usize = 8 * 1024;
+/// How long a client gets to send its request head.
+const HEAD_TIMEOUT: Duration = Duration::from_secs(2);
+/// Content type Prometheus expects for the text exposition format.
+pub const PROMETHEUS_TEXT: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// A plain HTTP request on the debug port, as opposed to a WebSocket handshake.
+#[derive(Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    /// Request target without its query string.
+    pub path: String,
+}
+
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn json(value: &impl Serialize) -> Self {
+        Self {
+            status: StatusCode::OK,
+            content_type: "application/json",
+            body: serde_json::to_string_pretty(value).unwrap_or_default(),
+        }
+    }
+
+    pub fn text(content_type: &'static str, body: String) -> Self {
+        Self {
+            status: StatusCode::OK,
+            content_type,
+            body,
+        }
+    }
+
+    pub fn not_found(path: &str) -> Self {
+        Self {
+            status: StatusCode::NOT_FOUND,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("no such endpoint: {path}\n"),
+        }
+    }
+
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+}
+
+/// Reads the request head of a new connection on the debug port.
+///
+/// Returns `None` as the request for a WebSocket upgrade, and the parsed request line for
+/// anything else. Either way the returned stream replays the head to its next reader, so the
+/// handshake sees the whole request.
+pub async fn read_request(stream: TcpStream) -> Result<(DebugStream, Option<HttpRequest>)> {
+    let mut stream = DebugStream {
+        inner: stream,
+        head: Vec::with_capacity(1024),
+        replayed: 0,
+    };
+    let head_len = tokio::time::timeout(HEAD_TIMEOUT, read_head(&mut stream))
+        .await
+        .context("timed out waiting for a request")??;
+    let head = String::from_utf8_lossy(&stream.head[..head_len]).into_owned();
+
+    let mut lines = head.lines();
+    let request_line = lines.next().unwrap_or_default();
+    let is_upgrade = lines.any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("upgrade") && value.trim().eq_ignore_ascii_case("websocket")
+        })
+    });
+    if is_upgrade {
+        return Ok((stream, None));
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        bail!("malformed request line {request_line:?}");
+    };
+    let path = target.split('?').next().unwrap_or(target);
+    let request = HttpRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+    };
+    Ok((stream, Some(request)))
+}
+
+/// Reads until the blank line ending the head and returns the head's length without it; any
+/// bytes read past it stay buffered as well.
+async fn read_head(stream: &mut DebugStream) -> Result<usize> {
+    let mut chunk = [0; 1024];
+    loop {
+        let room = chunk.len().min(HEAD_LIMIT - stream.head.len());
+        let n = stream.inner.read(&mut chunk[..room]).await?;
+        if n == 0 {
+            bail!("connection closed before a request");
+        }
+        // The terminator may straddle two reads
+        let from = stream.head.len().saturating_sub(3);
+        stream.head.extend_from_slice(&chunk[..n]);
+        if let Some(end) = stream.head[from..].windows(4).position(|w| w == b"\r\n\r\n") {
+            return Ok(from + end);
+        }
+        if stream.head.len() == HEAD_LIMIT {
+            bail!("request head larger than {HEAD_LIMIT} bytes");
+        }
+    }
+}
+
+/// A connection on the debug port whose request head was read by `read_request`; reads
+/// return that head first, then continue with the socket.
+pub struct DebugStream {
+    inner: TcpStream,
+    head: Vec<u8>,
+    replayed: usize,
+}
+
+impl AsyncRead for DebugStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        if this.replayed < this.head.len() {
+            let rest = &this.head[this.replayed..];
+            let n = rest.len().min(buf.remaining());
+            buf.put_slice(&rest[..n]);
+            this.replayed += n;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for DebugStream {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Answers `request` with `response` and closes the connection. Only `GET` and `HEAD` are served.
+pub async fn respond(mut stream: DebugStream, request: &HttpRequest, response: HttpResponse) -> Result<()> {
+    let response = match request.method.as_str() {
+        "GET" | "HEAD" => response,
+        _ => HttpResponse::text("text/plain; charset=utf-8", "only GET is supported\n".to_string())
+            .with_status(StatusCode::METHOD_NOT_ALLOWED),
+    };
+    let mut out = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    if request.method != "HEAD" {
+        out.push_str(&response.body);
+    }
+    stream.write_all(out.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::Message;
+
+    /// A connected pair: the client end and the server end as the debug port accepted it.
+    async fn connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (client, server)
+    }
+
+    /// Sends `request` in the given pieces and answers it with a fixed body.
+    async fn exchange(pieces: &[&str]) -> (Result<Option<HttpRequest>>, String) {
+        let (mut client, server) = connection().await;
+        let pieces: Vec<String> = pieces.iter().map(|piece| piece.to_string()).collect();
+        let sender = tokio::spawn(async move {
+            for piece in pieces {
+                client.write_all(piece.as_bytes()).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            client.shutdown().await.unwrap();
+            let mut response = String::new();
+            let _ = client.read_to_string(&mut response).await;
+            response
+        });
+
+        let request = match read_request(server).await {
+            Ok((stream, Some(request))) => {
+                let body = HttpResponse::text("text/plain", "hello\n".to_string());
+                respond(stream, &request, body).await.unwrap();
+                Ok(Some(request))
+            }
+            Ok((_, None)) => Ok(None),
+            Err(err) => Err(err),
+        };
+        (request, sender.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn get_and_head_are_served_other_methods_refused() {
+        let (request, response) = exchange(&["GET /metrics?name=x HTTP/1.1\r\nHost: a\r\n\r\n"]).await;
+        let request = request.unwrap().unwrap();
+        assert_eq!((request.method.as_str(), request.path.as_str()), ("GET", "/metrics"));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("Content-Length: 6\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello\n"));
+
+        let (_, response) = exchange(&["HEAD /config HTTP/1.1\r\n\r\n"]).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Length: 6\r\n"));
+        assert!(response.ends_with("\r\n\r\n"), "HEAD has no body: {response}");
+
+        for method in ["POST", "DELETE"] {
+            let (request, response) = exchange(&[&format!("{method} /config HTTP/1.1\r\n\r\nbody")]).await;
+            assert_eq!(request.unwrap().unwrap().method, method);
+            assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"), "{response}");
+            assert!(response.ends_with("only GET is supported\n"));
+        }
+    }
+
+    #[tokio::test]
+    async fn heads_split_across_reads_are_put_back_together() {
+        // Including a terminator that straddles two reads
+        let (request, _) = exchange(&["GE", "T /hea", "lth HTTP/1.1\r\nHost: a\r", "\n\r", "\n"]).await;
+        assert_eq!(request.unwrap().unwrap().path, "/health");
+    }
+
+    #[tokio::test]
+    async fn incomplete_and_malformed_heads_are_rejected() {
+        let (request, response) = exchange(&["GET /health HTTP/1.1\r\nHost: a\r\n"]).await;
+        assert!(format!("{:#}", request.unwrap_err()).contains("closed before a request"));
+        assert!(response.is_empty());
+
+        let (request, _) = exchange(&["GET\r\n\r\n"]).await;
+        assert!(format!("{:#}", request.unwrap_err()).contains("malformed request line"));
+    }
+
+    #[tokio::test]
+    async fn heads_are_limited_to_8_kib() {
+        let header = |len: usize| format!("X-Pad: {}\r\n", "a".repeat(len));
+        let request_line = "GET /health HTTP/1.1\r\n";
+        // The blank line ends exactly at the limit
+        let fits = format!("{request_line}{}\r\n", header(HEAD_LIMIT - request_line.len() - 11));
+        assert_eq!(fits.len(), HEAD_LIMIT);
+        let (request, _) = exchange(&[&fits]).await;
+        assert_eq!(request.unwrap().unwrap().path, "/health");
+
+        let oversized = format!("{request_line}{}\r\n", header(HEAD_LIMIT));
+        let (request, response) = exchange(&[&oversized]).await;
+        assert!(format!("{:#}", request.unwrap_err()).contains("larger than 8192 bytes"));
+        assert!(response.is_empty());
+    }
+
+    #[tokio::test]
+    async fn upgrades_keep_their_head_for_the_handshake() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let url = format!("ws://{}/?format=raw", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (stream, request) = read_request(stream).await.unwrap();
+            assert!(request.is_none());
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            ws.send(Message::Text("welcome".to_string())).await.unwrap();
+        });
+
+        let (mut client, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        assert_eq!(client.next().await.unwrap().unwrap(), Message::Text("welcome".to_string()));
+        server.await.unwrap();
+    }
+}
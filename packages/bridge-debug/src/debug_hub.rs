@@ -0,0 +1,1529 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::{accept_hdr_async, tungstenite, WebSocketStream};
+
+use crate::capture::{Capture, CaptureConfig, CaptureStatus, Redactor};
+use crate::metrics::Metrics;
+use crate::{unix_ms, Role};
+
+const DEFAULT_HISTORY_DEPTH: usize = 500;
+/// Frames buffered per debug client before newer frames are dropped for it.
+const DEFAULT_PEER_BUFFER: usize = 1024;
+
+/// Which way a mirrored message travelled relative to this process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Received from the other end of a link or from a debug client.
+    In,
+    /// Sent to the other end of a link.
+    Out,
+    /// Produced by the process itself (presence, socket events).
+    Local,
+}
+
+impl Direction {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "in" => Some(Self::In),
+            "out" => Some(Self::Out),
+            "local" => Some(Self::Local),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::In => "in",
+            Self::Out => "out",
+            Self::Local => "local",
+        }
+    }
+}
+
+/// The link a mirrored message was seen on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Hop {
+    #[serde(rename = "extension->sidecar")]
+    ExtensionToSidecar,
+    #[serde(rename = "sidecar->app")]
+    SidecarToApp,
+    #[serde(rename = "app->sidecar")]
+    AppToSidecar,
+    #[serde(rename = "debug->sidecar")]
+    DebugToSidecar,
+    #[serde(rename = "sidecar")]
+    Sidecar,
+    /// App hops, only seen in the app's hub.
+    #[serde(rename = "app->frontend")]
+    AppToFrontend,
+    #[serde(rename = "debug->app")]
+    DebugToApp,
+    #[serde(rename = "app")]
+    App,
+}
+
+impl Hop {
+    /// Accepts hop names as written in frames, with `>` optionally percent-encoded as in a URL.
+    fn parse(value: &str) -> Option<Self> {
+        let name = value.trim().replace("%3E", ">").replace("%3e", ">");
+        serde_json::from_value(json!(name)).ok()
+    }
+
+    /// The hop as written in frames, e.g. `extension->sidecar`.
+    pub fn name(self) -> String {
+        serde_json::to_value(self)
+            .ok()
+            .and_then(|value| value.as_str().map(str::to_string))
+            .unwrap_or_default()
+    }
+
+    /// Which way the hop runs as seen from `role`: `sidecar->app` leaves a sidecar but arrives
+    /// at the app.
+    pub fn direction(self, role: Role) -> Direction {
+        match self {
+            Self::Sidecar | Self::App => Direction::Local,
+            Self::AppToFrontend => Direction::Out,
+            Self::SidecarToApp if role == Role::Sidecar => Direction::Out,
+            Self::AppToSidecar if role == Role::App => Direction::Out,
+            _ => Direction::In,
+        }
+    }
+
+    /// The hop messages from inject-mode debug clients of `role` are mirrored on.
+    fn injected(role: Role) -> Self {
+        match role {
+            Role::App => Self::DebugToApp,
+            Role::Sidecar => Self::DebugToSidecar,
+        }
+    }
+}
+
+/// How mirrored messages are delivered to a debug client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FrameFormat {
+    /// Each message wrapped in a `debug.frame` envelope with hop, timing and size metadata.
+    #[default]
+    Annotated,
+    /// The original message text only, as older tooling expects.
+    Raw,
+}
+
+impl FrameFormat {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "annotated" | "frame" => Some(Self::Annotated),
+            "raw" | "legacy" => Some(Self::Raw),
+            _ => None,
+        }
+    }
+
+    fn from_env(role: Role) -> Self {
+        env::var(debug_env(role, "FORMAT"))
+            .ok()
+            .and_then(|v| Self::parse(&v))
+            .unwrap_or_default()
+    }
+}
+
+/// What a debug client may do besides watching.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClientMode {
+    /// Receives mirrored traffic and may use `debug.*` commands; anything else it sends is refused.
+    #[default]
+    Observe,
+    /// May also send messages into the bridge; requires the bridge secret.
+    Inject,
+}
+
+impl ClientMode {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "observe" | "read-only" | "readonly" => Some(Self::Observe),
+            "inject" => Some(Self::Inject),
+            _ => None,
+        }
+    }
+}
+
+/// Which part of the history a newly connected debug client wants replayed.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HistoryQuery {
+    /// Only replay events recorded at or after this unix timestamp (ms).
+    pub since_ms: Option<u128>,
+    /// Replay at most this many of the most recent events; `0` disables replay.
+    pub limit: Option<usize>,
+}
+
+/// Per-client subscription; empty lists match everything.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DebugFilter {
+    /// Envelope type globs, e.g. `focus.*`.
+    pub types: Vec<String>,
+    pub connection_ids: Vec<String>,
+    pub directions: Vec<Direction>,
+    /// Links, e.g. `extension->sidecar`.
+    pub hops: Vec<Hop>,
+    /// Browser names, compared case-insensitively.
+    pub browsers: Vec<String>,
+}
+
+impl DebugFilter {
+    fn matches(&self, event: &EventMeta) -> bool {
+        (self.types.is_empty()
+            || event
+                .kind
+                .as_deref()
+                .is_some_and(|kind| self.types.iter().any(|pattern| glob_match(pattern, kind))))
+            && (self.connection_ids.is_empty()
+                || event
+                    .connection_id
+                    .as_deref()
+                    .is_some_and(|id| self.connection_ids.iter().any(|c| c == id)))
+            && (self.directions.is_empty() || self.directions.contains(&event.direction))
+            && (self.hops.is_empty() || self.hops.contains(&event.hop))
+            && (self.browsers.is_empty()
+                || event
+                    .browser
+                    .as_deref()
+                    .is_some_and(|b| self.browsers.iter().any(|f| f.eq_ignore_ascii_case(b))))
+    }
+}
+
+/// Options a debug client passes in its connection query string, e.g.
+/// `ws://127.0.0.1:17888/?since=1700000000000&history=50&types=focus.*,presence.*&direction=in&format=raw`.
+#[derive(Debug, Default, Clone)]
+pub struct DebugClientOptions {
+    pub history: HistoryQuery,
+    pub filter: DebugFilter,
+    pub format: FrameFormat,
+    pub mode: ClientMode,
+}
+
+impl DebugClientOptions {
+    /// Parses a client's query string; the format defaults to `role`'s configured one.
+    pub fn from_query(role: Role, query: Option<&str>) -> Self {
+        let mut result = Self {
+            format: FrameFormat::from_env(role),
+            ..Self::default()
+        };
+        for pair in query.unwrap_or_default().split('&') {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let list = || {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|v| !v.is_empty())
+                    .map(str::to_string)
+            };
+            match key {
+                "since" => result.history.since_ms = value.parse().ok(),
+                "history" => result.history.limit = value.parse().ok(),
+                "types" | "type" => result.filter.types.extend(list()),
+                "connectionId" => result.filter.connection_ids.extend(list()),
+                "browser" => result.filter.browsers.extend(list()),
+                "direction" => result
+                    .filter
+                    .directions
+                    .extend(list().filter_map(|d| Direction::parse(&d))),
+                "hop" | "hops" => result.filter.hops.extend(list().filter_map(|h| Hop::parse(&h))),
+                "format" => result.format = FrameFormat::parse(value).unwrap_or(result.format),
+                "mode" => result.mode = ClientMode::parse(value).unwrap_or(result.mode),
+                _ => {}
+            }
+        }
+        result
+    }
+}
+
+/// Handles `debug.subscribe` / `debug.unsubscribe` commands from a debug client.
+///
+/// Returns the acknowledgement to send back to that client, or `None` when `message` is not a
+/// subscription command and should be treated as regular traffic.
+pub fn handle_subscription_command(hub: &DebugHub, peer_id: u64, message: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(message).ok()?;
+    let filter = match value.get("type").and_then(|t| t.as_str())? {
+        "debug.subscribe" => match value.get("payload") {
+            Some(payload) => match serde_json::from_value::<DebugFilter>(payload.clone()) {
+                Ok(filter) => filter,
+                Err(err) => {
+                    return Some(
+                        json!({
+                            "v": 1,
+                            "type": "debug.error",
+                            "payload": { "message": format!("invalid debug.subscribe payload: {err}") }
+                        })
+                        .to_string(),
+                    )
+                }
+            },
+            None => DebugFilter::default(),
+        },
+        "debug.unsubscribe" => DebugFilter::default(),
+        _ => return None,
+    };
+
+    let ack = json!({ "v": 1, "type": "debug.subscribed", "payload": filter }).to_string();
+    hub.set_filter(peer_id, filter);
+    Some(ack)
+}
+
+/// Whether `message` is an annotated `debug.frame` as rendered by a hub.
+pub fn is_debug_frame(message: &str) -> bool {
+    message.starts_with(r#"{"v":1,"type":"debug.frame","#)
+}
+
+/// Reply for an observe-mode client that tried to send a message into the bridge.
+pub fn read_only_notice() -> String {
+    json!({
+        "v": 1,
+        "type": "debug.error",
+        "payload": {
+            "message": "debug client is read-only; reconnect with ?mode=inject and the bridge secret to send messages"
+        }
+    })
+    .to_string()
+}
+
+/// Handles a `debug.capture` control message from any side of the bridge.
+///
+/// Anyone may query the capture, but only a `privileged` sender (the app, or a debug client in
+/// inject mode) may start or stop it. Returns the `debug.capture.status` reply, or `None` when
+/// `message` is something else.
+pub fn handle_capture_command(hub: &DebugHub, message: &str, privileged: bool) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(message).ok()?;
+    if value.get("type").and_then(|t| t.as_str()) != Some("debug.capture") {
+        return None;
+    }
+    let payload = value.get("payload").cloned().unwrap_or_else(|| json!({}));
+    if payload.get("enabled").is_some() && !privileged {
+        let refusal = json!({
+            "v": 1,
+            "type": "debug.error",
+            "payload": {
+                "message": "starting or stopping a capture requires the app or a debug client in inject mode"
+            }
+        });
+        return Some(refusal.to_string());
+    }
+    let reply = match hub.apply_capture_command(payload) {
+        Ok(status) => json!({ "v": 1, "type": "debug.capture.status", "payload": status }),
+        Err(err) => json!({
+            "v": 1,
+            "type": "debug.error",
+            "payload": { "message": format!("{err:#}") }
+        }),
+    };
+    Some(reply.to_string())
+}
+
+/// Matches `text` against a glob where `*` spans any run of characters and `?` one character.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[derive(Deserialize)]
+struct EnvelopeHead {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    payload: Option<PayloadHead>,
+}
+
+#[derive(Deserialize)]
+struct PayloadHead {
+    #[serde(rename = "connectionId")]
+    connection_id: Option<String>,
+    browser: Option<String>,
+}
+
+/// What the hub knows about a mirrored message, used for filtering and annotation.
+struct EventMeta {
+    hop: Hop,
+    direction: Direction,
+    /// Debug client that injected the message, if any.
+    injected_by: Option<u64>,
+    /// Sequence number in the sidecar hub that forwarded this event.
+    sidecar_seq: Option<u64>,
+    kind: Option<String>,
+    connection_id: Option<String>,
+    browser: Option<String>,
+}
+
+struct Event {
+    seq: u64,
+    timestamp_ms: u128,
+    monotonic_us: u64,
+    meta: EventMeta,
+    /// Whether `message` parsed as JSON and can be embedded verbatim.
+    is_json: bool,
+    message: Arc<str>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FrameHeader<'a> {
+    source: &'static str,
+    seq: u64,
+    direction: Direction,
+    hop: Hop,
+    connection_id: Option<&'a str>,
+    browser: Option<&'a str>,
+    #[serde(rename = "type")]
+    kind: Option<&'a str>,
+    timestamp: u128,
+    monotonic_us: u64,
+    bytes: usize,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    injected: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    debug_client: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sidecar_seq: Option<u64>,
+}
+
+impl Event {
+    fn render(&self, format: FrameFormat, role: Role) -> Arc<str> {
+        match format {
+            FrameFormat::Raw => self.message.clone(),
+            FrameFormat::Annotated => self.annotated(role).into(),
+        }
+    }
+
+    /// Wraps the message in a `debug.frame` envelope: JSON messages go under `payload.message`,
+    /// anything else as a string under `payload.text`.
+    fn annotated(&self, role: Role) -> String {
+        let header = FrameHeader {
+            source: if self.meta.sidecar_seq.is_some() { Role::Sidecar.name() } else { role.name() },
+            seq: self.seq,
+            direction: self.meta.direction,
+            hop: self.meta.hop,
+            connection_id: self.meta.connection_id.as_deref(),
+            browser: self.meta.browser.as_deref(),
+            kind: self.meta.kind.as_deref(),
+            timestamp: self.timestamp_ms,
+            monotonic_us: self.monotonic_us,
+            bytes: self.message.len(),
+            injected: self.meta.injected_by.is_some(),
+            debug_client: self.meta.injected_by,
+            sidecar_seq: self.meta.sidecar_seq,
+        };
+        let mut payload = json!(header);
+        let message = self
+            .is_json
+            .then(|| serde_json::from_str::<Value>(&self.message).ok())
+            .flatten();
+        match message {
+            Some(message) => payload["message"] = message,
+            None => payload["text"] = json!(&*self.message),
+        }
+        json!({ "v": 1, "type": "debug.frame", "payload": payload }).to_string()
+    }
+}
+
+struct Peer {
+    id: u64,
+    tx: mpsc::Sender<Arc<str>>,
+    filter: DebugFilter,
+    format: FrameFormat,
+    /// Frames dropped since the client last had room in its buffer.
+    lagged: u64,
+}
+
+impl Peer {
+    /// Queues `frame` without blocking the hub; returns `false` once the client is gone.
+    ///
+    /// A full buffer drops the frame and counts it, and the count is reported with a
+    /// `debug.lag` notice as soon as the client catches up.
+    fn deliver(&mut self, frame: Arc<str>, role: Role) -> bool {
+        if self.lagged > 0 {
+            let notice = json!({
+                "v": 1,
+                "type": "debug.lag",
+                "payload": { "dropped": self.lagged }
+            })
+            .to_string();
+            match self.tx.try_send(notice.into()) {
+                Ok(()) => self.lagged = 0,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    self.lagged += 1;
+                    return true;
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => return false,
+            }
+        }
+
+        match self.tx.try_send(frame) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                if self.lagged == 0 {
+                    eprintln!("[{}] debug client {} is lagging, dropping frames", role.name(), self.id);
+                }
+                self.lagged += 1;
+                true
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+struct HubState {
+    peers: Vec<Peer>,
+    next_peer_id: u64,
+    next_seq: u64,
+    history: VecDeque<Event>,
+    depth: usize,
+    peer_buffer: usize,
+    capture: Option<Capture>,
+    redactors: Vec<Arc<dyn Redactor>>,
+    hops: BTreeMap<Hop, HopCounters>,
+}
+
+/// Messages and bytes mirrored on one hop since the hub started.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct HopCounters {
+    pub messages: u64,
+    pub bytes: u64,
+}
+
+/// A snapshot of the hub for the debug port's HTTP endpoints.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HubStats {
+    pub clients: usize,
+    pub events: u64,
+    pub history: usize,
+    pub history_depth: usize,
+    pub peer_buffer: usize,
+    pub hops: BTreeMap<Hop, HopCounters>,
+    pub capture: CaptureStatus,
+}
+
+impl HubStats {
+    /// Appends the hub's counters in the Prometheus text format.
+    pub fn write_prometheus(&self, out: &mut String) {
+        use std::fmt::Write as _;
+
+        let _ = writeln!(out, "# HELP bridge_debug_clients Debug clients currently subscribed.");
+        let _ = writeln!(out, "# TYPE bridge_debug_clients gauge");
+        let _ = writeln!(out, "bridge_debug_clients {}", self.clients);
+        // Per-hop totals are the `hop` label of `bridge_messages_total`, see `Metrics`
+        let _ = writeln!(out, "# HELP bridge_capture_frames_total Frames written by the running capture.");
+        let _ = writeln!(out, "# TYPE bridge_capture_frames_total counter");
+        let _ = writeln!(out, "bridge_capture_frames_total {}", self.capture.frames);
+        let _ = writeln!(out, "# HELP bridge_capture_dropped_total Frames the running capture could not keep up with.");
+        let _ = writeln!(out, "# TYPE bridge_capture_dropped_total counter");
+        let _ = writeln!(out, "bridge_capture_dropped_total {}", self.capture.dropped);
+    }
+}
+
+/// A registered debug client.
+pub struct DebugSubscription {
+    pub id: u64,
+    pub rx: mpsc::Receiver<Arc<str>>,
+}
+
+/// The connection and browser a sidecar hub tags its events with.
+#[derive(Clone)]
+struct Identity {
+    connection_id: Arc<str>,
+    browser: Arc<str>,
+}
+
+#[derive(Clone)]
+pub struct DebugHub {
+    role: Role,
+    state: Arc<Mutex<HubState>>,
+    started: Instant,
+    metrics: Metrics,
+    identity: Option<Identity>,
+}
+
+/// The hub setting `name` for `role`: sidecars read `DEBUG_WS_*`, the app `BRIDGE_DEBUG_*`.
+fn debug_env(role: Role, name: &str) -> String {
+    match role {
+        Role::App => format!("BRIDGE_DEBUG_{name}"),
+        Role::Sidecar => format!("DEBUG_WS_{name}"),
+    }
+}
+
+impl DebugHub {
+    pub fn new(role: Role) -> Self {
+        let depth = env::var(debug_env(role, "HISTORY"))
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_HISTORY_DEPTH);
+        let peer_buffer = env::var(debug_env(role, "BUFFER"))
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|size| *size > 0)
+            .unwrap_or(DEFAULT_PEER_BUFFER);
+        Self {
+            role,
+            state: Arc::new(Mutex::new(HubState {
+                peers: Vec::new(),
+                next_peer_id: 1,
+                next_seq: 1,
+                history: VecDeque::with_capacity(depth),
+                depth,
+                peer_buffer,
+                capture: None,
+                redactors: Vec::new(),
+                hops: BTreeMap::new(),
+            })),
+            started: Instant::now(),
+            metrics: Metrics::default(),
+            identity: None,
+        }
+    }
+
+    /// Tags every event with this sidecar's `connection_id` and `browser` unless the envelope
+    /// names a different connection.
+    pub fn with_identity(mut self, connection_id: &str, browser: &str) -> Self {
+        self.identity = Some(Identity {
+            connection_id: connection_id.into(),
+            browser: browser.into(),
+        });
+        self
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Bridge-wide counters; every message the hub mirrors is counted here.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Mirrors `message`, taking connection and browser from the envelope payload if present.
+    pub fn broadcast(&self, hop: Hop, message: &str) {
+        self.publish(hop, None, None, None, message);
+    }
+
+    /// Mirrors `message` for a known sidecar connection; the envelope payload wins when it names
+    /// a connection itself.
+    pub fn broadcast_from(&self, hop: Hop, connection_id: Option<&str>, browser: Option<&str>, message: &str) {
+        self.publish(hop, connection_id, browser, None, message);
+    }
+
+    /// Mirrors a message an inject-mode debug client sent into the bridge, tagged with its id.
+    pub fn broadcast_injected(&self, peer_id: u64, message: &str) {
+        self.publish(Hop::injected(self.role), None, None, Some(peer_id), message);
+    }
+
+    fn publish(
+        &self,
+        hop: Hop,
+        connection_id: Option<&str>,
+        browser: Option<&str>,
+        injected_by: Option<u64>,
+        message: &str,
+    ) {
+        let head = serde_json::from_str::<EnvelopeHead>(message);
+        let is_json = head.is_ok();
+        let (kind, payload) = match head {
+            Ok(head) => (head.kind, head.payload),
+            Err(_) => (None, None),
+        };
+        let (payload_connection_id, payload_browser) = match payload {
+            Some(payload) => (payload.connection_id, payload.browser),
+            None => (None, None),
+        };
+        let identity = self.identity.as_ref();
+        let meta = EventMeta {
+            hop,
+            direction: hop.direction(self.role),
+            injected_by,
+            sidecar_seq: None,
+            kind,
+            connection_id: payload_connection_id
+                .or_else(|| connection_id.map(str::to_string))
+                .or_else(|| identity.map(|identity| identity.connection_id.to_string())),
+            browser: payload_browser
+                .or_else(|| browser.map(str::to_string))
+                .or_else(|| identity.map(|identity| identity.browser.to_string())),
+        };
+        self.metrics
+            .record_message(meta.hop, meta.direction.name(), meta.kind.as_deref(), message.len());
+        self.push(meta, is_json, message);
+    }
+
+    /// Merges a `debug.frame` a sidecar forwarded over its app link into this hub's stream.
+    ///
+    /// The frame gets a sequence number here like any other event; its sidecar one is kept as
+    /// `sidecarSeq`, and raw-format clients receive the original message.
+    pub fn merge_forwarded(&self, frame: &str) {
+        let Ok(mut frame) = serde_json::from_str::<Value>(frame) else {
+            return;
+        };
+        let Some(payload) = frame.get_mut("payload") else {
+            return;
+        };
+        let message = payload.get_mut("message").map(Value::take);
+        let Some(hop) = payload.get("hop").and_then(|h| Hop::parse(h.as_str()?)) else {
+            return;
+        };
+        let field = |name: &str| payload.get(name).and_then(|v| v.as_str()).map(str::to_string);
+        let meta = EventMeta {
+            hop,
+            // As the sidecar that saw it
+            direction: hop.direction(Role::Sidecar),
+            injected_by: payload.get("debugClient").and_then(|c| c.as_u64()),
+            sidecar_seq: payload.get("seq").and_then(|s| s.as_u64()),
+            kind: field("type"),
+            connection_id: field("connectionId"),
+            browser: field("browser"),
+        };
+        match (message, payload.get("text").and_then(|t| t.as_str())) {
+            (Some(message), _) => self.push(meta, true, &message.to_string()),
+            (None, Some(text)) => self.push(meta, false, text),
+            (None, None) => {}
+        }
+    }
+
+    /// Sequences an event, fans it out to matching peers and records it.
+    fn push(&self, meta: EventMeta, is_json: bool, message: &str) {
+        let mut state = self.state.lock().unwrap();
+        let event = Event {
+            seq: state.next_seq,
+            timestamp_ms: unix_ms(),
+            monotonic_us: self.started.elapsed().as_micros() as u64,
+            meta,
+            is_json,
+            message: message.into(),
+        };
+        state.next_seq += 1;
+        let counters = state.hops.entry(event.meta.hop).or_default();
+        counters.messages += 1;
+        counters.bytes += event.message.len() as u64;
+
+        // Render each format at most once per event, and only if some peer wants it
+        let role = self.role;
+        let mut annotated = None;
+        state.peers.retain_mut(|peer| {
+            if !peer.filter.matches(&event.meta) {
+                return true;
+            }
+            let frame = match peer.format {
+                FrameFormat::Raw => event.message.clone(),
+                FrameFormat::Annotated => annotated
+                    .get_or_insert_with(|| Arc::<str>::from(event.annotated(role)))
+                    .clone(),
+            };
+            peer.deliver(frame, role)
+        });
+
+        if let Some(capture) = &state.capture {
+            capture.record(
+                annotated
+                    .get_or_insert_with(|| Arc::<str>::from(event.annotated(role)))
+                    .clone(),
+            );
+        }
+
+        if state.depth > 0 {
+            if state.history.len() == state.depth {
+                state.history.pop_front();
+            }
+            state.history.push_back(event);
+        }
+    }
+
+    /// Registers a debug client, first queueing the recorded history selected by `options`.
+    ///
+    /// Replay and registration happen under one lock so no event is missed or duplicated.
+    pub fn register(&self, options: &DebugClientOptions) -> DebugSubscription {
+        let mut state = self.state.lock().unwrap();
+
+        let history = options.history;
+        let matching: Vec<&Event> = state
+            .history
+            .iter()
+            .filter(|event| history.since_ms.map_or(true, |since| event.timestamp_ms >= since))
+            .filter(|event| options.filter.matches(&event.meta))
+            .collect();
+        let skip = history
+            .limit
+            .map_or(0, |limit| matching.len().saturating_sub(limit));
+        let replay: Vec<Arc<str>> = matching
+            .into_iter()
+            .skip(skip)
+            .map(|event| event.render(options.format, self.role))
+            .collect();
+
+        // Leave room for the whole replay on top of the live buffer
+        let (tx, rx) = mpsc::channel(state.peer_buffer + replay.len());
+        for frame in replay {
+            let _ = tx.try_send(frame);
+        }
+
+        let id = state.next_peer_id;
+        state.next_peer_id += 1;
+        state.peers.push(Peer {
+            id,
+            tx,
+            filter: options.filter.clone(),
+            format: options.format,
+            lagged: 0,
+        });
+        DebugSubscription { id, rx }
+    }
+
+    /// Adds a hook that rewrites every captured frame before it reaches disk.
+    pub fn with_redactor(self, redactor: Arc<dyn Redactor>) -> Self {
+        self.state.lock().unwrap().redactors.push(redactor);
+        self
+    }
+
+    /// Starts writing every broadcast event to JSONL files, replacing any running capture.
+    pub fn start_capture(&self, config: CaptureConfig) -> Result<CaptureStatus> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(previous) = state.capture.take() {
+            previous.stop();
+        }
+        let label = match &self.identity {
+            Some(identity) => format!("{}-{}", self.role.name(), identity.connection_id),
+            None => self.role.name().to_string(),
+        };
+        let capture = Capture::start(self.role, config, &label, state.redactors.clone())?;
+        let status = capture.status();
+        state.capture = Some(capture);
+        Ok(status)
+    }
+
+    /// Applies a `debug.capture` payload: `{"enabled": true, ...}` starts (or restarts) capturing
+    /// with the given `CaptureConfig` overrides (never the directory), `{"enabled": false}` stops it and anything else
+    /// only reports the current status.
+    pub fn apply_capture_command(&self, payload: serde_json::Value) -> Result<CaptureStatus> {
+        match payload.get("enabled").and_then(|e| e.as_bool()) {
+            Some(true) => {
+                let config = serde_json::from_value::<CaptureConfig>(payload)
+                    .map_err(|err| anyhow!("invalid debug.capture payload: {err}"))?;
+                self.start_capture(config)
+            }
+            Some(false) => Ok(self.stop_capture()),
+            None => Ok(self.capture_status()),
+        }
+    }
+
+    pub fn stop_capture(&self) -> CaptureStatus {
+        let capture = self.state.lock().unwrap().capture.take();
+        capture.map(Capture::stop).unwrap_or_default()
+    }
+
+    pub fn stats(&self) -> HubStats {
+        let state = self.state.lock().unwrap();
+        HubStats {
+            clients: state.peers.len(),
+            events: state.next_seq - 1,
+            history: state.history.len(),
+            history_depth: state.depth,
+            peer_buffer: state.peer_buffer,
+            hops: state.hops.clone(),
+            capture: state.capture.as_ref().map(Capture::status).unwrap_or_default(),
+        }
+    }
+
+    pub fn capture_status(&self) -> CaptureStatus {
+        let state = self.state.lock().unwrap();
+        state.capture.as_ref().map(Capture::status).unwrap_or_default()
+    }
+
+    pub fn set_filter(&self, peer_id: u64, filter: DebugFilter) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(peer) = state.peers.iter_mut().find(|peer| peer.id == peer_id) {
+            peer.filter = filter;
+        }
+    }
+}
+
+/// Completes the WebSocket handshake for a debug client and parses its query string.
+///
+/// `stream` is usually the `DebugStream` returned by `debug_http::read_request`, which replays
+/// the request head it already read.
+///
+/// Clients asking for `mode=inject` must present `secret` in the `secret` query parameter or an
+/// `X-Bridge-Secret` header, otherwise the handshake is refused with `403`. Without a configured
+/// secret, injection is disabled.
+#[allow(clippy::result_large_err)]
+pub async fn accept_debug_client<S>(
+    role: Role,
+    stream: S,
+    secret: Option<&str>,
+) -> Result<(WebSocketStream<S>, DebugClientOptions)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut options = DebugClientOptions::default();
+    let ws = accept_hdr_async(stream, |req: &Request, resp: Response| {
+        let query = req.uri().query();
+        options = DebugClientOptions::from_query(role, query);
+        if options.mode == ClientMode::Inject {
+            let presented = req
+                .headers()
+                .get("x-bridge-secret")
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+                .or_else(|| query_param(query, "secret"));
+            if !secret_matches(secret, presented.as_deref()) {
+                let mut rejection = ErrorResponse::new(Some(
+                    "inject mode requires the bridge secret".to_string(),
+                ));
+                *rejection.status_mut() = StatusCode::FORBIDDEN;
+                return Err(rejection);
+            }
+        }
+        Ok(resp)
+    })
+    .await?;
+    Ok((ws, options))
+}
+
+/// Whether `accept_debug_client` failed because the client was refused, as opposed to
+/// hanging up before completing the handshake.
+pub fn is_refused_handshake(err: &anyhow::Error) -> bool {
+    matches!(err.downcast_ref::<tungstenite::Error>(), Some(tungstenite::Error::Http(_)))
+}
+
+fn query_param(query: Option<&str>, name: &str) -> Option<String> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+}
+
+/// Compares without short-circuiting so the check does not leak how much of a guess was right.
+fn secret_matches(expected: Option<&str>, presented: Option<&str>) -> bool {
+    let (Some(expected), Some(presented)) = (expected, presented) else {
+        return false;
+    };
+    if expected.is_empty() || expected.len() != presented.len() {
+        return false;
+    }
+    expected
+        .bytes()
+        .zip(presented.bytes())
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw_client() -> DebugClientOptions {
+        DebugClientOptions {
+            format: FrameFormat::Raw,
+            ..DebugClientOptions::default()
+        }
+    }
+
+    fn message(n: usize) -> String {
+        format!(r#"{{"v":1,"type":"test.tick","payload":{{"n":{n}}}}}"#)
+    }
+
+    #[test]
+    fn a_stalled_peer_lags_without_holding_back_the_hub_or_other_peers() {
+        let hub = DebugHub::new(Role::Sidecar);
+        let buffer = hub.stats().peer_buffer;
+        let mut stalled = hub.register(&raw_client());
+        let mut active = hub.register(&raw_client());
+
+        let total = buffer * 2 + 10;
+        for n in 0..total {
+            hub.broadcast(Hop::ExtensionToSidecar, &message(n));
+            let frame = active.rx.try_recv().expect("active peer gets every frame");
+            assert_eq!(&*frame, message(n));
+        }
+        assert_eq!(hub.stats().events, total as u64);
+        assert_eq!(hub.stats().clients, 2);
+
+        // The stalled peer kept what fit in its buffer and lost the rest
+        for n in 0..buffer {
+            assert_eq!(&*stalled.rx.try_recv().unwrap(), message(n));
+        }
+        assert!(stalled.rx.try_recv().is_err());
+
+        // Once it has room again it learns how much it missed, then resumes
+        hub.broadcast(Hop::ExtensionToSidecar, &message(total));
+        let notice: Value = serde_json::from_str(&stalled.rx.try_recv().unwrap()).unwrap();
+        assert_eq!(notice["type"], "debug.lag");
+        assert_eq!(notice["payload"]["dropped"], (total - buffer) as u64);
+        assert_eq!(&*stalled.rx.try_recv().unwrap(), message(total));
+        assert_eq!(&*active.rx.try_recv().unwrap(), message(total));
+    }
+
+    #[test]
+    fn a_disconnected_peer_is_dropped_on_the_next_broadcast() {
+        let hub = DebugHub::new(Role::App);
+        let gone = hub.register(&raw_client());
+        let mut active = hub.register(&raw_client());
+        drop(gone.rx);
+
+        hub.broadcast(Hop::App, &message(0));
+        assert_eq!(hub.stats().clients, 1);
+        assert_eq!(&*active.rx.try_recv().unwrap(), message(0));
+    }
+
+    #[test]
+    fn only_privileged_senders_start_or_stop_a_capture() {
+        let hub = DebugHub::new(Role::Sidecar);
+        for payload in [json!({ "enabled": true }), json!({ "enabled": false })] {
+            let command = json!({ "v": 1, "type": "debug.capture", "payload": payload }).to_string();
+            let reply: Value = serde_json::from_str(&handle_capture_command(&hub, &command, false).unwrap()).unwrap();
+            assert_eq!(reply["type"], "debug.error");
+        }
+        assert!(!hub.capture_status().enabled);
+
+        let query = r#"{"v":1,"type":"debug.capture"}"#;
+        let reply: Value = serde_json::from_str(&handle_capture_command(&hub, query, false).unwrap()).unwrap();
+        assert_eq!(reply["type"], "debug.capture.status");
+        assert_eq!(reply["payload"]["enabled"], false);
+        assert_eq!(handle_capture_command(&hub, r#"{"v":1,"type":"tabs.list"}"#, true), None);
+    }
+
+    #[test]
+    fn capture_payloads_cannot_choose_the_directory() {
+        let config: CaptureConfig =
+            serde_json::from_value(json!({ "dir": "/etc", "maxFiles": 2 })).unwrap();
+        assert_eq!(config.dir, CaptureConfig::default().dir);
+        assert_eq!(config.max_files, 2);
+    }
+
+    fn replayed(subscription: &mut DebugSubscription) -> Vec<String> {
+        std::iter::from_fn(|| subscription.rx.try_recv().ok())
+            .map(|frame| frame.to_string())
+            .collect()
+    }
+
+    /// A hub holding `message(0..count)`, recorded 10 ms apart from `unix_ms == 1000`.
+    fn hub_with_history(count: usize) -> DebugHub {
+        let hub = DebugHub::new(Role::Sidecar);
+        for n in 0..count {
+            hub.broadcast(Hop::ExtensionToSidecar, &message(n));
+        }
+        for (n, event) in hub.state.lock().unwrap().history.iter_mut().enumerate() {
+            event.timestamp_ms = 1000 + n as u128 * 10;
+        }
+        hub
+    }
+
+    #[test]
+    fn history_query_selects_what_a_late_joiner_gets_replayed() {
+        let hub = hub_with_history(5);
+        let cases: &[(Option<u128>, Option<usize>, &[usize])] = &[
+            (None, None, &[0, 1, 2, 3, 4]),
+            (Some(1020), None, &[2, 3, 4]),
+            (None, Some(2), &[3, 4]),
+            (Some(1010), Some(10), &[1, 2, 3, 4]),
+            (Some(1010), Some(2), &[3, 4]),
+            (None, Some(0), &[]),
+            (Some(5000), None, &[]),
+        ];
+        for (since_ms, limit, expected) in cases {
+            let options = DebugClientOptions {
+                history: HistoryQuery {
+                    since_ms: *since_ms,
+                    limit: *limit,
+                },
+                ..raw_client()
+            };
+            let mut late = hub.register(&options);
+            let expected: Vec<String> = expected.iter().map(|n| message(*n)).collect();
+            assert_eq!(replayed(&mut late), expected, "since {since_ms:?}, limit {limit:?}");
+        }
+    }
+
+    #[test]
+    fn a_late_joiner_gets_live_events_after_its_replay() {
+        let hub = hub_with_history(2);
+        let mut late = hub.register(&raw_client());
+        hub.broadcast(Hop::ExtensionToSidecar, &message(2));
+        assert_eq!(replayed(&mut late), vec![message(0), message(1), message(2)]);
+    }
+
+    #[test]
+    fn history_keeps_only_the_most_recent_events() {
+        let hub = DebugHub::new(Role::App);
+        hub.state.lock().unwrap().depth = 3;
+        for n in 0..5 {
+            hub.broadcast(Hop::App, &message(n));
+        }
+        let mut late = hub.register(&raw_client());
+        assert_eq!(replayed(&mut late), vec![message(2), message(3), message(4)]);
+
+        // A depth of 0 turns recording off
+        hub.state.lock().unwrap().depth = 0;
+        hub.broadcast(Hop::App, &message(5));
+        assert_eq!(hub.stats().history, 3);
+    }
+
+    #[test]
+    fn history_parameters_are_read_from_the_query_string() {
+        let options = DebugClientOptions::from_query(Role::Sidecar, Some("since=1700000000000&history=50"));
+        assert_eq!(options.history.since_ms, Some(1_700_000_000_000));
+        assert_eq!(options.history.limit, Some(50));
+
+        let options = DebugClientOptions::from_query(Role::Sidecar, Some("since=yesterday&history=-1"));
+        assert_eq!(options.history.since_ms, None);
+        assert_eq!(options.history.limit, None);
+        assert!(DebugClientOptions::from_query(Role::App, None).history.limit.is_none());
+    }
+
+    #[test]
+    fn glob_match_handles_stars_and_question_marks() {
+        let cases = [
+            ("focus.*", "focus.window", true),
+            ("focus.*", "focus.", true),
+            ("focus.*", "presence.status", false),
+            ("*.status", "presence.status", true),
+            ("*", "", true),
+            ("", "", true),
+            ("", "tabs.list", false),
+            ("tabs.?ist", "tabs.list", true),
+            ("tabs.?ist", "tabs.ist", false),
+            ("*.*.*", "debug.capture.status", true),
+            ("*a*b", "xaxxab", true),
+            ("*a*b", "xaxxa", false),
+            ("Focus.*", "focus.window", false),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(glob_match(pattern, text), expected, "{pattern:?} against {text:?}");
+        }
+    }
+
+    fn meta(kind: Option<&str>, hop: Hop, connection_id: Option<&str>, browser: Option<&str>) -> EventMeta {
+        EventMeta {
+            hop,
+            direction: hop.direction(Role::Sidecar),
+            injected_by: None,
+            sidecar_seq: None,
+            kind: kind.map(str::to_string),
+            connection_id: connection_id.map(str::to_string),
+            browser: browser.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn filters_match_every_listed_field() {
+        let focus_in = meta(Some("focus.window"), Hop::ExtensionToSidecar, Some("c1"), Some("chrome"));
+        let presence_out = meta(Some("presence.status"), Hop::SidecarToApp, Some("c2"), Some("firefox"));
+        let untyped = meta(None, Hop::Sidecar, None, None);
+
+        let filter = |query: &str| DebugClientOptions::from_query(Role::Sidecar, Some(query)).filter;
+        let cases = [
+            ("", [true, true, true]),
+            ("types=focus.*", [true, false, false]),
+            ("types=focus.*,presence.*", [true, true, false]),
+            ("direction=in", [true, false, false]),
+            ("direction=out,local", [false, true, true]),
+            ("connectionId=c2", [false, true, false]),
+            ("browser=CHROME", [true, false, false]),
+            ("hop=sidecar-%3Eapp", [false, true, false]),
+            ("types=focus.*&connectionId=c2", [false, false, false]),
+            ("types=*&direction=in,out", [true, true, false]),
+        ];
+        for (query, expected) in cases {
+            let filter = filter(query);
+            let matched = [&focus_in, &presence_out, &untyped].map(|event| filter.matches(event));
+            assert_eq!(matched, expected, "filter {query:?}");
+        }
+    }
+
+    #[test]
+    fn replay_and_live_traffic_both_honour_the_filter() {
+        let hub = DebugHub::new(Role::App);
+        let focus = r#"{"v":1,"type":"focus.window","payload":{"connectionId":"c1"}}"#;
+        let presence = r#"{"v":1,"type":"presence.status","payload":{"connectionId":"c2"}}"#;
+        hub.broadcast(Hop::SidecarToApp, focus);
+        hub.broadcast(Hop::SidecarToApp, presence);
+
+        let options = DebugClientOptions {
+            format: FrameFormat::Raw,
+            ..DebugClientOptions::from_query(Role::App, Some("types=presence.*"))
+        };
+        let mut client = hub.register(&options);
+        hub.broadcast(Hop::AppToSidecar, focus);
+        hub.broadcast(Hop::AppToSidecar, presence);
+        assert_eq!(replayed(&mut client), vec![presence.to_string(), presence.to_string()]);
+    }
+
+    #[test]
+    fn subscription_commands_replace_or_clear_the_filter() {
+        let hub = DebugHub::new(Role::Sidecar);
+        let mut client = hub.register(&raw_client());
+        let focus = r#"{"v":1,"type":"focus.window","payload":{}}"#;
+        let tabs = r#"{"v":1,"type":"tabs.list","payload":{}}"#;
+
+        let subscribe = r#"{"v":1,"type":"debug.subscribe","payload":{"types":["focus.*"],"directions":["in"]}}"#;
+        let ack: Value = serde_json::from_str(&handle_subscription_command(&hub, client.id, subscribe).unwrap()).unwrap();
+        assert_eq!(ack["type"], "debug.subscribed");
+        assert_eq!(ack["payload"]["types"], json!(["focus.*"]));
+        assert_eq!(ack["payload"]["directions"], json!(["in"]));
+        hub.broadcast(Hop::ExtensionToSidecar, focus);
+        hub.broadcast(Hop::ExtensionToSidecar, tabs);
+        hub.broadcast(Hop::SidecarToApp, focus);
+        assert_eq!(replayed(&mut client), vec![focus.to_string()]);
+
+        let unsubscribe = r#"{"v":1,"type":"debug.unsubscribe"}"#;
+        let ack: Value = serde_json::from_str(&handle_subscription_command(&hub, client.id, unsubscribe).unwrap()).unwrap();
+        assert_eq!(ack["payload"]["types"], json!([]));
+        hub.broadcast(Hop::SidecarToApp, tabs);
+        assert_eq!(replayed(&mut client), vec![tabs.to_string()]);
+
+        // A bad payload is reported and leaves the filter alone
+        let invalid = r#"{"v":1,"type":"debug.subscribe","payload":{"directions":["sideways"]}}"#;
+        let error: Value = serde_json::from_str(&handle_subscription_command(&hub, client.id, invalid).unwrap()).unwrap();
+        assert_eq!(error["type"], "debug.error");
+        hub.broadcast(Hop::SidecarToApp, tabs);
+        assert_eq!(replayed(&mut client), vec![tabs.to_string()]);
+
+        assert_eq!(handle_subscription_command(&hub, client.id, tabs), None);
+        assert_eq!(handle_subscription_command(&hub, client.id, "not json"), None);
+    }
+
+    fn annotated_client() -> DebugClientOptions {
+        DebugClientOptions {
+            format: FrameFormat::Annotated,
+            ..DebugClientOptions::default()
+        }
+    }
+
+    #[test]
+    fn json_messages_are_embedded_in_a_debug_frame() {
+        let hub = DebugHub::new(Role::Sidecar).with_identity("c1", "chrome");
+        let mut annotated = hub.register(&annotated_client());
+        let mut raw = hub.register(&raw_client());
+        let original = r#"{"v":1,"type":"tabs.list","payload":{"windowId":7}}"#;
+        hub.broadcast(Hop::ExtensionToSidecar, original);
+
+        assert_eq!(replayed(&mut raw), vec![original.to_string()]);
+        let frame = replayed(&mut annotated).remove(0);
+        assert!(is_debug_frame(&frame), "{frame}");
+        let frame: Value = serde_json::from_str(&frame).unwrap();
+        let keys: Vec<&str> = frame.as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(keys, ["v", "type", "payload"]);
+
+        let payload = &frame["payload"];
+        assert_eq!(payload["message"], serde_json::from_str::<Value>(original).unwrap());
+        assert!(payload.get("text").is_none());
+        assert_eq!(payload.as_object().unwrap().keys().next_back().unwrap(), "message");
+        assert_eq!(payload["source"], "sidecar");
+        assert_eq!(payload["seq"], 1);
+        assert_eq!(payload["direction"], "in");
+        assert_eq!(payload["hop"], "extension->sidecar");
+        assert_eq!(payload["connectionId"], "c1");
+        assert_eq!(payload["browser"], "chrome");
+        assert_eq!(payload["type"], "tabs.list");
+        assert_eq!(payload["bytes"], original.len());
+        assert!(payload["timestamp"].as_u64().unwrap() > 0);
+        assert!(payload["monotonicUs"].is_u64());
+        for absent in ["injected", "debugClient", "sidecarSeq"] {
+            assert!(payload.get(absent).is_none(), "{absent} in {payload}");
+        }
+    }
+
+    #[test]
+    fn other_messages_are_carried_as_text() {
+        let hub = DebugHub::new(Role::App);
+        let mut annotated = hub.register(&annotated_client());
+        let mut raw = hub.register(&raw_client());
+        let original = "not \"json\" {";
+        hub.broadcast(Hop::App, original);
+
+        assert_eq!(replayed(&mut raw), vec![original.to_string()]);
+        let frame: Value = serde_json::from_str(&replayed(&mut annotated).remove(0)).unwrap();
+        assert_eq!(frame["type"], "debug.frame");
+        assert_eq!(frame["payload"]["text"], original);
+        assert!(frame["payload"].get("message").is_none());
+        assert_eq!(frame["payload"]["source"], "app");
+        assert_eq!(frame["payload"]["direction"], "local");
+        assert_eq!(frame["payload"]["type"], Value::Null);
+        assert_eq!(frame["payload"]["connectionId"], Value::Null);
+    }
+
+    #[test]
+    fn injected_messages_name_the_debug_client() {
+        let hub = DebugHub::new(Role::App);
+        let mut annotated = hub.register(&annotated_client());
+        hub.broadcast_injected(41, r#"{"v":1,"type":"focus.window","payload":{"connectionId":"c9"}}"#);
+
+        let frame: Value = serde_json::from_str(&replayed(&mut annotated).remove(0)).unwrap();
+        assert_eq!(frame["payload"]["hop"], "debug->app");
+        assert_eq!(frame["payload"]["direction"], "in");
+        assert_eq!(frame["payload"]["connectionId"], "c9");
+        assert_eq!(frame["payload"]["injected"], true);
+        assert_eq!(frame["payload"]["debugClient"], 41);
+    }
+
+    #[test]
+    fn format_is_read_from_the_query_string() {
+        let format = |query| DebugClientOptions::from_query(Role::Sidecar, Some(query)).format;
+        assert_eq!(format("format=raw"), FrameFormat::Raw);
+        assert_eq!(format("format=legacy"), FrameFormat::Raw);
+        assert_eq!(format("format=frame"), FrameFormat::Annotated);
+        assert_eq!(format("format=ANNOTATED"), FrameFormat::Annotated);
+    }
+
+    #[test]
+    fn secrets_must_match_exactly_and_be_configured() {
+        let cases = [
+            (Some("s3cret"), Some("s3cret"), true),
+            (Some("s3cret"), Some("s3creT"), false),
+            (Some("s3cret"), Some("s3cret "), false),
+            (Some("s3cret"), Some(""), false),
+            (Some("s3cret"), None, false),
+            (Some(""), Some(""), false),
+            (None, Some("s3cret"), false),
+            (None, None, false),
+        ];
+        for (expected, presented, matches) in cases {
+            assert_eq!(secret_matches(expected, presented), matches, "{expected:?} vs {presented:?}");
+        }
+    }
+
+    /// Connects to a fresh debug socket guarded by `secret`; returns what the client saw and
+    /// what the hub accepted.
+    async fn handshake(
+        secret: Option<&str>,
+        query: &str,
+        header: Option<&str>,
+    ) -> (std::result::Result<(), tungstenite::Error>, Result<ClientMode>) {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let secret = secret.map(str::to_string);
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            accept_debug_client(Role::Sidecar, stream, secret.as_deref())
+                .await
+                .map(|(_, options)| options.mode)
+        });
+
+        let mut request = format!("ws://127.0.0.1:{port}/{query}").into_client_request().unwrap();
+        if let Some(header) = header {
+            request.headers_mut().insert("X-Bridge-Secret", header.parse().unwrap());
+        }
+        let client = tokio_tungstenite::connect_async(request).await.map(|_| ());
+        (client, server.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn inject_without_the_secret_is_refused_with_403() {
+        let cases = [
+            (Some("s3cret"), "?mode=inject"),
+            (Some("s3cret"), "?mode=inject&secret=guess1"),
+            (Some("s3cret"), "?mode=INJECT&secret="),
+            // No configured secret disables injection altogether
+            (None, "?mode=inject&secret=s3cret"),
+        ];
+        for (secret, query) in cases {
+            let (client, server) = handshake(secret, query, None).await;
+            match client {
+                Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), StatusCode::FORBIDDEN),
+                other => panic!("{query}: expected a 403, got {other:?}"),
+            }
+            let err = server.expect_err(query);
+            assert!(is_refused_handshake(&err), "{query}: {err:#}");
+        }
+    }
+
+    #[tokio::test]
+    async fn the_secret_in_the_query_or_header_grants_inject() {
+        let (client, server) = handshake(Some("s3cret"), "?mode=inject&secret=s3cret", None).await;
+        client.unwrap();
+        assert_eq!(server.unwrap(), ClientMode::Inject);
+
+        let (client, server) = handshake(Some("s3cret"), "?mode=inject", Some("s3cret")).await;
+        client.unwrap();
+        assert_eq!(server.unwrap(), ClientMode::Inject);
+    }
+
+    #[tokio::test]
+    async fn clients_without_inject_mode_are_observe_only() {
+        // A secret alone does not grant injection, and unknown modes fall back to observing
+        for query in ["", "?secret=s3cret", "?mode=admin&secret=s3cret", "?mode=read-only"] {
+            let (client, server) = handshake(Some("s3cret"), query, Some("s3cret")).await;
+            client.unwrap();
+            assert_eq!(server.unwrap(), ClientMode::Observe, "{query:?}");
+        }
+    }
+
+    #[test]
+    fn privileged_senders_can_stop_a_capture_others_cannot() {
+        let dir = env::temp_dir().join(format!("bridge-hub-capture-{}", std::process::id()));
+        let hub = DebugHub::new(Role::App);
+        let config = CaptureConfig {
+            dir: dir.clone(),
+            ..CaptureConfig::default()
+        };
+        assert!(hub.start_capture(config).unwrap().enabled);
+
+        let stop = r#"{"v":1,"type":"debug.capture","payload":{"enabled":false}}"#;
+        let refused: Value = serde_json::from_str(&handle_capture_command(&hub, stop, false).unwrap()).unwrap();
+        assert_eq!(refused["type"], "debug.error");
+        assert!(hub.capture_status().enabled);
+
+        let stopped: Value = serde_json::from_str(&handle_capture_command(&hub, stop, true).unwrap()).unwrap();
+        assert_eq!(stopped["type"], "debug.capture.status");
+        assert_eq!(stopped["payload"]["enabled"], false);
+        assert!(!hub.capture_status().enabled);
+
+        // Past the privilege check, a start is validated like any other payload
+        let start = r#"{"v":1,"type":"debug.capture","payload":{"enabled":true,"maxFiles":"many"}}"#;
+        let invalid: Value = serde_json::from_str(&handle_capture_command(&hub, start, true).unwrap()).unwrap();
+        assert_eq!(invalid["type"], "debug.error");
+        assert!(invalid["payload"]["message"].as_str().unwrap().starts_with("invalid debug.capture payload"));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// Annotated frames a sidecar hub with the given identity renders for `message` on `hop`.
+    fn forwarded_frames(hop: Hop, messages: &[&str]) -> Vec<String> {
+        let sidecar = DebugHub::new(Role::Sidecar).with_identity("c1", "firefox");
+        let mut forward = sidecar.register(&annotated_client());
+        for message in messages {
+            sidecar.broadcast(hop, message);
+        }
+        replayed(&mut forward)
+    }
+
+    #[test]
+    fn forwarded_frames_keep_the_sidecars_hop_direction_and_connection() {
+        let original = r#"{"v":1,"type":"tabs.list","payload":{"windowId":7}}"#;
+        let frames = forwarded_frames(Hop::ExtensionToSidecar, &["padding", original]);
+        let sent: Value = serde_json::from_str(&frames[1]).unwrap();
+
+        let app = DebugHub::new(Role::App);
+        app.broadcast(Hop::App, &message(0));
+        let mut annotated = app.register(&DebugClientOptions {
+            history: HistoryQuery {
+                since_ms: None,
+                limit: Some(0),
+            },
+            ..annotated_client()
+        });
+        let mut raw = app.register(&DebugClientOptions {
+            history: HistoryQuery {
+                since_ms: None,
+                limit: Some(0),
+            },
+            ..raw_client()
+        });
+        app.merge_forwarded(&frames[1]);
+
+        // Raw clients get the message the sidecar saw, byte for byte
+        assert_eq!(replayed(&mut raw), vec![original.to_string()]);
+        let merged: Vec<Value> = replayed(&mut annotated)
+            .iter()
+            .map(|frame| serde_json::from_str(frame).unwrap())
+            .collect();
+        let payload = &merged[0]["payload"];
+        // Still reported as the sidecar's own event
+        assert_eq!(payload["source"], "sidecar");
+        assert_eq!(payload["hop"], "extension->sidecar");
+        // `in` as the sidecar saw it, though the app never sees this hop itself
+        assert_eq!(payload["direction"], sent["payload"]["direction"]);
+        assert_eq!(payload["connectionId"], "c1");
+        assert_eq!(payload["browser"], "firefox");
+        assert_eq!(payload["type"], "tabs.list");
+        // Sequenced by the app hub, with the sidecar's own sequence number alongside
+        assert_eq!(payload["seq"], 2);
+        assert_eq!(payload["sidecarSeq"], sent["payload"]["seq"]);
+        assert_eq!(payload["message"], json!({ "v": 1, "type": "tabs.list", "payload": { "windowId": 7 } }));
+    }
+
+    #[test]
+    fn forwarded_text_frames_and_injections_survive_the_merge() {
+        let app = DebugHub::new(Role::App);
+        let mut raw = app.register(&raw_client());
+        let mut annotated = app.register(&annotated_client());
+
+        app.merge_forwarded(&forwarded_frames(Hop::Sidecar, &["not json"])[0]);
+        let sidecar = DebugHub::new(Role::Sidecar).with_identity("c2", "chrome");
+        let mut forward = sidecar.register(&annotated_client());
+        sidecar.broadcast_injected(9, &message(1));
+        app.merge_forwarded(&replayed(&mut forward)[0]);
+
+        assert_eq!(replayed(&mut raw), vec!["not json".to_string(), message(1)]);
+        let merged: Vec<Value> = replayed(&mut annotated)
+            .iter()
+            .map(|frame| serde_json::from_str(frame).unwrap())
+            .collect();
+        assert_eq!(merged[0]["payload"]["text"], "not json");
+        assert_eq!(merged[0]["payload"]["hop"], "sidecar");
+        assert_eq!(merged[1]["payload"]["hop"], "debug->sidecar");
+        assert_eq!(merged[1]["payload"]["connectionId"], "c2");
+        assert_eq!(merged[1]["payload"]["debugClient"], 9);
+    }
+
+    #[test]
+    fn frames_that_cannot_be_merged_are_ignored() {
+        let app = DebugHub::new(Role::App);
+        let mut raw = app.register(&raw_client());
+        for frame in [
+            "not json",
+            r#"{"v":1,"type":"debug.frame"}"#,
+            r#"{"v":1,"type":"debug.frame","payload":{"hop":"nowhere","message":{}}}"#,
+            r#"{"v":1,"type":"debug.frame","payload":{"hop":"sidecar"}}"#,
+        ] {
+            app.merge_forwarded(frame);
+        }
+        assert!(replayed(&mut raw).is_empty());
+        assert_eq!(app.stats().events, 0);
+    }
+
+    #[test]
+    fn a_message_relayed_to_the_frontend_is_counted_once_per_hop() {
+        let hub = DebugHub::new(Role::App);
+        let focus = r#"{"v":1,"type":"focus.result","payload":{"connectionId":"c1"}}"#;
+        hub.broadcast(Hop::SidecarToApp, focus);
+        hub.broadcast(Hop::AppToFrontend, focus);
+
+        let mut out = String::new();
+        hub.stats().write_prometheus(&mut out);
+        hub.metrics().write_prometheus(&mut out);
+        let counted: Vec<&str> = out.lines().filter(|line| line.starts_with("bridge_messages_total{")).collect();
+        assert_eq!(
+            counted,
+            [
+                r#"bridge_messages_total{hop="sidecar->app",direction="in",type="focus.result"} 1"#,
+                r#"bridge_messages_total{hop="app->frontend",direction="out",type="focus.result"} 1"#,
+            ]
+        );
+        // No second family counting the same traffic
+        assert!(!out.contains("bridge_hop_"));
+    }
+}
@@ -0,0 +1,325 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+
+use crate::private;
+
+/// How long `discover` waits for a listed debug socket to accept a connection.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// One live debug socket, as written to the index directory by the process that owns it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DebugEndpoint {
+    /// `app` or `sidecar`.
+    pub role: String,
+    pub pid: u32,
+    pub port: u16,
+    pub url: String,
+    pub connection_id: Option<String>,
+    pub browser: Option<String>,
+    /// Unix timestamp (ms) at which the endpoint was registered.
+    pub started_at: u64,
+}
+
+impl DebugEndpoint {
+    pub fn new(role: &str, port: u16, connection_id: Option<&str>, browser: Option<&str>) -> Self {
+        Self {
+            role: role.to_string(),
+            pid: std::process::id(),
+            port,
+            url: format!("ws://127.0.0.1:{port}/"),
+            connection_id: connection_id.map(str::to_string),
+            browser: browser.map(str::to_string),
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Directory holding one `<role>-<pid>.json` file per live debug socket; `BRIDGE_DEBUG_INDEX`
+/// overrides the default `debug` under the per-user runtime dir.
+pub fn index_dir() -> PathBuf {
+    env::var("BRIDGE_DEBUG_INDEX")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| private::runtime_dir().join("debug"))
+}
+
+/// Keeps an endpoint listed for as long as it is alive; the entry is removed on drop.
+pub struct Registration {
+    path: PathBuf,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+pub fn register(endpoint: &DebugEndpoint) -> Result<Registration> {
+    register_in(&index_dir(), endpoint)
+}
+
+fn register_in(dir: &Path, endpoint: &DebugEndpoint) -> Result<Registration> {
+    private::create_private_dir(dir)?;
+    let path = dir.join(format!("{}-{}.json", endpoint.role, endpoint.pid));
+
+    // Write then rename so readers never see a half-written entry
+    let staging = path.with_extension("json.tmp");
+    private::create_private_file(&staging)?
+        .write_all(&serde_json::to_vec_pretty(endpoint)?)
+        .with_context(|| format!("writing {}", staging.display()))?;
+    fs::rename(&staging, &path).with_context(|| format!("writing {}", path.display()))?;
+    Ok(Registration { path })
+}
+
+/// Lists every registered endpoint that still accepts connections, app first.
+///
+/// Only entries owned by the current user are considered. Entries are pruned once the process
+/// that wrote them is gone; one that is merely slow to accept is left out of this listing but
+/// kept. Entries are probed concurrently, so a listing takes at most one `PROBE_TIMEOUT`.
+pub async fn discover() -> Vec<DebugEndpoint> {
+    discover_in(&index_dir()).await
+}
+
+async fn discover_in(dir: &Path) -> Vec<DebugEndpoint> {
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return Vec::new();
+    };
+
+    let mut probes = JoinSet::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if !path.extension().is_some_and(|ext| ext == "json") {
+            continue;
+        }
+        probes.spawn(async move {
+            // Another user's entry could point us, and the bridge secret, at their socket
+            let metadata = tokio::fs::symlink_metadata(&path).await.ok()?;
+            if !metadata.is_file() || !private::owned_by_current_user(&metadata) {
+                return None;
+            }
+            let endpoint = tokio::fs::read(&path)
+                .await
+                .ok()
+                .and_then(|bytes| serde_json::from_slice::<DebugEndpoint>(&bytes).ok());
+            match endpoint {
+                Some(endpoint) if !owner_gone(&endpoint) => is_listening(endpoint.port).await.then_some(endpoint),
+                _ => {
+                    let _ = tokio::fs::remove_file(&path).await;
+                    None
+                }
+            }
+        });
+    }
+
+    let mut endpoints = Vec::new();
+    while let Some(probe) = probes.join_next().await {
+        if let Ok(Some(endpoint)) = probe {
+            endpoints.push(endpoint);
+        }
+    }
+    endpoints.sort_by(|a, b| (&a.role, a.started_at).cmp(&(&b.role, b.started_at)));
+    endpoints
+}
+
+/// Whether the process that registered `endpoint` has certainly exited: its pid is free, or
+/// now belongs to a process started after the entry was written.
+#[cfg(unix)]
+fn owner_gone(endpoint: &DebugEndpoint) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(endpoint.pid) else {
+        return true;
+    };
+    if pid <= 0 {
+        return true;
+    }
+    // SAFETY: signal 0 only checks whether the process exists
+    let exists = unsafe { libc::kill(pid, 0) } == 0
+        || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+    !exists || process_start_ms(endpoint.pid).is_some_and(|started| started > endpoint.started_at + START_SLACK_MS)
+}
+
+/// Without a way to tell, entries are kept until their owner removes them.
+#[cfg(not(unix))]
+fn owner_gone(_endpoint: &DebugEndpoint) -> bool {
+    false
+}
+
+/// Boot time is only known to the second, so a start time this close to registration is not
+/// taken as a reused pid.
+#[cfg(unix)]
+const START_SLACK_MS: u64 = 2_000;
+
+/// Unix time (ms) at which `pid` started, from `starttime` in its `stat`.
+#[cfg(target_os = "linux")]
+fn process_start_ms(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // `comm` may contain spaces; counting from the state after it, starttime is the 20th field
+    let ticks: u64 = stat.get(stat.rfind(')')? + 1..)?.split_whitespace().nth(19)?.parse().ok()?;
+    let boot: u64 = fs::read_to_string("/proc/stat")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()?;
+    // SAFETY: sysconf has no preconditions
+    let hz = u64::try_from(unsafe { libc::sysconf(libc::_SC_CLK_TCK) }).ok().filter(|hz| *hz > 0)?;
+    Some(boot * 1000 + ticks * 1000 / hz)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn process_start_ms(_pid: u32) -> Option<u64> {
+    None
+}
+
+async fn is_listening(port: u16) -> bool {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    matches!(tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(addr)).await, Ok(Ok(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    /// A throwaway index directory under the system temp dir, removed on drop.
+    struct Scratch {
+        root: PathBuf,
+    }
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            let root = env::temp_dir().join(format!("bridge-index-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            Self { root }
+        }
+
+        fn names(&self) -> Vec<String> {
+            let mut names: Vec<String> = fs::read_dir(&self.root)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        }
+
+        /// Lists `endpoint` as if the process it names had registered it.
+        fn plant(&self, endpoint: &DebugEndpoint) {
+            fs::create_dir_all(&self.root).unwrap();
+            let path = self.root.join(format!("{}-{}.json", endpoint.role, endpoint.pid));
+            fs::write(path, serde_json::to_vec(endpoint).unwrap()).unwrap();
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    /// Pid of a process that has already exited and been reaped.
+    #[cfg(unix)]
+    fn exited_pid() -> u32 {
+        let mut child = Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        pid
+    }
+
+    #[test]
+    fn registering_writes_a_private_entry_that_drop_removes() {
+        let scratch = Scratch::new("register");
+        let endpoint = DebugEndpoint::new("sidecar", 4242, Some("c1"), Some("Chrome"));
+        let registration = register_in(&scratch.root, &endpoint).unwrap();
+
+        // Renamed into place: no staging file is left behind
+        let name = format!("sidecar-{}.json", std::process::id());
+        assert_eq!(scratch.names(), [name.as_str()]);
+        let listed: DebugEndpoint = serde_json::from_slice(&fs::read(scratch.root.join(&name)).unwrap()).unwrap();
+        assert_eq!((listed.port, listed.connection_id.as_deref()), (4242, Some("c1")));
+        assert_eq!(listed.url, "ws://127.0.0.1:4242/");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&scratch.root), 0o700);
+            assert_eq!(mode(&scratch.root.join(&name)), 0o600);
+        }
+
+        // Registering again replaces the entry in one step
+        let moved = DebugEndpoint::new("sidecar", 4343, Some("c1"), Some("Chrome"));
+        let registration_again = register_in(&scratch.root, &moved).unwrap();
+        assert_eq!(scratch.names(), [name.as_str()]);
+        drop(registration);
+        assert!(scratch.names().is_empty());
+        drop(registration_again);
+    }
+
+    #[tokio::test]
+    async fn discovery_lists_live_sockets_app_first() {
+        let scratch = Scratch::new("discover");
+        let app = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let sidecar = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let _sidecar = register_in(
+            &scratch.root,
+            &DebugEndpoint::new("sidecar", sidecar.local_addr().unwrap().port(), Some("c1"), None),
+        )
+        .unwrap();
+        let _app = register_in(&scratch.root, &DebugEndpoint::new("app", app.local_addr().unwrap().port(), None, None))
+            .unwrap();
+
+        let roles: Vec<String> = discover_in(&scratch.root).await.into_iter().map(|e| e.role).collect();
+        assert_eq!(roles, ["app", "sidecar"]);
+        assert!(discover_in(&scratch.root.join("missing")).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unreachable_sockets_of_live_processes_are_kept() {
+        let scratch = Scratch::new("unreachable");
+        // Bound and released, so nothing accepts on the port while this process lives on
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let _registration = register_in(&scratch.root, &DebugEndpoint::new("app", port, None, None)).unwrap();
+
+        assert!(discover_in(&scratch.root).await.is_empty());
+        assert_eq!(scratch.names(), [format!("app-{}.json", std::process::id())]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn entries_of_exited_processes_are_pruned() {
+        let scratch = Scratch::new("prune");
+        let pid = exited_pid();
+        let mut dead = DebugEndpoint::new("sidecar", 1, Some("gone"), None);
+        dead.pid = pid;
+        scratch.plant(&dead);
+        fs::write(scratch.root.join("app-1.json"), b"{not json").unwrap();
+        fs::write(scratch.root.join("notes.txt"), b"left alone").unwrap();
+
+        assert!(discover_in(&scratch.root).await.is_empty());
+        assert_eq!(scratch.names(), ["notes.txt"]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn entries_whose_pid_was_reused_are_pruned() {
+        let scratch = Scratch::new("reused");
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        // This process is alive, but started long after an entry registered at the epoch
+        let mut stale = DebugEndpoint::new("app", listener.local_addr().unwrap().port(), None, None);
+        stale.started_at = 0;
+        scratch.plant(&stale);
+
+        assert!(discover_in(&scratch.root).await.is_empty());
+        assert!(scratch.names().is_empty());
+    }
+}
@@ -0,0 +1,36 @@
+//! Debug plumbing shared by the desktop app and the sidecar: the debug hub and its WebSocket
+//! clients, traffic capture, the HTTP introspection endpoints, Prometheus metrics and the index
+//! of live debug sockets.
+
+pub mod capture;
+pub mod debug_http;
+pub mod debug_hub;
+pub mod debug_index;
+pub mod metrics;
+pub mod private;
+
+/// The process a hub runs in. It decides the hop names debug clients inject on, how hops map
+/// to directions, the `source` of annotated frames and the prefix of log lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    App,
+    Sidecar,
+}
+
+impl Role {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::App => "app",
+            Self::Sidecar => "sidecar",
+        }
+    }
+}
+
+/// Milliseconds since the unix epoch.
+pub fn unix_ms() -> u128 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
@@ -0,0 +1,324 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::debug_hub::Hop;
+
+/// Upper bounds (bytes) of the size histogram buckets.
+const SIZE_BUCKETS: &[f64] = &[64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0];
+/// Upper bounds (seconds) of the latency histogram buckets.
+const LATENCY_BUCKETS: &[f64] = &[0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+/// Distinct message types tracked; further types are counted as `other` so a misbehaving peer
+/// cannot grow the registry without bound.
+const MAX_TYPES: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Counter {
+    /// Messages that could not be delivered, labelled with a `reason`.
+    RoutingFailures,
+    /// Debug clients refused during the WebSocket handshake.
+    HandshakeRejections,
+    /// Connections to the app after the first one.
+    Reconnects,
+}
+
+impl Counter {
+    fn name(self) -> &'static str {
+        match self {
+            Self::RoutingFailures => "bridge_routing_failures_total",
+            Self::HandshakeRejections => "bridge_handshake_rejections_total",
+            Self::Reconnects => "bridge_reconnects_total",
+        }
+    }
+
+    fn help(self) -> &'static str {
+        match self {
+            Self::RoutingFailures => "Messages that could not be delivered, by reason.",
+            Self::HandshakeRejections => "Debug clients refused during the handshake.",
+            Self::Reconnects => "Connections to the app after the first one.",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Histogram {
+    /// Native messaging frames exchanged with the extension, by `direction`.
+    NativeFrameBytes,
+    /// Time to hand one message to a socket or pipe, by `link`.
+    SendLatency,
+}
+
+impl Histogram {
+    fn name(self) -> &'static str {
+        match self {
+            Self::NativeFrameBytes => "bridge_native_frame_bytes",
+            Self::SendLatency => "bridge_send_latency_seconds",
+        }
+    }
+
+    fn help(self) -> &'static str {
+        match self {
+            Self::NativeFrameBytes => "Size of native messaging frames exchanged with the extension.",
+            Self::SendLatency => "Time taken to write one message to its link.",
+        }
+    }
+
+    fn buckets(self) -> &'static [f64] {
+        match self {
+            Self::NativeFrameBytes => SIZE_BUCKETS,
+            Self::SendLatency => LATENCY_BUCKETS,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Buckets {
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Buckets {
+    fn new(len: usize) -> Self {
+        Self {
+            counts: vec![0; len],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+}
+
+/// `name="value"` label pair, already escaped.
+type Label = String;
+
+#[derive(Default)]
+struct Registry {
+    /// Count and bytes keyed by hop, direction and envelope type.
+    messages: BTreeMap<(Hop, &'static str, String), (u64, u64)>,
+    /// Envelope types with their own series, at most `MAX_TYPES`.
+    types: BTreeSet<String>,
+    counters: BTreeMap<(Counter, Label), u64>,
+    histograms: BTreeMap<(Histogram, Label), Buckets>,
+}
+
+/// Counters and histograms for the bridge, exported in the Prometheus text format on the debug
+/// port's `/metrics`. Cheap to clone; every clone records into the same registry.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl Metrics {
+    /// Counts one message crossing `hop`, which runs `direction` (`in`, `out` or `local`).
+    ///
+    /// A message that crosses several hops, such as `sidecar->app` then `app->frontend`, is
+    /// counted once on each, so totals are taken over a single hop.
+    pub fn record_message(&self, hop: Hop, direction: &'static str, kind: Option<&str>, bytes: usize) {
+        let mut registry = self.registry.lock().unwrap();
+        let kind = kind.unwrap_or("<untyped>");
+        if registry.types.len() < MAX_TYPES && !registry.types.contains(kind) {
+            registry.types.insert(kind.to_string());
+        }
+        let kind = if registry.types.contains(kind) { kind } else { "other" };
+        let entry = registry.messages.entry((hop, direction, kind.to_string())).or_default();
+        entry.0 += 1;
+        entry.1 += bytes as u64;
+    }
+
+    pub fn increment(&self, counter: Counter, label: Option<(&str, &str)>) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry.counters.entry((counter, label_pair(label))).or_default() += 1;
+    }
+
+    pub fn observe(&self, histogram: Histogram, label: (&str, &str), value: f64) {
+        let bounds = histogram.buckets();
+        let mut registry = self.registry.lock().unwrap();
+        let buckets = registry
+            .histograms
+            .entry((histogram, label_pair(Some(label))))
+            .or_insert_with(|| Buckets::new(bounds.len()));
+        if let Some(index) = bounds.iter().position(|bound| value <= *bound) {
+            buckets.counts[index] += 1;
+        }
+        buckets.sum += value;
+        buckets.count += 1;
+    }
+
+    /// Records the time since `started` in seconds.
+    pub fn observe_since(&self, histogram: Histogram, label: (&str, &str), started: Instant) {
+        self.observe(histogram, label, started.elapsed().as_secs_f64());
+    }
+
+    pub fn write_prometheus(&self, out: &mut String) {
+        let registry = self.registry.lock().unwrap();
+
+        let _ = writeln!(
+            out,
+            "# HELP bridge_messages_total Messages by hop, direction and envelope type, counted once per hop crossed."
+        );
+        let _ = writeln!(out, "# TYPE bridge_messages_total counter");
+        for ((hop, direction, kind), (count, _)) in &registry.messages {
+            let _ = writeln!(
+                out,
+                "bridge_messages_total{{hop=\"{}\",direction=\"{direction}\",type=\"{}\"}} {count}",
+                hop.name(),
+                escape(kind)
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP bridge_message_bytes_total Message bytes by hop, direction and envelope type, counted once per hop crossed."
+        );
+        let _ = writeln!(out, "# TYPE bridge_message_bytes_total counter");
+        for ((hop, direction, kind), (_, bytes)) in &registry.messages {
+            let _ = writeln!(
+                out,
+                "bridge_message_bytes_total{{hop=\"{}\",direction=\"{direction}\",type=\"{}\"}} {bytes}",
+                hop.name(),
+                escape(kind)
+            );
+        }
+
+        let mut family = None;
+        for ((counter, label), value) in &registry.counters {
+            if family != Some(*counter) {
+                family = Some(*counter);
+                let _ = writeln!(out, "# HELP {} {}", counter.name(), counter.help());
+                let _ = writeln!(out, "# TYPE {} counter", counter.name());
+            }
+            let labels = if label.is_empty() { String::new() } else { format!("{{{label}}}") };
+            let _ = writeln!(out, "{}{labels} {value}", counter.name());
+        }
+
+        let mut family = None;
+        for ((histogram, label), buckets) in &registry.histograms {
+            let name = histogram.name();
+            if family != Some(*histogram) {
+                family = Some(*histogram);
+                let _ = writeln!(out, "# HELP {name} {}", histogram.help());
+                let _ = writeln!(out, "# TYPE {name} histogram");
+            }
+            let mut cumulative = 0;
+            for (bound, count) in histogram.buckets().iter().zip(&buckets.counts) {
+                cumulative += count;
+                let _ = writeln!(out, "{name}_bucket{{{label},le=\"{bound}\"}} {cumulative}");
+            }
+            let _ = writeln!(out, "{name}_bucket{{{label},le=\"+Inf\"}} {}", buckets.count);
+            let _ = writeln!(out, "{name}_sum{{{label}}} {}", buckets.sum);
+            let _ = writeln!(out, "{name}_count{{{label}}} {}", buckets.count);
+        }
+    }
+}
+
+fn label_pair(label: Option<(&str, &str)>) -> Label {
+    label
+        .map(|(name, value)| format!("{name}=\"{}\"", escape(value)))
+        .unwrap_or_default()
+}
+
+/// Escapes a label value as the text format requires.
+pub fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exposition(metrics: &Metrics) -> String {
+        let mut out = String::new();
+        metrics.write_prometheus(&mut out);
+        out
+    }
+
+    fn series<'a>(out: &'a str, prefix: &str) -> Vec<&'a str> {
+        out.lines().filter(|line| line.starts_with(prefix)).collect()
+    }
+
+    #[test]
+    fn exposition_matches_the_text_format() {
+        let metrics = Metrics::default();
+        metrics.record_message(Hop::SidecarToApp, "in", Some("focus.window"), 40);
+        metrics.record_message(Hop::SidecarToApp, "in", Some("focus.window"), 60);
+        metrics.record_message(Hop::AppToFrontend, "out", Some("focus.window"), 60);
+        metrics.record_message(Hop::App, "local", None, 5);
+        metrics.increment(Counter::RoutingFailures, Some(("reason", "no_connections")));
+        metrics.increment(Counter::Reconnects, None);
+        metrics.increment(Counter::Reconnects, None);
+        metrics.observe(Histogram::NativeFrameBytes, ("direction", "in"), 100.0);
+        metrics.observe(Histogram::NativeFrameBytes, ("direction", "in"), 5000.0);
+        metrics.observe(Histogram::NativeFrameBytes, ("direction", "in"), 2e6);
+
+        let expected = r#"# HELP bridge_messages_total Messages by hop, direction and envelope type, counted once per hop crossed.
+# TYPE bridge_messages_total counter
+bridge_messages_total{hop="sidecar->app",direction="in",type="focus.window"} 2
+bridge_messages_total{hop="app->frontend",direction="out",type="focus.window"} 1
+bridge_messages_total{hop="app",direction="local",type="<untyped>"} 1
+# HELP bridge_message_bytes_total Message bytes by hop, direction and envelope type, counted once per hop crossed.
+# TYPE bridge_message_bytes_total counter
+bridge_message_bytes_total{hop="sidecar->app",direction="in",type="focus.window"} 100
+bridge_message_bytes_total{hop="app->frontend",direction="out",type="focus.window"} 60
+bridge_message_bytes_total{hop="app",direction="local",type="<untyped>"} 5
+# HELP bridge_routing_failures_total Messages that could not be delivered, by reason.
+# TYPE bridge_routing_failures_total counter
+bridge_routing_failures_total{reason="no_connections"} 1
+# HELP bridge_reconnects_total Connections to the app after the first one.
+# TYPE bridge_reconnects_total counter
+bridge_reconnects_total 2
+# HELP bridge_native_frame_bytes Size of native messaging frames exchanged with the extension.
+# TYPE bridge_native_frame_bytes histogram
+bridge_native_frame_bytes_bucket{direction="in",le="64"} 0
+bridge_native_frame_bytes_bucket{direction="in",le="256"} 1
+bridge_native_frame_bytes_bucket{direction="in",le="1024"} 1
+bridge_native_frame_bytes_bucket{direction="in",le="4096"} 1
+bridge_native_frame_bytes_bucket{direction="in",le="16384"} 2
+bridge_native_frame_bytes_bucket{direction="in",le="65536"} 2
+bridge_native_frame_bytes_bucket{direction="in",le="262144"} 2
+bridge_native_frame_bytes_bucket{direction="in",le="1048576"} 2
+bridge_native_frame_bytes_bucket{direction="in",le="+Inf"} 3
+bridge_native_frame_bytes_sum{direction="in"} 2005100
+bridge_native_frame_bytes_count{direction="in"} 3
+"#;
+        assert_eq!(exposition(&metrics), expected);
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        let metrics = Metrics::default();
+        metrics.record_message(Hop::Sidecar, "local", Some("a\"b\\c\nd"), 1);
+        metrics.increment(Counter::RoutingFailures, Some(("reason", "say \"hi\"")));
+
+        let out = exposition(&metrics);
+        assert_eq!(
+            series(&out, "bridge_messages_total{"),
+            [r#"bridge_messages_total{hop="sidecar",direction="local",type="a\"b\\c\nd"} 1"#]
+        );
+        assert_eq!(
+            series(&out, "bridge_routing_failures_total{"),
+            [r#"bridge_routing_failures_total{reason="say \"hi\""} 1"#]
+        );
+        // Every sample stays on one line
+        assert!(out.lines().all(|line| line.starts_with('#') || line.starts_with("bridge_")));
+        assert_eq!(escape("plain.type"), "plain.type");
+    }
+
+    #[test]
+    fn types_beyond_the_cap_are_counted_as_other() {
+        let metrics = Metrics::default();
+        for n in 0..MAX_TYPES + 44 {
+            metrics.record_message(Hop::ExtensionToSidecar, "in", Some(&format!("type.{n}")), 1);
+        }
+        // A tracked type keeps its own series on every hop, an untracked one never gets one
+        metrics.record_message(Hop::SidecarToApp, "out", Some("type.0"), 1);
+        metrics.record_message(Hop::SidecarToApp, "out", Some("type.999"), 1);
+
+        let out = exposition(&metrics);
+        let counted = series(&out, "bridge_messages_total{");
+        assert_eq!(counted.len(), MAX_TYPES + 3);
+        assert!(counted.contains(&r#"bridge_messages_total{hop="extension->sidecar",direction="in",type="other"} 44"#));
+        assert!(counted.contains(&r#"bridge_messages_total{hop="sidecar->app",direction="out",type="type.0"} 1"#));
+        assert!(counted.contains(&r#"bridge_messages_total{hop="sidecar->app",direction="out",type="other"} 1"#));
+        assert!(!out.contains("type.300"));
+    }
+}
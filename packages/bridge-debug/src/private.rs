@@ -0,0 +1,113 @@
+use anyhow::{bail, Context, Result};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+/// Per-user directory for files that only live as long as the session, e.g. `$XDG_RUNTIME_DIR/bridge`.
+///
+/// Without `XDG_RUNTIME_DIR` this is `bridge-<uid>` under the system temp dir, which
+/// `create_private_dir` refuses to use if another user got there first.
+pub fn runtime_dir() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir).join("bridge"),
+        None => env::temp_dir().join(user_suffixed("bridge")),
+    }
+}
+
+/// Per-user directory for files kept across sessions: `$XDG_DATA_HOME/bridge`, else
+/// `~/.local/share/bridge`, falling back to `runtime_dir`.
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(dir).join("bridge");
+    }
+    #[cfg(unix)]
+    if let Some(home) = env::var_os("HOME").filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(home).join(".local/share/bridge");
+    }
+    #[cfg(windows)]
+    if let Some(local) = env::var_os("LOCALAPPDATA").filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(local).join("bridge");
+    }
+    runtime_dir()
+}
+
+#[cfg(unix)]
+fn user_suffixed(name: &str) -> String {
+    format!("{name}-{}", current_uid())
+}
+
+// The temp dir is already per user on other platforms
+#[cfg(not(unix))]
+fn user_suffixed(name: &str) -> String {
+    name.to_string()
+}
+
+#[cfg(unix)]
+pub fn current_uid() -> u32 {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    unsafe { libc::geteuid() }
+}
+
+/// Whether the file behind `metadata` belongs to the current user; always true where files
+/// have no unix owner.
+pub fn owned_by_current_user(metadata: &fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.uid() == current_uid()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        true
+    }
+}
+
+/// Creates `dir` and any missing parents, and makes sure only the current user can use it.
+///
+/// An existing `dir` owned by someone else is an error; one with looser permissions is
+/// tightened to `0700`.
+pub fn create_private_dir(dir: &Path) -> Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder
+        .create(dir)
+        .with_context(|| format!("creating {}", dir.display()))?;
+
+    // Not following symlinks, so a planted link cannot redirect us
+    let metadata = fs::symlink_metadata(dir).with_context(|| format!("inspecting {}", dir.display()))?;
+    if !metadata.is_dir() {
+        bail!("{} is not a directory", dir.display());
+    }
+    if !owned_by_current_user(&metadata) {
+        bail!("{} belongs to another user", dir.display());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o077 != 0 {
+            fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+                .with_context(|| format!("restricting {}", dir.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Creates or truncates `path` so that only the current user can read or write it.
+pub fn create_private_file(path: &Path) -> Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(path)
+        .with_context(|| format!("creating {}", path.display()))
+}
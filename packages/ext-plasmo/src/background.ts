@@ -6,7 +6,12 @@ import {
   TabsSavedPayloadSchema,
   TabsRestorePayloadSchema,
   PresenceStatusPayloadSchema,
-  FocusWindowPayloadSchema
+  FocusWindowPayloadSchema,
+  FocusResultPayloadSchema,
+  WindowClosedPayloadSchema,
+  WindowMarkPayloadSchema,
+  WindowMarkResultPayloadSchema,
+  SidecarInfoPayloadSchema
 } from "@bridge/shared-proto";
 
 const HOST_NAME = "com.bridge.app";
@@ -17,6 +22,10 @@ let reconnectTimer: number | undefined;
 let connectionId: string | null = null;
 let browser: string | null = null;
 
+// Title markers waiting for the sidecar's window.mark.result, by marker
+const MARKER_TIMEOUT_MS = 3000;
+const pendingMarkers = new Map<string, { tabId: number; timer: ReturnType<typeof setTimeout> }>();
+
 const randomId = (): string => {
   const globalCrypto = globalThis.crypto;
   if (globalCrypto?.randomUUID) {
@@ -46,6 +55,70 @@ const notifyFocusWindow = (windowId?: number | null, title?: string | null, url?
   }
 };
 
+const notifyWindowClosed = (windowId: number) => {
+  postToNative({
+    v: 1,
+    type: "window.closed",
+    payload: WindowClosedPayloadSchema.parse({ windowId, connectionId: connectionId ?? undefined })
+  });
+};
+
+const setTitleMarker = async (tabId: number, marker: string, apply: boolean) => {
+  await chrome.scripting.executeScript({
+    target: { tabId },
+    args: [marker, apply],
+    func: (marker: string, apply: boolean) => {
+      const prefix = `${marker} `;
+      if (apply && !document.title.startsWith(prefix)) {
+        document.title = prefix + document.title;
+      } else if (!apply && document.title.startsWith(prefix)) {
+        document.title = document.title.slice(prefix.length);
+      }
+    }
+  });
+};
+
+const clearTitleMarker = (marker: string) => {
+  const pending = pendingMarkers.get(marker);
+  if (!pending) {
+    return;
+  }
+  pendingMarkers.delete(marker);
+  clearTimeout(pending.timer);
+  setTitleMarker(pending.tabId, marker, false).catch((error) =>
+    console.warn("[bridge-ext] failed to remove window marker", error)
+  );
+};
+
+/**
+ * Prefixes the active tab's title with a unique marker so the sidecar can find the native
+ * window showing it and map `windowId` to it exactly (`window.mark`).
+ */
+const markWindow = async (windowId: number) => {
+  const [tab] = await chrome.tabs.query({ windowId, active: true });
+  if (tab?.id == null) {
+    return;
+  }
+
+  const marker = `[bridge-${randomId().replace(/-/g, "").slice(0, 12)}]`;
+  try {
+    await setTitleMarker(tab.id, marker, true);
+  } catch (error) {
+    // Pages the extension cannot script (chrome://, the web store) cannot carry a marker
+    console.warn("[bridge-ext] failed to apply window marker", error);
+    return;
+  }
+
+  const timer = setTimeout(() => clearTitleMarker(marker), MARKER_TIMEOUT_MS);
+  pendingMarkers.set(marker, { tabId: tab.id, timer });
+  postToNative({
+    v: 1,
+    id: randomId(),
+    type: "window.mark",
+    payload: WindowMarkPayloadSchema.parse({ windowId, marker, connectionId: connectionId ?? undefined })
+  });
+};
+
 const serializeTab = (tab: chrome.tabs.Tab) => ({
   id: tab.id ?? undefined,
   url: tab.url ?? undefined,
@@ -143,6 +216,47 @@ const onFromNative = async (raw: unknown) => {
         }
         break;
       }
+      case "focus.result": {
+        const result = FocusResultPayloadSchema.safeParse(payload);
+        if (result.success && result.data.status !== "focused") {
+          console.warn(`[bridge-ext] focus.window ${result.data.status}:`, result.data.error);
+        } else if (DEV) {
+          console.log("[bridge-ext] focus.result", payload);
+        }
+        // A searched window is a guess; map it exactly for the next focus.window
+        if (result.success && result.data.strategy === "search" && result.data.windowId != null) {
+          void markWindow(result.data.windowId);
+        }
+        break;
+      }
+      case "window.mark.result": {
+        const result = WindowMarkResultPayloadSchema.safeParse(payload);
+        if (!result.success) {
+          break;
+        }
+        clearTitleMarker(result.data.marker);
+        if (result.data.status !== "mapped") {
+          console.warn(`[bridge-ext] window.mark ${result.data.status}:`, result.data.error);
+        } else if (DEV) {
+          console.log("[bridge-ext] window.mark.result", payload);
+        }
+        break;
+      }
+      case "sidecar.info.result": {
+        const info = SidecarInfoPayloadSchema.safeParse(payload);
+        if (info.success) {
+          const { version, profile, browser: sidecarBrowser, connectionId: sidecarConnection, app } = info.data;
+          console.info(
+            `[bridge-ext] sidecar ${version} (${profile}) for ${sidecarBrowser} ${sidecarConnection}, app ${
+              app.connected ? "connected" : "disconnected"
+            }`,
+            info.data
+          );
+        } else {
+          console.warn("[bridge-ext] malformed sidecar.info.result", info.error);
+        }
+        break;
+      }
       case "tabs.restore": {
         const args = TabsRestorePayloadSchema.parse(payload);
         await restoreTabs(args);
@@ -605,6 +719,7 @@ const subscribeTabEvents = () => {
   chrome.tabs.onAttached.addListener((_id, _info) => void sendCurrentWindowTabs("attached"));
   chrome.tabs.onDetached.addListener((_id, _info) => void sendCurrentWindowTabs("detached"));
   chrome.windows.onFocusChanged.addListener(() => void sendCurrentWindowTabs("focus-changed"));
+  chrome.windows.onRemoved.addListener((windowId) => notifyWindowClosed(windowId));
 };
 
 if (DEV) {
@@ -613,6 +728,10 @@ if (DEV) {
       if (message?.type === "test.triggerTabsList") {
         await sendCurrentWindowTabs("dev-trigger");
         sendResponse({ ok: true });
+      } else if (message?.type === "test.sidecarInfo") {
+        // The answer is logged by the sidecar.info.result case
+        postToNative({ v: 1, id: randomId(), type: "sidecar.info" });
+        sendResponse({ ok: true });
       } else if (message?.type === "test.openOrFocus") {
         try {
           const args = TabsOpenOrFocusPayloadSchema.parse(message.payload);
@@ -2,4 +2,5 @@ export * from "./schemas/envelope.js";
 export * from "./schemas/tabs.js";
 export * from "./schemas/presence.js";
 export * from "./schemas/window.js";
+export * from "./schemas/sidecar.js";
 export * from "./utils/guards.js";
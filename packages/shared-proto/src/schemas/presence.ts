@@ -12,7 +12,8 @@ export const PresenceStatusPayloadSchema = z.object({
   sidecar: z.enum(["online", "offline"]).optional(),
   timestamp: z.number().int().optional(),
   connectionId: z.string().optional(),
-  browser: z.string().optional()
+  browser: z.string().optional(),
+  extensionId: z.string().optional()
 });
 
 export type PresenceStatusPayload = z.infer<typeof PresenceStatusPayloadSchema>;
@@ -0,0 +1,39 @@
+import { z } from "zod";
+
+/**
+ * Sidecar's answer to `sidecar.info`, sent back to whichever side asked.
+ */
+export const SidecarInfoPayloadSchema = z.object({
+  version: z.string(),
+  profile: z.enum(["debug", "release"]),
+  pid: z.number().int(),
+  uptimeMs: z.number().int(),
+  connectionId: z.string(),
+  browser: z.string(),
+  caller: z
+    .object({
+      family: z.enum(["chromium", "firefox"]),
+      extensionId: z.string(),
+      origin: z.string().nullable(),
+      manifest: z.string().nullable(),
+      parentWindow: z.number().int().nullable()
+    })
+    .nullable(),
+  app: z.object({
+    url: z.string(),
+    connected: z.boolean(),
+    connectedAt: z.number().int().nullable(),
+    reconnects: z.number().int()
+  }),
+  debugPort: z.number().int().nullable(),
+  queues: z.object({
+    toApp: z.number().int().nullable(),
+    toExtension: z.number().int().nullable()
+  }),
+  config: z.object({
+    env: z.array(z.string()),
+    allowList: z.array(z.string()).nullable()
+  })
+});
+
+export type SidecarInfoPayload = z.infer<typeof SidecarInfoPayloadSchema>;
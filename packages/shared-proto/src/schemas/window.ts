@@ -10,3 +10,93 @@ export const FocusWindowPayloadSchema = z.object({
 
 export type FocusWindowPayload = z.infer<typeof FocusWindowPayloadSchema>;
 
+/**
+ * Sent by the extension when a browser window closes, so the sidecar can drop its cached handle.
+ */
+export const WindowClosedPayloadSchema = z.object({
+  windowId: z.number().int(),
+  connectionId: z.string().optional()
+});
+
+export type WindowClosedPayload = z.infer<typeof WindowClosedPayloadSchema>;
+
+export const ScoreItemSchema = z.object({
+  rule: z.string(),
+  points: z.number().int(),
+  detail: z.string().optional()
+});
+
+/**
+ * Sidecar's answer to `focus.window`, sent to the extension and forwarded to the app.
+ * `requestId` (and the envelope `id`) repeat the request's envelope `id`.
+ */
+export const FocusResultPayloadSchema = z.object({
+  requestId: z.string().nullable(),
+  status: z.enum(["focused", "not_found", "failed"]),
+  backend: z.string().nullable(),
+  strategy: z.enum(["cache", "search"]),
+  cacheHit: z.boolean(),
+  window: z.number().int().nullable(),
+  title: z.string().nullable(),
+  process: z.string().nullable(),
+  score: z.number().int().nullable(),
+  scoreBreakdown: z.array(ScoreItemSchema),
+  error: z.string().nullable(),
+  windowId: z.number().int().nullable(),
+  connectionId: z.string().nullable(),
+  browser: z.string().nullable()
+});
+
+export type FocusResultPayload = z.infer<typeof FocusResultPayloadSchema>;
+
+
+/**
+ * Sent by the extension after prefixing the active tab's title in `windowId` with `marker`; the
+ * sidecar maps the native window showing it and answers with `window.mark.result`.
+ */
+export const WindowMarkPayloadSchema = z.object({
+  windowId: z.number().int(),
+  marker: z.string().min(8),
+  connectionId: z.string().optional()
+});
+
+export type WindowMarkPayload = z.infer<typeof WindowMarkPayloadSchema>;
+
+/**
+ * Once this arrives the extension removes the marker again.
+ */
+export const WindowMarkResultPayloadSchema = z.object({
+  requestId: z.string().nullable(),
+  status: z.enum(["mapped", "not_found", "ambiguous", "failed"]),
+  backend: z.string().nullable(),
+  window: z.number().int().nullable(),
+  title: z.string().nullable(),
+  attempts: z.number().int(),
+  error: z.string().nullable(),
+  windowId: z.number().int().nullable(),
+  marker: z.string(),
+  connectionId: z.string().nullable()
+});
+
+export type WindowMarkResultPayload = z.infer<typeof WindowMarkResultPayloadSchema>;
+
+export const FocusCacheQueryPayloadSchema = z
+  .object({
+    clear: z.boolean().optional()
+  })
+  .optional();
+
+export const FocusCacheStatePayloadSchema = z.object({
+  size: z.number().int(),
+  capacity: z.number().int(),
+  entries: z.array(
+    z.object({
+      connectionId: z.string(),
+      windowId: z.number().int(),
+      window: z.number().int(),
+      source: z.enum(["search", "marker"])
+    })
+  )
+});
+
+export type FocusCacheStatePayload = z.infer<typeof FocusCacheStatePayloadSchema>;
@@ -10,7 +10,12 @@ import {
   TabsSavedPayloadSchema,
   TabsRestorePayloadSchema
 } from "../schemas/tabs.js";
-import { FocusWindowPayloadSchema } from "../schemas/window.js";
+import {
+  FocusCacheQueryPayloadSchema,
+  FocusWindowPayloadSchema,
+  WindowClosedPayloadSchema,
+  WindowMarkPayloadSchema
+} from "../schemas/window.js";
 
 export const isEnvelope = (value: unknown): value is ReturnType<typeof EnvelopeSchema.parse> => {
   try {
@@ -28,6 +33,10 @@ export const payloadParsers = {
   "tabs.save": TabsSavedPayloadSchema,
   "tabs.restore": TabsRestorePayloadSchema,
   "focus.window": FocusWindowPayloadSchema,
+  "focus.cache": FocusCacheQueryPayloadSchema,
+  "window.closed": WindowClosedPayloadSchema,
+  "window.mark": WindowMarkPayloadSchema,
+  "sidecar.info": z.undefined().optional(),
   "presence.query": PresenceQueryPayloadSchema,
   "presence.status": PresenceStatusPayloadSchema
 } as const;
@@ -42,6 +42,11 @@ $manifest = @{
 $manifest | Out-File -FilePath $ManifestPath -Encoding utf8 -NoNewline
 Write-Host "Manifest generated: $ManifestPath" -ForegroundColor Green
 
+# The sidecar refuses callers missing from this list, even if a stale manifest lets them in
+$AllowListPath = Join-Path (Split-Path -Parent $SidecarExePath) "allowed-extensions.json"
+ConvertTo-Json -InputObject @($ExtensionId) | Out-File -FilePath $AllowListPath -Encoding utf8 -NoNewline
+Write-Host "Allow-list written: $AllowListPath" -ForegroundColor Green
+
 # Function to register in registry
 function Register-NativeMessagingHost {
     param(
@@ -0,0 +1,521 @@
+//! Replays a JSONL traffic capture against the desktop app as if it were a sidecar.
+//!
+//! Connects to the app WebSocket the way `bridge_to_app` does, announces itself with
+//! `presence.status`, then sends every message the captured sidecar delivered to the app with
+//! the original (or scaled) spacing. With `--assert` the messages the app sends back are compared
+//! with the ones recorded in the capture.
+
+use anyhow::{bail, Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, sleep_until, Instant};
+use tokio_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+const DEFAULT_APP_WS: &str = "ws://127.0.0.1:17342";
+const DEFAULT_SETTLE: Duration = Duration::from_millis(1000);
+
+const USAGE: &str = "usage: bridge-replay <capture.jsonl>... [--app-ws URL] [--speed FACTOR] \
+[--from CONNECTION] [--connection-id ID] [--browser NAME] [--settle-ms MS] [--assert] [--strict]";
+
+/// How closely the app's replies have to follow the capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AssertMode {
+    Off,
+    /// Same envelope types in the same order.
+    Types,
+    /// Same envelopes, ignoring `timestamp` fields.
+    Strict,
+}
+
+struct Args {
+    captures: Vec<PathBuf>,
+    app_ws: String,
+    /// Playback speed; `2.0` halves every gap, `0` sends without waiting.
+    speed: f64,
+    /// Captured connection to replay; defaults to the first one in the capture.
+    from: Option<String>,
+    /// Connection id to announce instead of the captured one.
+    connection_id: Option<String>,
+    browser: Option<String>,
+    /// How long to keep listening for replies after the last message.
+    settle: Duration,
+    assert: AssertMode,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        Self::parse_from(env::args().skip(1))
+    }
+
+    fn parse_from(raw: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Self {
+            captures: Vec::new(),
+            app_ws: env::var("APP_WS").unwrap_or_else(|_| DEFAULT_APP_WS.to_string()),
+            speed: 1.0,
+            from: None,
+            connection_id: None,
+            browser: None,
+            settle: DEFAULT_SETTLE,
+            assert: AssertMode::Off,
+        };
+
+        let mut raw = raw.into_iter();
+        while let Some(arg) = raw.next() {
+            let mut value = |name: &str| raw.next().with_context(|| format!("{name} needs a value\n{USAGE}"));
+            match arg.as_str() {
+                "--app-ws" => args.app_ws = value("--app-ws")?,
+                "--speed" => {
+                    args.speed = value("--speed")?.parse().context("--speed must be a number")?;
+                    if args.speed < 0.0 {
+                        bail!("--speed must not be negative");
+                    }
+                }
+                "--from" => args.from = Some(value("--from")?),
+                "--connection-id" => args.connection_id = Some(value("--connection-id")?),
+                "--browser" => args.browser = Some(value("--browser")?),
+                "--settle-ms" => {
+                    let ms = value("--settle-ms")?.parse().context("--settle-ms must be a number")?;
+                    args.settle = Duration::from_millis(ms);
+                }
+                "--assert" if args.assert == AssertMode::Off => args.assert = AssertMode::Types,
+                "--assert" => {}
+                "--strict" => args.assert = AssertMode::Strict,
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    std::process::exit(0);
+                }
+                other if other.starts_with("--") => bail!("unknown option {other}\n{USAGE}"),
+                path => args.captures.push(PathBuf::from(path)),
+            }
+        }
+
+        if args.captures.is_empty() {
+            bail!("{USAGE}");
+        }
+        Ok(args)
+    }
+}
+
+#[derive(Deserialize)]
+struct CapturedFrame {
+    payload: FrameInfo,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FrameInfo {
+    hop: String,
+    connection_id: Option<String>,
+    browser: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    timestamp: u64,
+    monotonic_us: Option<u64>,
+    message: Option<Value>,
+    text: Option<String>,
+}
+
+impl FrameInfo {
+    fn raw(&self) -> Option<String> {
+        match (&self.message, &self.text) {
+            (Some(message), _) => Some(message.to_string()),
+            (None, Some(text)) => Some(text.clone()),
+            (None, None) => None,
+        }
+    }
+
+    /// Presence is re-announced by the replay itself and `debug.*` replies are sidecar-local.
+    fn is_replayable(&self) -> bool {
+        self.kind
+            .as_deref()
+            .map_or(true, |kind| kind != "presence.status" && !kind.starts_with("debug."))
+    }
+}
+
+/// The part of a capture that concerns one sidecar connection.
+struct Session {
+    connection_id: Option<String>,
+    browser: Option<String>,
+    /// Messages for the app, with their offset from the first one.
+    outgoing: Vec<(Duration, String)>,
+    /// What the app sent back during the captured session.
+    expected: Vec<Value>,
+}
+
+fn load_session(paths: &[PathBuf], connection_filter: Option<&str>) -> Result<Session> {
+    let mut frames = Vec::new();
+    for path in paths {
+        let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let frame: CapturedFrame = serde_json::from_str(&line)
+                .with_context(|| format!("{}:{}: not a debug.frame", path.display(), index + 1))?;
+            frames.push(frame.payload);
+        }
+    }
+
+    // App captures hold every connection; follow the requested one, or the first one seen
+    let connection_id = connection_filter.map(str::to_string).or_else(|| {
+        frames
+            .iter()
+            .filter(|frame| frame.hop == "sidecar->app")
+            .find_map(|frame| frame.connection_id.clone())
+    });
+    let same_connection = |frame: &FrameInfo| match (&connection_id, &frame.connection_id) {
+        (Some(wanted), Some(id)) => wanted == id,
+        _ => true,
+    };
+
+    let mut session = Session {
+        connection_id: connection_id.clone(),
+        browser: None,
+        outgoing: Vec::new(),
+        expected: Vec::new(),
+    };
+    let mut first: Option<(u64, Option<u64>)> = None;
+
+    for frame in frames.iter().filter(|frame| same_connection(frame)) {
+        match frame.hop.as_str() {
+            "sidecar->app" if frame.is_replayable() => {
+                let Some(raw) = frame.raw() else { continue };
+                session.browser = session.browser.take().or_else(|| frame.browser.clone());
+                let (start_ms, start_us) = *first.get_or_insert((frame.timestamp, frame.monotonic_us));
+                let offset = match (start_us, frame.monotonic_us) {
+                    (Some(start), Some(now)) if now >= start => Duration::from_micros(now - start),
+                    _ => Duration::from_millis(frame.timestamp.saturating_sub(start_ms)),
+                };
+                session.outgoing.push((offset, raw));
+            }
+            "app->sidecar" => {
+                if let Some(message) = &frame.message {
+                    session.expected.push(message.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(session)
+}
+
+fn unix_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
+
+fn presence_envelope(state: &str, connection_id: &str, browser: &str, reason: Option<&str>) -> String {
+    let mut payload = json!({
+        "sidecar": state,
+        "timestamp": unix_ms(),
+        "connectionId": connection_id,
+        "browser": browser
+    });
+    if let Some(reason) = reason {
+        payload["reason"] = json!(reason);
+    }
+    json!({ "v": 1, "type": "presence.status", "payload": payload }).to_string()
+}
+
+/// Points captured `connectionId` fields at the replay's connection and drops `timestamp`s so
+/// envelopes from different runs compare equal.
+fn normalize(value: &mut Value, from: Option<&str>, to: &str, strip_timestamps: bool) {
+    match value {
+        Value::Object(map) => {
+            if strip_timestamps {
+                map.remove("timestamp");
+            }
+            for (key, inner) in map.iter_mut() {
+                if key == "connectionId" && from.is_some_and(|from| inner.as_str() == Some(from)) {
+                    *inner = Value::String(to.to_string());
+                } else {
+                    normalize(inner, from, to, strip_timestamps);
+                }
+            }
+        }
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| normalize(item, from, to, strip_timestamps)),
+        _ => {}
+    }
+}
+
+fn envelope_type(value: &Value) -> &str {
+    value.get("type").and_then(|t| t.as_str()).unwrap_or("<untyped>")
+}
+
+/// Returns one line per difference between the captured and the live replies.
+fn compare(mode: AssertMode, expected: &[Value], received: &[Value]) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    for index in 0..expected.len().max(received.len()) {
+        match (expected.get(index), received.get(index)) {
+            (Some(want), Some(got)) => {
+                let differs = match mode {
+                    AssertMode::Strict => want != got,
+                    _ => envelope_type(want) != envelope_type(got),
+                };
+                if differs {
+                    mismatches.push(format!("#{index}: expected {want}, got {got}"));
+                }
+            }
+            (Some(want), None) => mismatches.push(format!("#{index}: expected {want}, got nothing")),
+            (None, Some(got)) => mismatches.push(format!("#{index}: unexpected {got}")),
+            (None, None) => {}
+        }
+    }
+    mismatches
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse()?;
+    let mut session = load_session(&args.captures, args.from.as_deref())?;
+
+    let captured_id = session.connection_id.clone();
+    let connection_id = args
+        .connection_id
+        .clone()
+        .or_else(|| captured_id.clone())
+        .unwrap_or_else(|| format!("replay-{:x}", unix_ms()));
+    let browser = args
+        .browser
+        .clone()
+        .or_else(|| session.browser.clone())
+        .unwrap_or_else(|| "Replay".to_string());
+    let strip = args.assert == AssertMode::Strict;
+    for (_, raw) in session.outgoing.iter_mut() {
+        if let Ok(mut value) = serde_json::from_str::<Value>(raw) {
+            normalize(&mut value, captured_id.as_deref(), &connection_id, false);
+            *raw = value.to_string();
+        }
+    }
+    for value in session.expected.iter_mut() {
+        normalize(value, captured_id.as_deref(), &connection_id, strip);
+    }
+
+    eprintln!(
+        "[replay] {} messages for {connection_id} ({browser}) -> {}",
+        session.outgoing.len(),
+        args.app_ws
+    );
+
+    let (ws_stream, _) = connect_async(&args.app_ws)
+        .await
+        .with_context(|| format!("connecting to {}", args.app_ws))?;
+    let (mut write, mut read) = ws_stream.split();
+    write
+        .send(Message::Text(presence_envelope("online", &connection_id, &browser, None)))
+        .await?;
+
+    let (received_tx, mut received_rx) = mpsc::unbounded_channel::<Value>();
+    let reader = tokio::spawn(async move {
+        while let Some(Ok(message)) = read.next().await {
+            let Message::Text(txt) = message else { continue };
+            match serde_json::from_str::<Value>(&txt) {
+                Ok(mut value) => {
+                    eprintln!("[replay] <- {}", envelope_type(&value));
+                    normalize(&mut value, None, "", strip);
+                    let _ = received_tx.send(value);
+                }
+                Err(_) => eprintln!("[replay] <- non-JSON text: {txt}"),
+            }
+        }
+    });
+
+    let start = Instant::now();
+    for (offset, raw) in &session.outgoing {
+        if args.speed > 0.0 {
+            sleep_until(start + offset.div_f64(args.speed)).await;
+        }
+        let kind = serde_json::from_str::<Value>(raw)
+            .map(|value| envelope_type(&value).to_string())
+            .unwrap_or_default();
+        eprintln!("[replay] -> {kind}");
+        write.send(Message::Text(raw.clone())).await?;
+    }
+
+    sleep(args.settle).await;
+    let offline = presence_envelope("offline", &connection_id, &browser, Some("replay-complete"));
+    let _ = write.send(Message::Text(offline)).await;
+    let frame = CloseFrame {
+        code: CloseCode::Normal,
+        reason: "replay-complete".into(),
+    };
+    let _ = write.send(Message::Close(Some(frame))).await;
+
+    if tokio::time::timeout(DEFAULT_SETTLE, reader).await.is_err() {
+        eprintln!("[replay] app did not close the connection after the replay");
+    }
+    let mut received = Vec::new();
+    while let Ok(value) = received_rx.try_recv() {
+        received.push(value);
+    }
+    eprintln!(
+        "[replay] done: sent {}, received {} (captured {})",
+        session.outgoing.len(),
+        received.len(),
+        session.expected.len()
+    );
+
+    if args.assert != AssertMode::Off {
+        let mismatches = compare(args.assert, &session.expected, &received);
+        if !mismatches.is_empty() {
+            for mismatch in &mismatches {
+                eprintln!("[replay] mismatch {mismatch}");
+            }
+            bail!("{} replies differ from the capture", mismatches.len());
+        }
+        eprintln!("[replay] replies match the capture");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// An app capture with two connections: `c1` (chrome, with monotonic times) and `c2`
+    /// (firefox, wall-clock times only).
+    fn fixture() -> Vec<PathBuf> {
+        vec![PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/app-capture.jsonl")]
+    }
+
+    fn types(messages: &[(Duration, String)]) -> Vec<String> {
+        messages
+            .iter()
+            .map(|(_, raw)| match serde_json::from_str::<Value>(raw) {
+                Ok(value) => envelope_type(&value).to_string(),
+                Err(_) => raw.clone(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replays_what_the_first_sidecar_sent_and_expects_what_the_app_answered() {
+        let session = load_session(&fixture(), None).unwrap();
+        assert_eq!(session.connection_id.as_deref(), Some("c1"));
+        assert_eq!(session.browser.as_deref(), Some("chrome"));
+
+        // Presence is re-announced by the replay and debug replies stay local; app->frontend
+        // never reached the sidecar
+        assert_eq!(types(&session.outgoing), ["tabs.list", "not json"]);
+        let offsets: Vec<Duration> = session.outgoing.iter().map(|(offset, _)| *offset).collect();
+        assert_eq!(offsets, [Duration::ZERO, Duration::from_micros(250_000)]);
+
+        let expected: Vec<&str> = session.expected.iter().map(envelope_type).collect();
+        assert_eq!(expected, ["tabs.list.result"]);
+        assert_eq!(session.expected[0]["payload"]["tabs"][0]["title"], "Inbox");
+    }
+
+    #[test]
+    fn from_picks_another_connection_and_falls_back_to_wall_clock_offsets() {
+        let session = load_session(&fixture(), Some("c2")).unwrap();
+        assert_eq!(session.connection_id.as_deref(), Some("c2"));
+        assert_eq!(session.browser.as_deref(), Some("firefox"));
+        assert_eq!(types(&session.outgoing), ["focus.window", "focus.window"]);
+        assert_eq!(session.outgoing[1].0, Duration::from_millis(60));
+        let expected: Vec<&str> = session.expected.iter().map(envelope_type).collect();
+        assert_eq!(expected, ["focus.result"]);
+
+        let session = load_session(&fixture(), Some("c3")).unwrap();
+        assert!(session.outgoing.is_empty() && session.expected.is_empty());
+    }
+
+    #[test]
+    fn a_line_that_is_not_a_frame_names_its_position() {
+        let paths = vec![env::temp_dir().join(format!("bridge-replay-bad-{}.jsonl", std::process::id()))];
+        let first = fs::read_to_string(&fixture()[0]).unwrap().lines().next().unwrap().to_string();
+        fs::write(&paths[0], format!("{first}\n\n{{\"v\":1,\"type\":\"tabs.list\"}}\n")).unwrap();
+        let err = load_session(&paths, None).err().unwrap();
+        let _ = fs::remove_file(&paths[0]);
+        assert!(format!("{err:#}").contains(":3: not a debug.frame"), "{err:#}");
+    }
+
+    #[test]
+    fn normalize_moves_the_captured_connection_and_strips_timestamps_on_request() {
+        let captured = json!({
+            "type": "focus.result",
+            "timestamp": 1,
+            "payload": { "connectionId": "c1", "timestamp": 2, "other": { "connectionId": "c9" } },
+            "items": [{ "connectionId": "c1", "timestamp": 3 }]
+        });
+
+        let mut value = captured.clone();
+        normalize(&mut value, Some("c1"), "replay", false);
+        assert_eq!(value["payload"]["connectionId"], "replay");
+        assert_eq!(value["items"][0]["connectionId"], "replay");
+        assert_eq!(value["payload"]["other"]["connectionId"], "c9");
+        assert_eq!(value["timestamp"], 1);
+
+        let mut value = captured.clone();
+        normalize(&mut value, None, "replay", true);
+        assert_eq!(value["payload"]["connectionId"], "c1");
+        assert!(value.get("timestamp").is_none());
+        assert!(value["payload"].get("timestamp").is_none());
+        assert!(value["items"][0].get("timestamp").is_none());
+    }
+
+    #[test]
+    fn types_mode_compares_envelope_types_and_strict_mode_whole_envelopes() {
+        let expected = [
+            json!({ "type": "tabs.list.result", "payload": { "tabs": [1] } }),
+            json!({ "type": "focus.result", "payload": { "ok": true } }),
+        ];
+        let same_types = [
+            json!({ "type": "tabs.list.result", "payload": { "tabs": [2] } }),
+            json!({ "type": "focus.result", "payload": { "ok": true } }),
+        ];
+        assert!(compare(AssertMode::Types, &expected, &same_types).is_empty());
+        let strict = compare(AssertMode::Strict, &expected, &same_types);
+        assert_eq!(strict.len(), 1);
+        assert!(strict[0].starts_with("#0: expected"), "{strict:?}");
+        assert!(compare(AssertMode::Strict, &expected, &expected).is_empty());
+
+        let reordered = [expected[1].clone(), expected[0].clone()];
+        assert_eq!(compare(AssertMode::Types, &expected, &reordered).len(), 2);
+
+        let missing = compare(AssertMode::Types, &expected, &expected[..1]);
+        assert_eq!(missing.len(), 1);
+        assert!(missing[0].ends_with("got nothing"), "{missing:?}");
+        let extra = compare(AssertMode::Types, &expected[..1], &expected);
+        assert!(extra[0].starts_with("#1: unexpected"), "{extra:?}");
+    }
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        Args::parse_from(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn assert_and_strict_flags_pick_the_mode() {
+        let mode = |args: &[&str]| parse(args).unwrap().assert;
+        assert_eq!(mode(&["a.jsonl"]), AssertMode::Off);
+        assert_eq!(mode(&["a.jsonl", "--assert"]), AssertMode::Types);
+        assert_eq!(mode(&["a.jsonl", "--strict"]), AssertMode::Strict);
+        assert_eq!(mode(&["--strict", "a.jsonl", "--assert"]), AssertMode::Strict);
+        assert_eq!(mode(&["--assert", "a.jsonl", "--strict"]), AssertMode::Strict);
+
+        let args = parse(&["a.jsonl", "b.jsonl", "--speed", "2", "--from", "c2", "--settle-ms", "50"]).unwrap();
+        assert_eq!(args.captures, [PathBuf::from("a.jsonl"), PathBuf::from("b.jsonl")]);
+        assert_eq!(args.speed, 2.0);
+        assert_eq!(args.from.as_deref(), Some("c2"));
+        assert_eq!(args.settle, Duration::from_millis(50));
+
+        for bad in [&["--assert"][..], &["a.jsonl", "--speed", "-1"], &["a.jsonl", "--speed"], &["a.jsonl", "--loop"]] {
+            assert!(parse(bad).is_err(), "{bad:?}");
+        }
+    }
+}
@@ -0,0 +1,580 @@
+//! Live inspector for the bridge debug sockets.
+//!
+//! Connects to a debug socket served by the app (`run_debug_listener`) or a sidecar
+//! (`spawn_debug_ws`), asks for annotated frames narrowed by the usual query filters, and prints
+//! one coloured line per envelope. Payloads can additionally be matched against a regex, message
+//! rates are summarised per type, and with `--inject` lines read from stdin are sent into the
+//! bridge using the bridge secret.
+
+use anyhow::{bail, Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::io::{BufRead, IsTerminal};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{interval_at, Instant};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+use bridge_debug::debug_index::{self, DebugEndpoint};
+
+/// The app's debug socket unless `BRIDGE_DEBUG_PORT` moved it.
+const DEFAULT_DEBUG_WS: &str = "ws://127.0.0.1:17888/";
+const DEFAULT_RATE_WINDOW: Duration = Duration::from_secs(5);
+
+const USAGE: &str = "usage: bridge-tap [URL | --sidecar ID|BROWSER] [--list] [--type GLOB]... \
+[--connection ID]... [--browser NAME]... [--direction in|out|local] [--hop HOP]... [--grep REGEX] \
+[--history N] [--rates [SECS]] [--quiet] [--expand] [--json] [--color|--no-color] \
+[--inject] [--send JSON]... [--secret SECRET]";
+
+struct Args {
+    url: Option<String>,
+    /// Sidecar to attach to, by connection id (or a prefix of it) or browser name.
+    sidecar: Option<String>,
+    list: bool,
+    types: Vec<String>,
+    connection_ids: Vec<String>,
+    browsers: Vec<String>,
+    directions: Vec<String>,
+    hops: Vec<String>,
+    /// Matched against the mirrored message text; frames that do not match are not shown.
+    grep: Option<Regex>,
+    history: Option<usize>,
+    /// Interval for per-type rate summaries.
+    rates: Option<Duration>,
+    /// Print only rate summaries and notices, no frames.
+    quiet: bool,
+    /// Pretty-print each message over several lines.
+    expand: bool,
+    /// Print frames exactly as received, one JSON document per line.
+    json: bool,
+    color: bool,
+    /// Connect in inject mode and send stdin lines into the bridge.
+    inject: bool,
+    send: Vec<String>,
+    secret: Option<String>,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        Self::parse_from(env::args().skip(1))
+    }
+
+    fn parse_from(raw: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Self {
+            url: None,
+            sidecar: None,
+            list: false,
+            types: Vec::new(),
+            connection_ids: Vec::new(),
+            browsers: Vec::new(),
+            directions: Vec::new(),
+            hops: Vec::new(),
+            grep: None,
+            history: None,
+            rates: None,
+            quiet: false,
+            expand: false,
+            json: false,
+            color: std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none(),
+            inject: false,
+            send: Vec::new(),
+            secret: env::var("BRIDGE_SECRET").ok().filter(|s| !s.is_empty()),
+        };
+
+        let mut raw = raw.into_iter().peekable();
+        while let Some(arg) = raw.next() {
+            let mut value = |name: &str| raw.next().with_context(|| format!("{name} needs a value\n{USAGE}"));
+            match arg.as_str() {
+                "--sidecar" => args.sidecar = Some(value("--sidecar")?),
+                "--list" => args.list = true,
+                "--type" => args.types.push(value("--type")?),
+                "--connection" => args.connection_ids.push(value("--connection")?),
+                "--browser" => args.browsers.push(value("--browser")?),
+                "--direction" => args.directions.push(value("--direction")?),
+                "--hop" => args.hops.push(value("--hop")?),
+                "--grep" => {
+                    let pattern = value("--grep")?;
+                    args.grep = Some(Regex::new(&pattern).with_context(|| format!("invalid --grep {pattern}"))?);
+                }
+                "--history" => {
+                    args.history = Some(value("--history")?.parse().context("--history must be a number")?)
+                }
+                "--rates" => {
+                    let secs = raw.next_if(|next| !next.starts_with("--"));
+                    args.rates = Some(match secs {
+                        Some(secs) => {
+                            let secs: f64 = secs.parse().context("--rates takes a number of seconds")?;
+                            if secs <= 0.0 {
+                                bail!("--rates interval must be positive");
+                            }
+                            Duration::from_secs_f64(secs)
+                        }
+                        None => DEFAULT_RATE_WINDOW,
+                    });
+                }
+                "--quiet" => args.quiet = true,
+                "--expand" => args.expand = true,
+                "--json" => args.json = true,
+                "--color" => args.color = true,
+                "--no-color" => args.color = false,
+                "--inject" => args.inject = true,
+                "--send" => {
+                    let message = value("--send")?;
+                    serde_json::from_str::<Value>(&message).context("--send needs a JSON envelope")?;
+                    args.send.push(message);
+                    args.inject = true;
+                }
+                "--secret" => args.secret = Some(value("--secret")?),
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    std::process::exit(0);
+                }
+                other if other.starts_with("--") => bail!("unknown option {other}\n{USAGE}"),
+                url if args.url.is_none() => args.url = Some(url.to_string()),
+                extra => bail!("unexpected argument {extra}\n{USAGE}"),
+            }
+        }
+
+        if args.inject && args.secret.is_none() {
+            bail!("--inject and --send need the bridge secret (--secret or BRIDGE_SECRET)");
+        }
+        // The secret only goes to a socket the user named, never to one picked from the index
+        if args.inject && args.url.is_none() {
+            bail!("--inject and --send need the socket URL; see bridge-tap --list");
+        }
+        if args.url.is_some() && args.sidecar.is_some() {
+            bail!("pass either a URL or --sidecar, not both");
+        }
+        Ok(args)
+    }
+
+    /// Query string for the debug socket; everything but the regex is filtered server side.
+    fn query(&self) -> String {
+        let mut pairs = vec!["format=annotated".to_string()];
+        let mut list = |key: &str, values: &[String]| {
+            if !values.is_empty() {
+                pairs.push(format!("{key}={}", values.join(",")));
+            }
+        };
+        list("types", &self.types);
+        list("connectionId", &self.connection_ids);
+        list("browser", &self.browsers);
+        list("direction", &self.directions);
+        // `>` is not allowed in a URL; the hub accepts it percent-encoded
+        list("hop", &self.hops.iter().map(|h| h.replace('>', "%3E")).collect::<Vec<_>>());
+        if let Some(history) = self.history {
+            pairs.push(format!("history={history}"));
+        }
+        if self.inject {
+            pairs.push("mode=inject".to_string());
+        }
+        pairs.join("&")
+    }
+}
+
+/// The socket to attach to: the URL given, else the app or the chosen sidecar among the live
+/// `endpoints`.
+fn resolve_url(args: &Args, endpoints: &[DebugEndpoint]) -> Result<String> {
+    if let Some(url) = &args.url {
+        return Ok(url.clone());
+    }
+    let Some(wanted) = &args.sidecar else {
+        return Ok(endpoints
+            .iter()
+            .find(|endpoint| endpoint.role == "app")
+            .map(|endpoint| endpoint.url.clone())
+            .unwrap_or_else(|| DEFAULT_DEBUG_WS.to_string()));
+    };
+
+    let matches: Vec<&DebugEndpoint> = endpoints
+        .iter()
+        .filter(|endpoint| endpoint.role == "sidecar")
+        .filter(|endpoint| {
+            endpoint.connection_id.as_deref().is_some_and(|id| id.starts_with(wanted.as_str()))
+                || endpoint.browser.as_deref().is_some_and(|b| b.eq_ignore_ascii_case(wanted))
+        })
+        .collect();
+    match matches.as_slice() {
+        [endpoint] => Ok(endpoint.url.clone()),
+        [] => bail!("no live sidecar matches {wanted}; see bridge-tap --list"),
+        _ => bail!("{} sidecars match {wanted}; pass a longer connection id", matches.len()),
+    }
+}
+
+fn print_endpoints(endpoints: &[DebugEndpoint]) {
+    if endpoints.is_empty() {
+        println!("no live debug sockets listed");
+        return;
+    }
+    for endpoint in endpoints {
+        println!(
+            "{:<8} pid {:<7} {:<28} {} {}",
+            endpoint.role,
+            endpoint.pid,
+            endpoint.url,
+            endpoint.browser.as_deref().unwrap_or("-"),
+            endpoint.connection_id.as_deref().unwrap_or("")
+        );
+    }
+}
+
+/// ANSI styling that collapses to plain text when colour is off.
+#[derive(Clone, Copy)]
+struct Painter {
+    enabled: bool,
+}
+
+impl Painter {
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.enabled {
+            format!("\x1b[{code}m{text}\x1b[0m")
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn direction(&self, direction: &str) -> String {
+        let code = match direction {
+            "in" => "32",
+            "out" => "36",
+            _ => "90",
+        };
+        self.paint(code, &format!("{direction:<5}"))
+    }
+
+    /// Types of one family (`tabs.*`, `focus.*`, ...) share a colour.
+    fn kind(&self, kind: &str) -> String {
+        const PALETTE: [&str; 6] = ["33", "35", "34", "93", "95", "94"];
+        let family = kind.split('.').next().unwrap_or(kind);
+        let code = match family {
+            "debug" => "90",
+            "error" => "1;31",
+            _ => {
+                let hash = family.bytes().fold(0usize, |acc, b| acc.wrapping_mul(31).wrapping_add(b as usize));
+                PALETTE[hash % PALETTE.len()]
+            }
+        };
+        self.paint(code, kind)
+    }
+}
+
+/// UTC wall clock time of a unix timestamp in milliseconds.
+fn clock(ms: u64) -> String {
+    let secs = ms / 1000;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        ms % 1000
+    )
+}
+
+/// Frame counts per type, for the current window and since start. A message seen on several
+/// hops counts once per hop; filter with `--hop` to count a single link.
+struct Rates {
+    counts: HashMap<String, (u64, u64)>,
+    window_start: Instant,
+}
+
+impl Rates {
+    fn new() -> Self {
+        Self {
+            counts: HashMap::new(),
+            window_start: Instant::now(),
+        }
+    }
+
+    fn record(&mut self, kind: &str) {
+        let (window, total) = self.counts.entry(kind.to_string()).or_default();
+        *window += 1;
+        *total += 1;
+    }
+
+    fn report(&mut self, painter: Painter) {
+        let window = self.window_start.elapsed().max(Duration::from_millis(1));
+        self.window_start = Instant::now();
+        let mut rows: Vec<(&String, &mut (u64, u64))> = self.counts.iter_mut().collect();
+        rows.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then(b.1 .1.cmp(&a.1 .1)).then(a.0.cmp(b.0)));
+        println!("{}", painter.paint("1", &format!("-- rates over {:.1}s --", window.as_secs_f64())));
+        for (kind, (count, total)) in rows {
+            if *total == 0 {
+                continue;
+            }
+            let rate = *count as f64 / window.as_secs_f64();
+            println!("{:>9.2}/s {:>8} total  {}", rate, total, painter.kind(kind));
+            *count = 0;
+        }
+    }
+}
+
+/// Prints one received message; returns its envelope type when it is a shown `debug.frame`.
+fn show(args: &Args, painter: Painter, text: &str) -> Option<String> {
+    let Ok(envelope) = serde_json::from_str::<Value>(text) else {
+        println!("{}", painter.paint("90", text));
+        return None;
+    };
+    let envelope_type = envelope.get("type").and_then(|t| t.as_str()).unwrap_or("");
+    if envelope_type != "debug.frame" {
+        // Hub notices: subscription acks, lag reports, errors and capture status
+        let payload = envelope.get("payload").map(Value::to_string).unwrap_or_default();
+        eprintln!("{} {}", painter.kind(envelope_type), painter.paint("90", &payload));
+        return None;
+    }
+
+    let payload = envelope.get("payload")?;
+    let field = |name: &str| payload.get(name).and_then(|v| v.as_str()).unwrap_or("");
+    let message = match (payload.get("message"), payload.get("text")) {
+        (Some(message), _) => message.to_string(),
+        (None, Some(Value::String(text))) => text.clone(),
+        _ => String::new(),
+    };
+    if args.grep.as_ref().is_some_and(|grep| !grep.is_match(&message)) {
+        return None;
+    }
+    let kind = if field("type").is_empty() { "<untyped>" } else { field("type") };
+
+    if !args.quiet {
+        if args.json {
+            println!("{text}");
+        } else {
+            let seq = payload.get("seq").and_then(|s| s.as_u64()).unwrap_or_default();
+            let timestamp = payload.get("timestamp").and_then(|t| t.as_u64()).unwrap_or_default();
+            let origin = [field("browser"), field("connectionId")]
+                .into_iter()
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ");
+            let mut line = format!(
+                "{} {} {} {} {} {}",
+                painter.paint("90", &clock(timestamp)),
+                painter.paint("90", &format!("{:>5}", format!("#{seq}"))),
+                painter.direction(field("direction")),
+                painter.paint("2", &format!("{:<18}", field("hop"))),
+                painter.kind(kind),
+                painter.paint("90", &format!("[{origin}]")),
+            );
+            if let Some(client) = payload.get("debugClient").and_then(|c| c.as_u64()) {
+                line.push_str(&painter.paint("1;35", &format!(" injected by #{client}")));
+            }
+            if args.expand {
+                let body = payload
+                    .get("message")
+                    .and_then(|m| serde_json::to_string_pretty(m).ok())
+                    .unwrap_or(message);
+                println!("{line}");
+                for body_line in body.lines() {
+                    println!("    {body_line}");
+                }
+            } else {
+                println!("{line} {message}");
+            }
+        }
+    }
+    Some(kind.to_string())
+}
+
+/// Forwards stdin lines to the socket task; blocking reads stay off the runtime.
+fn read_stdin(tx: mpsc::Sender<String>) {
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if line.trim().is_empty() {
+                continue;
+            }
+            if tx.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse()?;
+    if args.list {
+        print_endpoints(&debug_index::discover().await);
+        return Ok(());
+    }
+
+    let endpoints = match args.url {
+        Some(_) => Vec::new(),
+        None => debug_index::discover().await,
+    };
+    let base = resolve_url(&args, &endpoints)?;
+    let separator = if base.contains('?') { '&' } else { '?' };
+    let url = format!("{base}{separator}{}", args.query());
+    let mut request = url.as_str().into_client_request().context("invalid debug socket URL")?;
+    if args.inject {
+        // A header rather than the query string, so the secret needs no escaping
+        let secret = args.secret.as_deref().unwrap_or_default();
+        request
+            .headers_mut()
+            .insert("X-Bridge-Secret", HeaderValue::from_str(secret).context("secret is not a valid header value")?);
+    }
+
+    let (ws_stream, _) = connect_async(request)
+        .await
+        .with_context(|| format!("connecting to {base}"))?;
+    eprintln!("[tap] connected to {base}");
+    let (mut write, mut read) = ws_stream.split();
+
+    for message in &args.send {
+        write.send(Message::Text(message.clone())).await?;
+    }
+    let (stdin_tx, mut stdin_rx) = mpsc::channel::<String>(64);
+    if args.inject && args.send.is_empty() {
+        read_stdin(stdin_tx);
+    } else {
+        drop(stdin_tx);
+    }
+
+    let painter = Painter { enabled: args.color && !args.json };
+    let window = args.rates.unwrap_or(DEFAULT_RATE_WINDOW);
+    let mut ticker = interval_at(Instant::now() + window, window);
+    let mut rates = Rates::new();
+    // Created once: a fresh ctrl_c future per iteration would re-register the signal handler
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    loop {
+        tokio::select! {
+            incoming = read.next() => match incoming {
+                Some(Ok(Message::Text(text))) => {
+                    if let Some(kind) = show(&args, painter, &text) {
+                        rates.record(&kind);
+                    }
+                }
+                Some(Ok(Message::Close(frame))) => {
+                    let reason = frame.map(|f| f.reason.to_string()).unwrap_or_default();
+                    eprintln!("[tap] socket closed {reason}");
+                    break;
+                }
+                Some(Ok(_)) => {}
+                Some(Err(err)) => {
+                    eprintln!("[tap] socket error: {err:#}");
+                    break;
+                }
+                None => break,
+            },
+            Some(line) = stdin_rx.recv() => {
+                if serde_json::from_str::<Value>(&line).is_err() {
+                    eprintln!("[tap] not sent, not JSON: {line}");
+                    continue;
+                }
+                write.send(Message::Text(line)).await?;
+            }
+            _ = ticker.tick(), if args.rates.is_some() => rates.report(painter),
+            _ = &mut ctrl_c => break,
+        }
+    }
+
+    if args.rates.is_some() {
+        rates.report(painter);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        Args::parse_from(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn endpoint(role: &str, port: u16, connection_id: Option<&str>, browser: Option<&str>) -> DebugEndpoint {
+        DebugEndpoint::new(role, port, connection_id, browser)
+    }
+
+    #[test]
+    fn query_carries_the_server_side_filters() {
+        let cases: &[(&[&str], &str)] = &[
+            (&[], "format=annotated"),
+            (
+                &["--type", "focus.*", "--type", "tabs.list", "--browser", "chrome"],
+                "format=annotated&types=focus.*,tabs.list&browser=chrome",
+            ),
+            (
+                &["--connection", "c1", "--direction", "in", "--history", "20"],
+                "format=annotated&connectionId=c1&direction=in&history=20",
+            ),
+            (&["--hop", "extension->sidecar", "--hop", "app"], "format=annotated&hop=extension-%3Esidecar,app"),
+            // Matched locally, so not part of the query
+            (&["--grep", "example\\.com", "--quiet"], "format=annotated"),
+            (
+                &["ws://127.0.0.1:9/", "--send", r#"{"v":1}"#, "--secret", "s"],
+                "format=annotated&mode=inject",
+            ),
+        ];
+        for (argv, query) in cases {
+            assert_eq!(parse(argv).unwrap().query(), *query, "{argv:?}");
+        }
+    }
+
+    #[test]
+    fn bad_arguments_are_reported() {
+        let cases: &[(&[&str], &str)] = &[
+            (&["--type"], "--type needs a value"),
+            (&["--history", "many"], "--history must be a number"),
+            (&["--rates", "0"], "--rates interval must be positive"),
+            (&["--grep", "("], "invalid --grep"),
+            (&["--send", "not json", "--secret", "s"], "--send needs a JSON envelope"),
+            (&["--bogus"], "unknown option --bogus"),
+            (&["ws://a/", "ws://b/"], "unexpected argument ws://b/"),
+            (&["ws://a/", "--sidecar", "c1"], "either a URL or --sidecar"),
+            // The secret is never sent to a socket found in the index
+            (&["--inject", "--secret", "s"], "need the socket URL"),
+            (&["--sidecar", "c1", "--inject", "--secret", "s"], "need the socket URL"),
+        ];
+        for (argv, error) in cases {
+            let err = parse(argv).err().unwrap_or_else(|| panic!("{argv:?} parsed"));
+            assert!(format!("{err:#}").contains(error), "{argv:?}: {err:#}");
+        }
+    }
+
+    #[test]
+    fn rates_take_an_optional_interval() {
+        assert_eq!(parse(&["--rates"]).unwrap().rates, Some(DEFAULT_RATE_WINDOW));
+        assert_eq!(parse(&["--rates", "0.5"]).unwrap().rates, Some(Duration::from_millis(500)));
+        let args = parse(&["--rates", "--quiet"]).unwrap();
+        assert_eq!(args.rates, Some(DEFAULT_RATE_WINDOW));
+        assert!(args.quiet);
+    }
+
+    #[test]
+    fn resolve_url_prefers_the_given_url_then_the_app() {
+        let endpoints = [
+            endpoint("app", 17900, None, None),
+            endpoint("sidecar", 40001, Some("c1-aaaa"), Some("Chrome")),
+        ];
+        let url = parse(&["ws://127.0.0.1:9/"]).unwrap();
+        assert_eq!(resolve_url(&url, &endpoints).unwrap(), "ws://127.0.0.1:9/");
+        assert_eq!(resolve_url(&parse(&[]).unwrap(), &endpoints).unwrap(), "ws://127.0.0.1:17900/");
+        assert_eq!(resolve_url(&parse(&[]).unwrap(), &endpoints[1..]).unwrap(), DEFAULT_DEBUG_WS);
+    }
+
+    #[test]
+    fn resolve_url_picks_one_sidecar_by_connection_prefix_or_browser() {
+        let endpoints = [
+            endpoint("app", 17900, None, None),
+            endpoint("sidecar", 40001, Some("c1-aaaa"), Some("Chrome")),
+            endpoint("sidecar", 40002, Some("c1-bbbb"), Some("Firefox")),
+            endpoint("sidecar", 40003, Some("c2-cccc"), None),
+        ];
+        let resolve = |wanted: &str| resolve_url(&parse(&["--sidecar", wanted]).unwrap(), &endpoints);
+
+        assert_eq!(resolve("c1-b").unwrap(), "ws://127.0.0.1:40002/");
+        assert_eq!(resolve("chrome").unwrap(), "ws://127.0.0.1:40001/");
+        assert_eq!(resolve("c2").unwrap(), "ws://127.0.0.1:40003/");
+        assert!(format!("{:#}", resolve("c1").unwrap_err()).contains("2 sidecars match c1"));
+        assert!(format!("{:#}", resolve("edge").unwrap_err()).contains("no live sidecar matches edge"));
+        // The app is never picked as a sidecar
+        assert!(resolve_url(&parse(&["--sidecar", "app"]).unwrap(), &endpoints[..1]).is_err());
+    }
+}
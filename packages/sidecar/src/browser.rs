@@ -0,0 +1,240 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How many ancestors are inspected before giving up; browsers launch native hosts directly or
+/// through a shell or sandbox wrapper, so the browser is never far up the tree.
+const MAX_DEPTH: usize = 8;
+
+/// Executable names of each browser's main process, including the distribution wrappers.
+const EXECUTABLES: &[(&str, &[&str])] = &[
+    ("Chrome", &["chrome", "google-chrome", "google-chrome-stable", "google-chrome-beta", "google-chrome-unstable"]),
+    ("Chromium", &["chromium", "chromium-browser", "chromium-freeworld"]),
+    ("Edge", &["msedge", "microsoft-edge", "microsoft-edge-stable", "microsoft-edge-beta", "microsoft-edge-dev"]),
+    ("Brave", &["brave", "brave-browser", "brave-browser-stable", "brave-browser-beta", "brave-browser-nightly"]),
+    ("Vivaldi", &["vivaldi", "vivaldi-bin", "vivaldi-stable", "vivaldi-snapshot"]),
+    ("Firefox", &["firefox", "firefox-bin", "firefox-esr", "firefox-developer-edition", "firefox-nightly"]),
+];
+
+/// Install locations that identify a browser whose executable has an unusual name.
+const INSTALL_DIRS: &[(&str, &[&str])] = &[
+    ("Chrome", &["/opt/google/chrome/"]),
+    ("Chromium", &["/snap/chromium/", "/usr/lib/chromium/"]),
+    ("Edge", &["/opt/microsoft/msedge"]),
+    ("Brave", &["/opt/brave.com/", "/snap/brave/"]),
+    ("Vivaldi", &["/opt/vivaldi"]),
+    ("Firefox", &["/snap/firefox/", "/usr/lib/firefox/", "/app/lib/firefox/"]),
+];
+
+/// Flatpak app ids, passed as an argument to the `flatpak run` or `bwrap` process that sits
+/// between a sandboxed browser and the host.
+const FLATPAK_IDS: &[(&str, &str)] = &[
+    ("Chrome", "com.google.Chrome"),
+    ("Chromium", "org.chromium.Chromium"),
+    ("Edge", "com.microsoft.Edge"),
+    ("Brave", "com.brave.Browser"),
+    ("Vivaldi", "com.vivaldi.Vivaldi"),
+    ("Firefox", "org.mozilla.firefox"),
+];
+
+/// Root of the process filesystem; `BRIDGE_PROC_ROOT` points detection at a fake tree.
+pub fn proc_root() -> PathBuf {
+    env::var("BRIDGE_PROC_ROOT")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("/proc"))
+}
+
+/// Walks up from `<root>/self` through `stat` parent ids and names the first ancestor whose
+/// `cmdline` (or `comm`, for processes without one) belongs to a known browser.
+pub fn detect_from_procfs(root: &Path) -> Option<&'static str> {
+    let mut pid = parent_pid(&fs::read_to_string(root.join("self/stat")).ok()?)?;
+    for _ in 0..MAX_DEPTH {
+        if pid <= 1 {
+            break;
+        }
+        let dir = root.join(pid.to_string());
+        let stat = fs::read_to_string(dir.join("stat")).ok()?;
+        let argv = fs::read(dir.join("cmdline"))
+            .map(|raw| split_cmdline(&raw))
+            .unwrap_or_default();
+        if let Some(browser) = identify(&argv, command_name(&stat).unwrap_or_default()) {
+            eprintln!("[sidecar] Browser {browser} found at pid {pid} ({})", argv.first().map_or("", String::as_str));
+            return Some(browser);
+        }
+        pid = parent_pid(&stat)?;
+    }
+    None
+}
+
+/// Lowercase executable name of `pid`: the file name of `argv[0]`, or `comm` when the process
+/// has no command line.
+pub fn executable_name(root: &Path, pid: u32) -> Option<String> {
+    let dir = root.join(pid.to_string());
+    let argv = fs::read(dir.join("cmdline"))
+        .map(|raw| split_cmdline(&raw))
+        .unwrap_or_default();
+    let name = match argv.first() {
+        Some(arg0) => arg0.rsplit('/').next().unwrap_or(arg0).to_string(),
+        None => command_name(&fs::read_to_string(dir.join("stat")).ok()?)?.to_string(),
+    };
+    Some(name.to_lowercase())
+}
+
+fn identify(argv: &[String], comm: &str) -> Option<&'static str> {
+    let program = argv.first().map_or(comm, |arg0| arg0.rsplit('/').next().unwrap_or(arg0));
+    let program = program.to_lowercase();
+    let by_name = EXECUTABLES
+        .iter()
+        .find(|(_, names)| names.contains(&program.as_str()))
+        .map(|(browser, _)| *browser);
+    let by_dir = || {
+        let path = argv.first()?;
+        INSTALL_DIRS
+            .iter()
+            .find(|(_, dirs)| dirs.iter().any(|dir| path.starts_with(dir)))
+            .map(|(browser, _)| *browser)
+    };
+    // `flatpak run org.mozilla.firefox` or a ref such as `org.mozilla.firefox/x86_64/stable`
+    let by_flatpak = || {
+        FLATPAK_IDS
+            .iter()
+            .find(|(_, id)| {
+                argv.iter()
+                    .skip(1)
+                    .any(|arg| arg == id || arg.strip_prefix(id).is_some_and(|rest| rest.starts_with('/')))
+            })
+            .map(|(browser, _)| *browser)
+    };
+    by_name.or_else(by_dir).or_else(by_flatpak)
+}
+
+/// `comm` is the second field of `stat`, in parentheses; it may itself contain spaces and
+/// parentheses, so the fields after it are found from the last `)`.
+fn command_name(stat: &str) -> Option<&str> {
+    let start = stat.find('(')?;
+    let end = stat.rfind(')')?;
+    stat.get(start + 1..end)
+}
+
+fn parent_pid(stat: &str) -> Option<u32> {
+    let rest = stat.get(stat.rfind(')')? + 1..)?;
+    // Fields after `comm`: state, ppid, ...
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// `cmdline` holds NUL-terminated arguments; it is empty for kernel threads and zombies.
+fn split_cmdline(raw: &[u8]) -> Vec<String> {
+    raw.split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A throwaway `/proc` lookalike; `self` is a process whose parent is `parent`.
+    struct ProcTree {
+        root: PathBuf,
+    }
+
+    impl ProcTree {
+        fn new(name: &str, parent: u32) -> Self {
+            let root = env::temp_dir().join(format!("bridge-proc-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            let tree = Self { root };
+            tree.write("self", "sidecar", parent, Some(&["/usr/lib/bridge/bridge-sidecar"]));
+            tree
+        }
+
+        /// Adds a process; `argv` `None` leaves out `cmdline` entirely.
+        fn process(&self, pid: u32, comm: &str, ppid: u32, argv: Option<&[&str]>) -> &Self {
+            self.write(&pid.to_string(), comm, ppid, argv);
+            self
+        }
+
+        fn write(&self, dir: &str, comm: &str, ppid: u32, argv: Option<&[&str]>) {
+            let dir = self.root.join(dir);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("stat"), format!("1234 ({comm}) S {ppid} 1 1 0 -1 4194304")).unwrap();
+            if let Some(argv) = argv {
+                let cmdline: Vec<u8> = argv.iter().flat_map(|arg| arg.bytes().chain([0])).collect();
+                fs::write(dir.join("cmdline"), cmdline).unwrap();
+            }
+        }
+
+        fn detect(&self) -> Option<&'static str> {
+            detect_from_procfs(&self.root)
+        }
+    }
+
+    impl Drop for ProcTree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn walks_the_parent_chain_to_the_browser() {
+        let tree = ProcTree::new("chain", 300);
+        tree.process(300, "sh", 200, Some(&["/bin/sh", "-c", "bridge-sidecar"]))
+            .process(200, "chrome", 100, Some(&["/opt/google/chrome/chrome", "--type=utility"]))
+            .process(100, "systemd", 1, Some(&["/usr/lib/systemd/systemd", "--user"]));
+        assert_eq!(tree.detect(), Some("Chrome"));
+        assert_eq!(executable_name(&tree.root, 300).as_deref(), Some("sh"));
+    }
+
+    #[test]
+    fn recognises_renamed_and_wrapped_browsers() {
+        let tree = ProcTree::new("renamed", 200);
+        tree.process(200, "Web Content", 1, Some(&["/usr/lib/firefox/firefox-custom-build"]));
+        assert_eq!(tree.detect(), Some("Firefox"));
+
+        let tree = ProcTree::new("flatpak", 200);
+        tree.process(200, "bwrap", 1, Some(&["bwrap", "--args", "41", "org.chromium.Chromium/x86_64/stable"]));
+        assert_eq!(tree.detect(), Some("Chromium"));
+
+        let tree = ProcTree::new("case", 200);
+        tree.process(200, "Brave-Browser", 1, Some(&["/usr/bin/Brave-Browser"]));
+        assert_eq!(tree.detect(), Some("Brave"));
+    }
+
+    #[test]
+    fn falls_back_to_comm_without_a_command_line() {
+        let tree = ProcTree::new("comm", 200);
+        tree.process(200, "msedge", 1, None);
+        assert_eq!(tree.detect(), Some("Edge"));
+        assert_eq!(executable_name(&tree.root, 200).as_deref(), Some("msedge"));
+
+        // Parentheses and spaces in comm do not shift the parent pid
+        let tree = ProcTree::new("parens", 300);
+        tree.process(300, "a) (b", 200, None).process(200, "vivaldi-bin", 1, None);
+        assert_eq!(tree.detect(), Some("Vivaldi"));
+    }
+
+    #[test]
+    fn gives_up_on_missing_processes_and_unknown_trees() {
+        // The parent has already exited
+        let tree = ProcTree::new("missing", 200);
+        assert_eq!(tree.detect(), None);
+        assert_eq!(executable_name(&tree.root, 200), None);
+
+        // Reaches init without meeting a browser
+        let tree = ProcTree::new("unknown", 200);
+        tree.process(200, "bash", 1, Some(&["/bin/bash"]));
+        assert_eq!(tree.detect(), None);
+
+        // No `self` at all
+        let empty = env::temp_dir().join(format!("bridge-proc-empty-{}", std::process::id()));
+        assert_eq!(detect_from_procfs(&empty), None);
+    }
+
+    #[test]
+    fn stops_walking_a_parent_loop() {
+        let tree = ProcTree::new("loop", 200);
+        tree.process(200, "bash", 300, Some(&["/bin/bash"]))
+            .process(300, "bash", 200, Some(&["/bin/bash"]));
+        assert_eq!(tree.detect(), None);
+    }
+}
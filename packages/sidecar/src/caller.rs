@@ -0,0 +1,475 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Browser families, told apart by how they pass the caller to a native messaging host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CallerFamily {
+    /// Chrome, Edge, Brave and the other Chromium browsers: `chrome-extension://<id>/`, followed
+    /// on Windows by `--parent-window=<hwnd>`.
+    Chromium,
+    /// Firefox: the path to the host manifest, then the add-on id.
+    Firefox,
+}
+
+/// The extension that launched this host, parsed from the command line the browser passes.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Caller {
+    pub family: CallerFamily,
+    /// Checked against the browser's id format, so it can be trusted to identify the extension.
+    pub extension_id: String,
+    /// `chrome-extension://<id>/`; Firefox passes no origin.
+    pub origin: Option<String>,
+    /// Firefox only: the native messaging manifest the host was started from.
+    pub manifest: Option<PathBuf>,
+    /// Chromium on Windows only: the window that called `connectNative`, `0` for background pages.
+    pub parent_window: Option<i64>,
+}
+
+impl Caller {
+    /// Parses the arguments after the program name; `None` when they follow neither browser's
+    /// convention, e.g. when the host is started by hand.
+    pub fn from_args(args: &[String]) -> Option<Self> {
+        if let Some(origin) = args.iter().find(|arg| arg.starts_with(CHROMIUM_SCHEME)) {
+            let id = origin[CHROMIUM_SCHEME.len()..].trim_end_matches('/');
+            if !is_chromium_id(id) {
+                eprintln!("[sidecar] ignoring caller origin with malformed extension id: {origin}");
+                return None;
+            }
+            let parent_window = args
+                .iter()
+                .find_map(|arg| arg.strip_prefix("--parent-window="))
+                .and_then(|hwnd| hwnd.parse().ok());
+            return Some(Self {
+                family: CallerFamily::Chromium,
+                extension_id: id.to_string(),
+                origin: Some(origin.clone()),
+                manifest: None,
+                parent_window,
+            });
+        }
+
+        match args {
+            [manifest, id, ..] if manifest.ends_with(".json") => {
+                if !is_gecko_id(id) {
+                    eprintln!("[sidecar] ignoring caller with malformed add-on id: {id}");
+                    return None;
+                }
+                Some(Self {
+                    family: CallerFamily::Firefox,
+                    extension_id: id.clone(),
+                    origin: None,
+                    manifest: Some(PathBuf::from(manifest)),
+                    parent_window: None,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+const CHROMIUM_SCHEME: &str = "chrome-extension://";
+
+/// File name of the allow-list looked up next to the executable.
+const ALLOWLIST_FILE: &str = "allowed-extensions.json";
+
+/// Extension ids allowed to launch this host. The browser's own manifest check can be bypassed
+/// by a stale manifest that reuses the host name, so the sidecar checks again.
+#[derive(Debug, Clone)]
+pub struct AllowList {
+    ids: BTreeSet<String>,
+    /// Where the ids came from, for error messages.
+    sources: Vec<String>,
+}
+
+impl AllowList {
+    /// Reads `BRIDGE_ALLOWED_EXTENSIONS` (comma separated) and the JSON array in
+    /// `allowed-extensions.json` next to the executable, or in `BRIDGE_ALLOWLIST`. Entries may be
+    /// bare ids or `chrome-extension://<id>/` origins as in the manifests. `None` when neither is
+    /// configured, in which case every caller is accepted.
+    pub fn load() -> Result<Option<Self>> {
+        let beside_exe = env::current_exe().ok().and_then(|exe| Some(exe.parent()?.join(ALLOWLIST_FILE)));
+        Self::from_vars(|name| env::var(name).ok(), beside_exe)
+    }
+
+    /// `load` with the environment read through `var` and the file next to the executable at
+    /// `default_file`.
+    fn from_vars(var: impl Fn(&str) -> Option<String>, default_file: Option<PathBuf>) -> Result<Option<Self>> {
+        let mut list = Self {
+            ids: BTreeSet::new(),
+            sources: Vec::new(),
+        };
+        if let Some(value) = var("BRIDGE_ALLOWED_EXTENSIONS") {
+            list.extend(value.split(','));
+            list.sources.push("BRIDGE_ALLOWED_EXTENSIONS".to_string());
+        }
+
+        let (path, explicit) = match var("BRIDGE_ALLOWLIST") {
+            Some(path) => (Some(PathBuf::from(path)), true),
+            None => (default_file, false),
+        };
+        if let Some(path) = path.filter(|path| explicit || path.exists()) {
+            let raw = fs::read_to_string(&path).with_context(|| format!("reading allow-list {}", path.display()))?;
+            let entries: Vec<String> = serde_json::from_str(&raw)
+                .with_context(|| format!("allow-list {} must be a JSON array of extension ids", path.display()))?;
+            list.extend(entries.iter().map(String::as_str));
+            list.sources.push(path.display().to_string());
+        }
+
+        Ok((!list.sources.is_empty()).then_some(list))
+    }
+
+    fn extend<'a>(&mut self, entries: impl Iterator<Item = &'a str>) {
+        let ids = entries
+            .map(|entry| entry.trim().trim_start_matches(CHROMIUM_SCHEME).trim_end_matches('/'))
+            .filter(|id| !id.is_empty())
+            .map(str::to_string);
+        self.ids.extend(ids);
+    }
+
+    /// A host started without caller arguments cannot be attributed to an extension and is refused.
+    pub fn permits(&self, caller: Option<&Caller>) -> bool {
+        caller.is_some_and(|caller| self.ids.contains(&caller.extension_id))
+    }
+
+    pub fn sources(&self) -> &[String] {
+        &self.sources
+    }
+}
+
+/// Decides whether `caller` may use the bridge under the allow-list `loaded`.
+///
+/// Returns where the allow-list came from, empty when none is configured, or why the caller is
+/// refused; an allow-list that cannot be read refuses everyone.
+pub fn vet_caller(loaded: Result<Option<AllowList>>, caller: Option<&Caller>) -> Result<Vec<String>, String> {
+    match loaded {
+        Ok(None) => {
+            eprintln!(
+                "[sidecar] warning: no extension allow-list configured (BRIDGE_ALLOWED_EXTENSIONS, BRIDGE_ALLOWLIST \
+                 or {ALLOWLIST_FILE} next to the executable); any extension may connect"
+            );
+            Ok(Vec::new())
+        }
+        Ok(Some(list)) if list.permits(caller) => Ok(list.sources().to_vec()),
+        Ok(Some(list)) => Err(format!(
+            "caller {} is not in the allow-list ({})",
+            caller.map_or("<none>", |caller| caller.extension_id.as_str()),
+            list.sources().join(", ")
+        )),
+        Err(err) => Err(format!("{err:#}")),
+    }
+}
+
+/// The `error.caller_not_allowed` envelope telling the extension why it was refused.
+pub fn caller_not_allowed(caller: Option<&Caller>, refusal: &str) -> String {
+    json!({
+        "v": 1,
+        "type": "error.caller_not_allowed",
+        "payload": {
+            "extensionId": caller.map(|caller| &caller.extension_id),
+            "origin": caller.and_then(|caller| caller.origin.as_ref()),
+            "message": refusal,
+        }
+    })
+    .to_string()
+}
+
+/// Chromium extension ids are 32 characters from `a` to `p`.
+fn is_chromium_id(id: &str) -> bool {
+    id.len() == 32 && id.bytes().all(|b| (b'a'..=b'p').contains(&b))
+}
+
+/// Gecko add-on ids are either a braced UUID or email-like (`name@domain`).
+fn is_gecko_id(id: &str) -> bool {
+    let email_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_');
+    if let Some(uuid) = id.strip_prefix('{').and_then(|rest| rest.strip_suffix('}')) {
+        return uuid.len() == 36 && uuid.chars().all(|c| c.is_ascii_hexdigit() || c == '-');
+    }
+    match id.split_once('@') {
+        Some((name, domain)) => !domain.is_empty() && name.chars().all(email_char) && domain.chars().all(email_char),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHROME_ID: &str = "knldjmfmopnpolahpmmgbagdohdnhkik";
+
+    fn args(argv: &[&str]) -> Vec<String> {
+        argv.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn chromium_callers_are_read_from_the_origin() {
+        let origin = format!("chrome-extension://{CHROME_ID}/");
+        let cases: &[(&[&str], Option<i64>)] = &[
+            // Linux and macOS
+            (&[&origin], None),
+            // Windows adds the calling window; `0` is a background page
+            (&[&origin, "--parent-window=0"], Some(0)),
+            (&[&origin, "--parent-window=132456"], Some(132456)),
+            (&[&origin, "--parent-window=hwnd"], None),
+            // The origin need not come first
+            (&["--parent-window=7", &origin], Some(7)),
+        ];
+        for (argv, parent_window) in cases {
+            let caller = Caller::from_args(&args(argv)).unwrap_or_else(|| panic!("{argv:?} not parsed"));
+            assert_eq!(caller.family, CallerFamily::Chromium);
+            assert_eq!(caller.extension_id, CHROME_ID);
+            assert_eq!(caller.origin.as_deref(), Some(origin.as_str()));
+            assert_eq!(caller.manifest, None);
+            assert_eq!(caller.parent_window, *parent_window, "{argv:?}");
+        }
+
+        // The trailing slash is optional
+        let bare = Caller::from_args(&args(&[&format!("chrome-extension://{CHROME_ID}")])).unwrap();
+        assert_eq!(bare.extension_id, CHROME_ID);
+    }
+
+    #[test]
+    fn firefox_callers_are_read_from_the_manifest_and_addon_id() {
+        let manifest = "/home/user/.mozilla/native-messaging-hosts/com.example.bridge.json";
+        let cases = [
+            "bridge@example.com",
+            "{d3b07384-d9a0-4c3b-9e6b-2f1e3a7c5b90}",
+            "{D3B07384-D9A0-4C3B-9E6B-2F1E3A7C5B90}",
+        ];
+        for id in cases {
+            let caller = Caller::from_args(&args(&[manifest, id])).unwrap_or_else(|| panic!("{id} not parsed"));
+            assert_eq!(caller.family, CallerFamily::Firefox);
+            assert_eq!(caller.extension_id, id);
+            assert_eq!(caller.origin, None);
+            assert_eq!(caller.manifest, Some(PathBuf::from(manifest)));
+            assert_eq!(caller.parent_window, None);
+        }
+
+        // Windows passes the manifest path as registered
+        let windows = Caller::from_args(&args(&[r"C:\Program Files\Bridge\com.example.bridge.json", "bridge@example.com"]));
+        assert_eq!(windows.unwrap().family, CallerFamily::Firefox);
+    }
+
+    #[test]
+    fn malformed_or_missing_callers_are_not_trusted() {
+        let cases: &[&[&str]] = &[
+            &[],
+            &["--verbose"],
+            &["chrome-extension://"],
+            &["chrome-extension://knldjmfmopnpolahpmmgbagdohdnhki/"],
+            &["chrome-extension://KNLDJMFMOPNPOLAHPMMGBAGDOHDNHKIK/"],
+            &["chrome-extension://knldjmfmopnpolahpmmgbagdohdnhkiq/"],
+            &["chrome-extension://knldjmfmopnpolahpmmgbagdohdnhkik/background.html"],
+            // A malformed origin is not rescued by Firefox-style arguments after it
+            &["chrome-extension://evil/", "bridge@example.com"],
+            &["/tmp/com.example.bridge.json"],
+            &["/tmp/com.example.bridge.txt", "bridge@example.com"],
+            &["/tmp/com.example.bridge.json", "bridge"],
+            &["/tmp/com.example.bridge.json", "{not-a-uuid}"],
+            &["moz-extension://bridge@example.com/", "bridge@example.com"],
+        ];
+        for argv in cases {
+            assert!(Caller::from_args(&args(argv)).is_none(), "{argv:?} was trusted");
+        }
+    }
+
+    #[test]
+    fn chromium_ids_are_32_letters_from_a_to_p() {
+        let cases = [
+            (CHROME_ID, true),
+            ("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa", true),
+            ("pppppppppppppppppppppppppppppppp", true),
+            ("", false),
+            ("knldjmfmopnpolahpmmgbagdohdnhki", false),
+            ("knldjmfmopnpolahpmmgbagdohdnhkikk", false),
+            ("Knldjmfmopnpolahpmmgbagdohdnhkik", false),
+            ("qnldjmfmopnpolahpmmgbagdohdnhkik", false),
+            ("0nldjmfmopnpolahpmmgbagdohdnhkik", false),
+            ("knldjmfmopnpolahpmmgbagdohdnhkí", false),
+        ];
+        for (id, valid) in cases {
+            assert_eq!(is_chromium_id(id), valid, "{id:?}");
+        }
+    }
+
+    #[test]
+    fn gecko_ids_are_braced_uuids_or_email_like() {
+        let cases = [
+            ("bridge@example.com", true),
+            ("Bridge.Tab-Saver_2@Example.ORG", true),
+            // Firefox accepts an empty local part
+            ("@example.com", true),
+            ("{d3b07384-d9a0-4c3b-9e6b-2f1e3a7c5b90}", true),
+            ("{D3B07384-D9A0-4C3B-9E6B-2F1E3A7C5B90}", true),
+            ("", false),
+            ("bridge", false),
+            ("bridge@", false),
+            ("a@b@c", false),
+            ("bridge tab@example.com", false),
+            ("bridge@example.com/", false),
+            ("d3b07384-d9a0-4c3b-9e6b-2f1e3a7c5b90", false),
+            ("{d3b07384-d9a0-4c3b-9e6b-2f1e3a7c5b9}", false),
+            ("{g3b07384-d9a0-4c3b-9e6b-2f1e3a7c5b90}", false),
+            ("{d3b07384-d9a0-4c3b-9e6b-2f1e3a7c5b90", false),
+        ];
+        for (id, valid) in cases {
+            assert_eq!(is_gecko_id(id), valid, "{id:?}");
+        }
+    }
+
+    /// A throwaway directory for allow-list files, removed on drop.
+    struct Scratch {
+        root: PathBuf,
+    }
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            let root = env::temp_dir().join(format!("bridge-allowlist-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+            Self { root }
+        }
+
+        fn file(&self, name: &str, contents: &str) -> PathBuf {
+            let path = self.root.join(name);
+            fs::write(&path, contents).unwrap();
+            path
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn load(vars: &[(&str, &str)], default_file: Option<PathBuf>) -> Result<Option<AllowList>> {
+        AllowList::from_vars(
+            |name| vars.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_string()),
+            default_file,
+        )
+    }
+
+    fn chrome(id: &str) -> Caller {
+        Caller::from_args(&args(&[&format!("chrome-extension://{id}/")])).unwrap()
+    }
+
+    fn firefox(id: &str) -> Caller {
+        Caller::from_args(&args(&["/tmp/com.example.bridge.json", id])).unwrap()
+    }
+
+    const OTHER_ID: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+
+    #[test]
+    fn no_configuration_means_no_allow_list() {
+        let scratch = Scratch::new("none");
+        assert!(load(&[], None).unwrap().is_none());
+        assert!(load(&[], Some(scratch.root.join("allowed-extensions.json"))).unwrap().is_none());
+        assert!(vet_caller(load(&[], None), None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn the_environment_lists_ids_or_origins() {
+        let origin = format!(" chrome-extension://{CHROME_ID}/ ");
+        let value = format!("bridge@example.com,{origin},,");
+        let list = load(&[("BRIDGE_ALLOWED_EXTENSIONS", &value)], None).unwrap().unwrap();
+        assert_eq!(list.sources(), ["BRIDGE_ALLOWED_EXTENSIONS"]);
+        assert!(list.permits(Some(&chrome(CHROME_ID))));
+        assert!(list.permits(Some(&firefox("bridge@example.com"))));
+        assert!(!list.permits(Some(&chrome(OTHER_ID))));
+        assert!(!list.permits(Some(&firefox("Bridge@example.com"))));
+        // A host started by hand names no extension
+        assert!(!list.permits(None));
+
+        // Set but empty still configures a list, one that admits nobody
+        let empty = load(&[("BRIDGE_ALLOWED_EXTENSIONS", "")], None).unwrap().unwrap();
+        assert!(!empty.permits(Some(&chrome(CHROME_ID))));
+    }
+
+    #[test]
+    fn files_add_to_the_environment_and_an_explicit_one_replaces_the_default() {
+        let scratch = Scratch::new("files");
+        let beside_exe = scratch.file("allowed-extensions.json", &format!(r#"["chrome-extension://{OTHER_ID}/"]"#));
+        let explicit = scratch.file("explicit.json", r#"["bridge@example.com"]"#);
+
+        let list = load(&[], Some(beside_exe.clone())).unwrap().unwrap();
+        assert_eq!(list.sources(), [beside_exe.display().to_string()]);
+        assert!(list.permits(Some(&chrome(OTHER_ID))));
+
+        let list = load(
+            &[("BRIDGE_ALLOWED_EXTENSIONS", CHROME_ID), ("BRIDGE_ALLOWLIST", &explicit.display().to_string())],
+            Some(beside_exe),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(list.sources(), ["BRIDGE_ALLOWED_EXTENSIONS".to_string(), explicit.display().to_string()]);
+        assert!(list.permits(Some(&chrome(CHROME_ID))));
+        assert!(list.permits(Some(&firefox("bridge@example.com"))));
+        assert!(!list.permits(Some(&chrome(OTHER_ID))), "the file beside the executable was read too");
+    }
+
+    #[test]
+    fn unreadable_allow_lists_are_errors() {
+        let scratch = Scratch::new("errors");
+        let missing = scratch.root.join("missing.json").display().to_string();
+        let err = load(&[("BRIDGE_ALLOWLIST", &missing)], None).unwrap_err();
+        assert!(format!("{err:#}").contains("reading allow-list"), "{err:#}");
+
+        for contents in ["{\"ids\": []}", "[1, 2]", "not json"] {
+            let path = scratch.file("bad.json", contents).display().to_string();
+            let err = load(&[("BRIDGE_ALLOWLIST", &path)], None).unwrap_err();
+            assert!(format!("{err:#}").contains("must be a JSON array of extension ids"), "{contents}: {err:#}");
+            // Neither is a file beside the executable silently skipped when it is broken
+            let err = load(&[], Some(PathBuf::from(&path))).unwrap_err();
+            assert!(format!("{err:#}").contains("must be a JSON array"), "{err:#}");
+        }
+    }
+
+    #[test]
+    fn callers_outside_the_list_are_refused_with_the_reason() {
+        let vars = [("BRIDGE_ALLOWED_EXTENSIONS", CHROME_ID)];
+        let allowed = chrome(CHROME_ID);
+        assert_eq!(vet_caller(load(&vars, None), Some(&allowed)).unwrap(), ["BRIDGE_ALLOWED_EXTENSIONS"]);
+
+        let refusal = vet_caller(load(&vars, None), Some(&chrome(OTHER_ID))).unwrap_err();
+        assert_eq!(refusal, format!("caller {OTHER_ID} is not in the allow-list (BRIDGE_ALLOWED_EXTENSIONS)"));
+        let refusal = vet_caller(load(&vars, None), None).unwrap_err();
+        assert_eq!(refusal, "caller <none> is not in the allow-list (BRIDGE_ALLOWED_EXTENSIONS)");
+
+        let broken = vet_caller(Err(anyhow::anyhow!("allow-list x must be a JSON array")), Some(&allowed));
+        assert_eq!(broken.unwrap_err(), "allow-list x must be a JSON array");
+    }
+
+    #[test]
+    fn refusals_tell_the_extension_who_was_refused() {
+        let caller = chrome(OTHER_ID);
+        let envelope: serde_json::Value = serde_json::from_str(&caller_not_allowed(Some(&caller), "not listed")).unwrap();
+        assert_eq!(
+            envelope,
+            json!({
+                "v": 1,
+                "type": "error.caller_not_allowed",
+                "payload": {
+                    "extensionId": OTHER_ID,
+                    "origin": format!("chrome-extension://{OTHER_ID}/"),
+                    "message": "not listed",
+                }
+            })
+        );
+
+        let firefox_caller = firefox("bridge@example.com");
+        let envelope: serde_json::Value =
+            serde_json::from_str(&caller_not_allowed(Some(&firefox_caller), "no")).unwrap();
+        assert_eq!(envelope["payload"]["extensionId"], "bridge@example.com");
+        assert_eq!(envelope["payload"]["origin"], serde_json::Value::Null);
+
+        let envelope: serde_json::Value = serde_json::from_str(&caller_not_allowed(None, "no")).unwrap();
+        assert_eq!(envelope["payload"]["extensionId"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,327 @@
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use bridge_debug::debug_hub::{self, DebugHub};
+
+/// Which side of the sidecar a control message arrived from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// Native messaging on stdin.
+    Extension,
+    /// The app WebSocket.
+    App,
+    /// A client of the debug WebSocket.
+    Debug,
+}
+
+/// Every source, for handlers that answer whoever asks.
+pub const ANY_SOURCE: &[Source] = &[Source::Extension, Source::App, Source::Debug];
+
+/// Where a handler's reply goes. Debug clients always get the reply themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplyTo {
+    pub extension: bool,
+    pub app: bool,
+}
+
+impl ReplyTo {
+    /// Both ends of the bridge, e.g. results the app wants to observe as well.
+    pub const EXTENSION_AND_APP: Self = Self {
+        extension: true,
+        app: true,
+    };
+
+    /// Back to the side the message came from.
+    pub fn sender(source: Source) -> Self {
+        Self {
+            extension: source == Source::Extension,
+            app: source == Source::App,
+        }
+    }
+}
+
+/// What a handler did with a message.
+#[derive(Debug, Clone)]
+pub struct Handled {
+    pub reply: Option<String>,
+    pub reply_to: ReplyTo,
+    /// Whether the message also continues to the other side of the bridge.
+    pub forward: bool,
+}
+
+impl Handled {
+    /// Answers `reply_to`; the message stops here.
+    pub fn reply(reply: String, reply_to: ReplyTo) -> Self {
+        Self {
+            reply: Some(reply),
+            reply_to,
+            forward: false,
+        }
+    }
+
+    /// Only observed; the message travels on unchanged.
+    pub fn forward() -> Self {
+        Self {
+            reply: None,
+            reply_to: ReplyTo {
+                extension: false,
+                app: false,
+            },
+            forward: true,
+        }
+    }
+}
+
+/// What a handler gets besides the parsed envelope.
+pub struct ControlContext<'a> {
+    pub source: Source,
+    /// Whether the sender may change sidecar state rather than only query it: the app, or a
+    /// debug client that connected in inject mode with the bridge secret.
+    pub privileged: bool,
+    /// This sidecar's connection.
+    pub connection_id: &'a str,
+    pub hub: &'a DebugHub,
+    /// The message as received.
+    pub raw: &'a str,
+}
+
+/// Plain functions, or closures holding the state a handler reports on.
+pub type Handler = Box<dyn Fn(&ControlContext, &Value) -> Result<Handled> + Send + Sync>;
+
+struct Registration {
+    sources: &'static [Source],
+    /// The handler may wait, e.g. on the windowing system.
+    blocking: bool,
+    handler: Handler,
+}
+
+/// Sidecar-local handlers by envelope `type`. Modules register theirs at startup; messages of
+/// any other type pass through the sidecar untouched.
+#[derive(Default)]
+pub struct ControlRegistry {
+    handlers: HashMap<&'static str, Registration>,
+}
+
+impl ControlRegistry {
+    /// Handles `message_type` when it arrives from one of `sources`. A later registration for
+    /// the same type replaces the earlier one.
+    pub fn register(
+        &mut self,
+        message_type: &'static str,
+        sources: &'static [Source],
+        handler: impl Fn(&ControlContext, &Value) -> Result<Handled> + Send + Sync + 'static,
+    ) {
+        self.insert(message_type, sources, false, Box::new(handler));
+    }
+
+    /// Like `register`, for handlers that may take a while; readers run them off their own
+    /// thread so the messages behind are not held up, see `blocks`.
+    pub fn register_blocking(
+        &mut self,
+        message_type: &'static str,
+        sources: &'static [Source],
+        handler: impl Fn(&ControlContext, &Value) -> Result<Handled> + Send + Sync + 'static,
+    ) {
+        self.insert(message_type, sources, true, Box::new(handler));
+    }
+
+    fn insert(&mut self, message_type: &'static str, sources: &'static [Source], blocking: bool, handler: Handler) {
+        let registration = Registration {
+            sources,
+            blocking,
+            handler,
+        };
+        if self.handlers.insert(message_type, registration).is_some() {
+            eprintln!("[sidecar] control handler for {message_type} replaced");
+        }
+    }
+
+    /// Whether `message` goes to a handler registered with `register_blocking`.
+    pub fn blocks(&self, message: &str) -> bool {
+        let Ok(value) = serde_json::from_str::<Value>(message) else {
+            return false;
+        };
+        value
+            .get("type")
+            .and_then(|t| t.as_str())
+            .and_then(|message_type| self.handlers.get(message_type))
+            .is_some_and(|registration| registration.blocking)
+    }
+
+    /// Runs the handler for `message`'s type, or returns `None` when there is none for this
+    /// source, the message is not an envelope, or the handler failed; the caller then passes
+    /// the message on as before. `inject` says whether a debug sender connected in inject mode.
+    pub fn dispatch(
+        &self,
+        source: Source,
+        inject: bool,
+        connection_id: &str,
+        hub: &DebugHub,
+        message: &str,
+    ) -> Option<Handled> {
+        let value: Value = serde_json::from_str(message).ok()?;
+        let message_type = value.get("type").and_then(|t| t.as_str())?;
+        let registration = self.handlers.get(message_type)?;
+        if !registration.sources.contains(&source) {
+            return None;
+        }
+
+        let context = ControlContext {
+            source,
+            privileged: source == Source::App || (source == Source::Debug && inject),
+            connection_id,
+            hub,
+            raw: message,
+        };
+        match (registration.handler)(&context, &value) {
+            Ok(handled) => Some(handled),
+            Err(err) => {
+                eprintln!("[sidecar] {message_type} handler failed: {err:#}");
+                None
+            }
+        }
+    }
+}
+
+/// Registers the traffic capture commands. Every source may query the capture, only privileged
+/// ones may start or stop it.
+pub fn register_debug_handlers(registry: &mut ControlRegistry) {
+    registry.register("debug.capture", ANY_SOURCE, |context, _| {
+        Ok(match debug_hub::handle_capture_command(context.hub, context.raw, context.privileged) {
+            Some(reply) => Handled::reply(reply, ReplyTo::sender(context.source)),
+            None => Handled::forward(),
+        })
+    });
+}
+
+/// The payload of `request`, `{}` when it has none.
+pub fn payload(request: &Value) -> Value {
+    request.get("payload").cloned().unwrap_or_else(|| json!({}))
+}
+
+/// A `result_type` reply to `request`, carrying the request's `id` (as envelope `id` and
+/// `requestId`) and the `echoed` request payload fields so the receivers can match it up.
+pub fn result_envelope(
+    result_type: &str,
+    request: &Value,
+    request_payload: &Value,
+    echoed: &[&str],
+    outcome: &impl Serialize,
+) -> Result<String> {
+    let mut payload = serde_json::to_value(outcome)?;
+    payload["requestId"] = request.get("id").cloned().unwrap_or_default();
+    for &key in echoed {
+        payload[key] = request_payload.get(key).cloned().unwrap_or_default();
+    }
+    Ok(reply_envelope(result_type, request, payload))
+}
+
+/// A `reply_type` envelope with `payload`, carrying the request's `id` when it had one.
+pub fn reply_envelope(reply_type: &str, request: &Value, payload: Value) -> String {
+    let mut envelope = json!({ "v": 1, "type": reply_type, "payload": payload });
+    if let Some(id) = request.get("id") {
+        envelope["id"] = id.clone();
+    }
+    envelope.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bridge_debug::Role;
+
+    const EXTENSION: &[Source] = &[Source::Extension];
+
+    /// Echoes the source and privilege it was called with.
+    fn echo(context: &ControlContext, request: &Value) -> Result<Handled> {
+        let payload = json!({ "source": format!("{:?}", context.source), "privileged": context.privileged });
+        Ok(Handled::reply(reply_envelope("test.echoed", request, payload), ReplyTo::sender(context.source)))
+    }
+
+    fn registry() -> ControlRegistry {
+        let mut registry = ControlRegistry::default();
+        registry.register("test.echo", ANY_SOURCE, echo);
+        registry.register("test.extension", EXTENSION, |_, _| Ok(Handled::forward()));
+        registry.register("test.fail", ANY_SOURCE, |_, _| anyhow::bail!("broken"));
+        registry.register_blocking("test.slow", EXTENSION, |_, _| Ok(Handled::forward()));
+        registry
+    }
+
+    fn dispatch(registry: &ControlRegistry, source: Source, inject: bool, message: &str) -> Option<Handled> {
+        let hub = DebugHub::new(Role::Sidecar);
+        registry.dispatch(source, inject, "conn-1", &hub, message)
+    }
+
+    #[test]
+    fn routes_by_type_and_answers_the_sender() {
+        let registry = registry();
+        for (source, extension, app) in [(Source::Extension, true, false), (Source::App, false, true), (Source::Debug, false, false)] {
+            let handled = dispatch(&registry, source, false, r#"{"v":1,"id":"r1","type":"test.echo"}"#).unwrap();
+            assert!(!handled.forward);
+            assert_eq!(handled.reply_to, ReplyTo { extension, app });
+            let reply: Value = serde_json::from_str(&handled.reply.unwrap()).unwrap();
+            assert_eq!(reply["type"], "test.echoed");
+            assert_eq!(reply["id"], "r1");
+            assert_eq!(reply["payload"]["source"], format!("{source:?}"));
+        }
+    }
+
+    #[test]
+    fn only_the_app_and_inject_mode_debug_clients_are_privileged() {
+        let registry = registry();
+        let privileged = |source, inject| {
+            let handled = dispatch(&registry, source, inject, r#"{"v":1,"type":"test.echo"}"#).unwrap();
+            let reply: Value = serde_json::from_str(&handled.reply.unwrap()).unwrap();
+            reply["payload"]["privileged"].as_bool().unwrap()
+        };
+        assert!(privileged(Source::App, false));
+        assert!(privileged(Source::Debug, true));
+        assert!(!privileged(Source::Debug, false));
+        // Inject mode only means something for debug clients
+        assert!(!privileged(Source::Extension, true));
+    }
+
+    #[test]
+    fn ignores_messages_from_other_sources() {
+        let registry = registry();
+        let message = r#"{"v":1,"type":"test.extension"}"#;
+        assert!(dispatch(&registry, Source::Extension, false, message).is_some_and(|handled| handled.forward));
+        assert!(dispatch(&registry, Source::App, false, message).is_none());
+        assert!(dispatch(&registry, Source::Debug, true, message).is_none());
+    }
+
+    #[test]
+    fn passes_everything_else_through() {
+        let registry = registry();
+        for message in [
+            r#"{"v":1,"type":"tabs.openOrFocus","payload":{}}"#,
+            r#"{"v":1,"payload":{}}"#,
+            r#"{"v":1,"type":42}"#,
+            r#"["test.echo"]"#,
+            "not json",
+            // A failing handler leaves the message to the caller as well
+            r#"{"v":1,"type":"test.fail"}"#,
+        ] {
+            assert!(dispatch(&registry, Source::App, false, message).is_none(), "{message}");
+        }
+    }
+
+    #[test]
+    fn later_registrations_replace_earlier_ones() {
+        let mut registry = registry();
+        registry.register("test.echo", EXTENSION, |_, _| Ok(Handled::forward()));
+        assert!(dispatch(&registry, Source::App, false, r#"{"type":"test.echo"}"#).is_none());
+        assert!(dispatch(&registry, Source::Extension, false, r#"{"type":"test.echo"}"#).is_some_and(|handled| handled.forward));
+    }
+
+    #[test]
+    fn only_blocking_registrations_block() {
+        let registry = registry();
+        assert!(registry.blocks(r#"{"v":1,"type":"test.slow"}"#));
+        assert!(!registry.blocks(r#"{"v":1,"type":"test.echo"}"#));
+        assert!(!registry.blocks(r#"{"v":1,"type":"tabs.openOrFocus"}"#));
+        assert!(!registry.blocks("not json"));
+    }
+}
@@ -0,0 +1,190 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+use super::WindowHandle;
+
+/// Entries kept before the least recently used one is evicted.
+pub const DEFAULT_CAPACITY: usize = 256;
+
+/// A browser window: window ids restart per browser and profile, so they are only unique
+/// together with the connection that reported them.
+pub type WindowKey = (String, i32);
+
+/// How a cached handle was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheSource {
+    /// Best scoring window for a `focus.window`; may be wrong when windows look alike.
+    Search,
+    /// The window carrying the extension's `window.mark` marker; exact.
+    Marker,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    window: WindowHandle,
+    source: CacheSource,
+    /// Tick of the last lookup or insert, for LRU eviction.
+    used: u64,
+}
+
+/// Native handles of browser windows focused before, by `(connectionId, windowId)`. Entries go
+/// when the window closes, its connection goes offline, or the cache is full.
+#[derive(Debug)]
+pub struct WindowCache {
+    entries: HashMap<WindowKey, Entry>,
+    capacity: usize,
+    tick: u64,
+}
+
+/// One cached window, as reported by `focus.cache.state`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedWindow {
+    pub connection_id: String,
+    pub window_id: i32,
+    pub window: WindowHandle,
+    pub source: CacheSource,
+}
+
+impl Default for WindowCache {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl WindowCache {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            capacity: capacity.max(1),
+            tick: 0,
+        }
+    }
+
+    pub fn get(&mut self, key: &WindowKey) -> Option<WindowHandle> {
+        self.tick += 1;
+        let entry = self.entries.get_mut(key)?;
+        entry.used = self.tick;
+        Some(entry.window)
+    }
+
+    pub fn insert(&mut self, key: WindowKey, window: WindowHandle, source: CacheSource) {
+        self.tick += 1;
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            let oldest = self.entries.iter().min_by_key(|(_, entry)| entry.used).map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                eprintln!("[sidecar] focus cache full, evicting {oldest:?}");
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(key, Entry { window, source, used: self.tick });
+    }
+
+    pub fn remove(&mut self, key: &WindowKey) -> Option<WindowHandle> {
+        self.entries.remove(key).map(|entry| entry.window)
+    }
+
+    /// Drops every window of `connection_id`; returns how many there were.
+    pub fn forget_connection(&mut self, connection_id: &str) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|(connection, _), _| connection != connection_id);
+        before - self.entries.len()
+    }
+
+    pub fn clear(&mut self) -> usize {
+        let removed = self.entries.len();
+        self.entries.clear();
+        removed
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Entries, most recently used first.
+    pub fn entries(&self) -> Vec<CachedWindow> {
+        let mut entries: Vec<(&WindowKey, &Entry)> = self.entries.iter().collect();
+        entries.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.used));
+        entries
+            .into_iter()
+            .map(|((connection_id, window_id), entry)| CachedWindow {
+                connection_id: connection_id.clone(),
+                window_id: *window_id,
+                window: entry.window,
+                source: entry.source,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(connection_id: &str, window_id: i32) -> WindowKey {
+        (connection_id.to_string(), window_id)
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_window_when_full() {
+        let mut cache = WindowCache::with_capacity(3);
+        cache.insert(key("a", 1), 0x10, CacheSource::Search);
+        cache.insert(key("a", 2), 0x20, CacheSource::Search);
+        cache.insert(key("a", 3), 0x30, CacheSource::Marker);
+        // A lookup counts as a use, so window 2 becomes the oldest
+        assert_eq!(cache.get(&key("a", 1)), Some(0x10));
+
+        cache.insert(key("a", 4), 0x40, CacheSource::Search);
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.get(&key("a", 2)), None);
+        let windows: Vec<i32> = cache.entries().iter().map(|entry| entry.window_id).collect();
+        assert_eq!(windows, vec![4, 1, 3]);
+
+        // Replacing a cached window never evicts another
+        cache.insert(key("a", 3), 0x31, CacheSource::Search);
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.get(&key("a", 3)), Some(0x31));
+        assert_eq!(cache.entries()[0].source, CacheSource::Search);
+    }
+
+    #[test]
+    fn capacity_is_at_least_one() {
+        let mut cache = WindowCache::with_capacity(0);
+        assert_eq!(cache.capacity(), 1);
+        cache.insert(key("a", 1), 0x10, CacheSource::Search);
+        cache.insert(key("a", 2), 0x20, CacheSource::Search);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get(&key("a", 2)), Some(0x20));
+    }
+
+    #[test]
+    fn forgetting_a_connection_keeps_the_others() {
+        let mut cache = WindowCache::default();
+        cache.insert(key("a", 1), 0x10, CacheSource::Search);
+        cache.insert(key("a", 2), 0x20, CacheSource::Marker);
+        cache.insert(key("b", 1), 0x30, CacheSource::Search);
+
+        assert_eq!(cache.forget_connection("a"), 2);
+        assert_eq!(cache.forget_connection("a"), 0);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get(&key("b", 1)), Some(0x30));
+    }
+
+    #[test]
+    fn removes_one_window() {
+        let mut cache = WindowCache::default();
+        cache.insert(key("a", 1), 0x10, CacheSource::Search);
+        cache.insert(key("b", 1), 0x20, CacheSource::Search);
+
+        assert_eq!(cache.remove(&key("a", 1)), Some(0x10));
+        assert_eq!(cache.remove(&key("a", 1)), None);
+        assert_eq!(cache.get(&key("b", 1)), Some(0x20));
+        assert_eq!(cache.clear(), 1);
+        assert_eq!(cache.len(), 0);
+    }
+}
@@ -0,0 +1,319 @@
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::sync::Mutex;
+
+use super::{cache_state, focus_window, forget_connection, forget_window, map_marked_window};
+use super::{FocusOutcome, FocusWindowPayload, MarkOutcome, WindowMarkPayload};
+use super::{WindowCache, WINDOW_CACHE};
+use crate::control::{self, ControlContext, ControlRegistry, Handled, ReplyTo, Source, ANY_SOURCE};
+
+/// Requests the extension makes about its own windows.
+const EXTENSION: &[Source] = &[Source::Extension];
+/// Events that make cached handles stale, whichever side reports them.
+const BRIDGE: &[Source] = &[Source::Extension, Source::App];
+
+pub fn register(registry: &mut ControlRegistry) {
+    registry.register("focus.window", EXTENSION, handle_focus_window);
+    // Retries for up to a second while the browser updates the window title
+    registry.register_blocking("window.mark", EXTENSION, handle_window_mark);
+    registry.register("focus.cache", ANY_SOURCE, handle_focus_cache);
+    registry.register("window.closed", BRIDGE, handle_window_closed);
+    registry.register("presence.status", BRIDGE, handle_presence);
+}
+
+/// Requests without a `connectionId` are attributed to this sidecar's connection.
+fn request_payload(context: &ControlContext, request: &Value) -> Value {
+    let mut payload = control::payload(request);
+    if let Some(fields) = payload.as_object_mut() {
+        fields.entry("connectionId").or_insert_with(|| json!(context.connection_id));
+    }
+    payload
+}
+
+/// Answered with `focus.result`, which the app sees as well.
+fn handle_focus_window(context: &ControlContext, request: &Value) -> Result<Handled> {
+    focus_result(context, request, focus_window)
+}
+
+/// `handle_focus_window` with the focusing itself passed in, so tests can run it on a mock desktop.
+fn focus_result(
+    context: &ControlContext,
+    request: &Value,
+    focus: impl FnOnce(&FocusWindowPayload) -> FocusOutcome,
+) -> Result<Handled> {
+    let payload_value = request_payload(context, request);
+    let outcome = match serde_json::from_value::<FocusWindowPayload>(payload_value.clone()) {
+        Ok(payload) => {
+            eprintln!("[sidecar] focus.window request: {payload:?}");
+            focus(&payload)
+        }
+        Err(err) => FocusOutcome::rejected(anyhow::Error::new(err).context("invalid focus.window payload")),
+    };
+    if let Some(error) = &outcome.error {
+        eprintln!("[sidecar] focus.window failed: {error}");
+    }
+
+    let echoed = ["windowId", "connectionId", "browser"];
+    let reply = control::result_envelope("focus.result", request, &payload_value, &echoed, &outcome)?;
+    Ok(Handled::reply(reply, ReplyTo::EXTENSION_AND_APP))
+}
+
+/// Answered with `window.mark.result`, which the app sees as well.
+fn handle_window_mark(context: &ControlContext, request: &Value) -> Result<Handled> {
+    let payload_value = request_payload(context, request);
+    let outcome = match serde_json::from_value::<WindowMarkPayload>(payload_value.clone()) {
+        Ok(payload) => {
+            eprintln!("[sidecar] window.mark request: {payload:?}");
+            map_marked_window(&payload)
+        }
+        Err(err) => MarkOutcome::rejected(anyhow::Error::new(err).context("invalid window.mark payload")),
+    };
+
+    let echoed = ["windowId", "marker", "connectionId"];
+    let reply = control::result_envelope("window.mark.result", request, &payload_value, &echoed, &outcome)?;
+    Ok(Handled::reply(reply, ReplyTo::EXTENSION_AND_APP))
+}
+
+/// `"clear": true` in the payload empties the cache after reporting it; only privileged senders
+/// may clear it, anyone may query it.
+fn handle_focus_cache(context: &ControlContext, request: &Value) -> Result<Handled> {
+    let clear = request.pointer("/payload/clear").and_then(|v| v.as_bool()).unwrap_or(false);
+    let reply = if clear && !context.privileged {
+        let message = "clearing the focus cache requires the app or a debug client in inject mode";
+        control::reply_envelope("debug.error", request, json!({ "message": message }))
+    } else {
+        control::reply_envelope("focus.cache.state", request, cache_state(clear))
+    };
+    Ok(Handled::reply(reply, ReplyTo::sender(context.source)))
+}
+
+fn handle_window_closed(context: &ControlContext, message: &Value) -> Result<Handled> {
+    window_closed(context, message, &WINDOW_CACHE)
+}
+
+/// Drops the cached window named by `window.closed`, attributed to this sidecar's connection
+/// unless the message names another.
+fn window_closed(context: &ControlContext, message: &Value, cache: &Mutex<WindowCache>) -> Result<Handled> {
+    let connection_id = message.pointer("/payload/connectionId").and_then(|v| v.as_str());
+    let window_id = message
+        .pointer("/payload/windowId")
+        .and_then(|v| v.as_i64())
+        .and_then(|id| i32::try_from(id).ok());
+    if let Some(window_id) = window_id {
+        forget_window(cache, connection_id.unwrap_or(context.connection_id), window_id);
+    }
+    Ok(Handled::forward())
+}
+
+fn handle_presence(_context: &ControlContext, message: &Value) -> Result<Handled> {
+    presence(message, &WINDOW_CACHE)
+}
+
+/// Drops the cached windows of connections reported offline.
+fn presence(message: &Value, cache: &Mutex<WindowCache>) -> Result<Handled> {
+    if message.pointer("/payload/sidecar").and_then(|v| v.as_str()) == Some("offline") {
+        if let Some(connection_id) = message.pointer("/payload/connectionId").and_then(|v| v.as_str()) {
+            forget_connection(cache, connection_id);
+        }
+    }
+    Ok(Handled::forward())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::focus::mock::MockWindow;
+    use crate::focus::{focus_window_with, CacheSource, MockBackend};
+    use bridge_debug::debug_hub::DebugHub;
+    use bridge_debug::Role;
+
+    fn focus_cache(source: Source, inject: bool, payload: Value) -> Value {
+        let mut registry = ControlRegistry::default();
+        register(&mut registry);
+        let hub = DebugHub::new(Role::Sidecar);
+        let message = json!({ "v": 1, "id": "q1", "type": "focus.cache", "payload": payload }).to_string();
+        let handled = registry.dispatch(source, inject, "conn-1", &hub, &message).expect("focus.cache is handled");
+        assert!(!handled.forward);
+        serde_json::from_str(&handled.reply.expect("focus.cache is answered")).unwrap()
+    }
+
+    #[test]
+    fn anyone_may_query_the_focus_cache() {
+        for (source, inject) in [(Source::Extension, false), (Source::App, false), (Source::Debug, false)] {
+            let reply = focus_cache(source, inject, json!({}));
+            assert_eq!(reply["type"], "focus.cache.state", "{source:?}");
+            assert_eq!(reply["id"], "q1");
+            assert!(reply["payload"]["capacity"].is_u64());
+        }
+    }
+
+    #[test]
+    fn only_privileged_senders_clear_the_focus_cache() {
+        for source in [Source::Extension, Source::Debug] {
+            let reply = focus_cache(source, false, json!({ "clear": true }));
+            assert_eq!(reply["type"], "debug.error", "{source:?}");
+            assert_eq!(reply["id"], "q1");
+        }
+        for (source, inject) in [(Source::App, false), (Source::Debug, true)] {
+            let reply = focus_cache(source, inject, json!({ "clear": true }));
+            assert_eq!(reply["type"], "focus.cache.state", "{source:?}");
+        }
+    }
+
+    /// Runs `focus.window` from the extension on a mock desktop and returns how it was answered.
+    fn focus_on_mock(windows: Vec<MockWindow>, cache: &Mutex<WindowCache>, payload: Value) -> (Handled, Value) {
+        let hub = DebugHub::new(Role::Sidecar);
+        let request = json!({ "v": 1, "id": "f1", "type": "focus.window", "payload": payload });
+        let raw = request.to_string();
+        let context = ControlContext {
+            source: Source::Extension,
+            privileged: false,
+            connection_id: "conn-1",
+            hub: &hub,
+            raw: &raw,
+        };
+        let backend = MockBackend::new(windows);
+        let handled = focus_result(&context, &request, |payload| focus_window_with(&backend, cache, payload)).unwrap();
+        let reply = serde_json::from_str(handled.reply.as_deref().expect("focus.window is answered")).unwrap();
+        (handled, reply)
+    }
+
+    fn chrome(handle: u64, title: &str) -> MockWindow {
+        MockWindow {
+            handle,
+            process: Some("chrome.exe".to_string()),
+            title: title.to_string(),
+            closed: false,
+            fail_activate: false,
+        }
+    }
+
+    #[test]
+    fn focus_results_reach_both_ends_and_echo_the_request() {
+        let cache = Mutex::new(WindowCache::default());
+        let windows = vec![chrome(0x20, "Docs - Google Chrome"), chrome(0x30, "Inbox - Google Chrome")];
+        let request = json!({ "windowId": 7, "title": "Inbox", "browser": "chrome" });
+        let (handled, reply) = focus_on_mock(windows, &cache, request);
+
+        assert!(handled.reply_to.extension && handled.reply_to.app);
+        assert!(!handled.forward);
+        assert_eq!(reply["type"], "focus.result");
+        assert_eq!(reply["id"], "f1");
+        let payload = &reply["payload"];
+        assert_eq!(payload["requestId"], "f1");
+        assert_eq!(payload["windowId"], 7);
+        // Attributed to this sidecar's connection since the request named none
+        assert_eq!(payload["connectionId"], "conn-1");
+        assert_eq!(payload["browser"], "chrome");
+        assert_eq!(payload["status"], "focused");
+        assert_eq!(payload["backend"], "mock");
+        assert_eq!(payload["strategy"], "search");
+        assert_eq!(payload["cacheHit"], false);
+        assert_eq!(payload["window"], 0x30);
+        assert_eq!(payload["title"], "Inbox - Google Chrome");
+        assert_eq!(payload["process"], "chrome.exe");
+        assert!(payload["score"].is_i64());
+        assert!(!payload["scoreBreakdown"].as_array().unwrap().is_empty());
+        assert!(payload["error"].is_null());
+        assert_eq!(cache.lock().unwrap().get(&("conn-1".to_string(), 7)), Some(0x30));
+    }
+
+    #[test]
+    fn invalid_focus_requests_are_answered_with_a_failed_result() {
+        let cache = Mutex::new(WindowCache::default());
+        let (handled, reply) = focus_on_mock(vec![chrome(0x20, "Inbox")], &cache, json!({ "windowId": "seven" }));
+
+        assert!(handled.reply_to.extension && handled.reply_to.app);
+        assert_eq!(reply["type"], "focus.result");
+        let payload = &reply["payload"];
+        assert_eq!(payload["requestId"], "f1");
+        assert_eq!(payload["windowId"], "seven");
+        assert_eq!(payload["status"], "failed");
+        assert!(payload["backend"].is_null());
+        assert!(payload["error"].as_str().unwrap().contains("invalid focus.window payload"));
+        assert_eq!(cache.lock().unwrap().len(), 0);
+    }
+
+    /// A cache holding windows 1 and 2 of `conn-1` and window 1 of `conn-2`.
+    fn cache_of_two_connections() -> Mutex<WindowCache> {
+        let mut cache = WindowCache::default();
+        for (connection_id, window_id, handle) in [("conn-1", 1, 0x10), ("conn-1", 2, 0x20), ("conn-2", 1, 0x30)] {
+            cache.insert((connection_id.to_string(), window_id), handle, CacheSource::Search);
+        }
+        Mutex::new(cache)
+    }
+
+    fn cached(cache: &Mutex<WindowCache>) -> Vec<(String, i32)> {
+        let entries = cache.lock().unwrap().entries();
+        let mut keys: Vec<_> = entries.into_iter().map(|entry| (entry.connection_id, entry.window_id)).collect();
+        keys.sort();
+        keys
+    }
+
+    fn key(connection_id: &str, window_id: i32) -> (String, i32) {
+        (connection_id.to_string(), window_id)
+    }
+
+    fn close_window(source: Source, cache: &Mutex<WindowCache>, payload: Value) -> Handled {
+        let hub = DebugHub::new(Role::Sidecar);
+        let message = json!({ "v": 1, "type": "window.closed", "payload": payload });
+        let raw = message.to_string();
+        let context = ControlContext {
+            source,
+            privileged: source == Source::App,
+            connection_id: "conn-1",
+            hub: &hub,
+            raw: &raw,
+        };
+        window_closed(&context, &message, cache).unwrap()
+    }
+
+    #[test]
+    fn closed_windows_leave_the_focus_cache() {
+        // Without a connectionId the window is this sidecar's own
+        let cache = cache_of_two_connections();
+        let handled = close_window(Source::Extension, &cache, json!({ "windowId": 1 }));
+        assert!(handled.forward);
+        assert!(handled.reply.is_none());
+        assert_eq!(cached(&cache), [key("conn-1", 2), key("conn-2", 1)]);
+
+        let cache = cache_of_two_connections();
+        close_window(Source::App, &cache, json!({ "windowId": 1, "connectionId": "conn-2" }));
+        assert_eq!(cached(&cache), [key("conn-1", 1), key("conn-1", 2)]);
+
+        // Unknown windows and payloads without a usable windowId leave the cache alone
+        let unusable = [
+            json!({ "windowId": 9 }),
+            json!({ "windowId": "1" }),
+            json!({ "windowId": 1i64 << 40 }),
+            json!({}),
+        ];
+        for payload in unusable {
+            let cache = cache_of_two_connections();
+            assert!(close_window(Source::Extension, &cache, payload.clone()).forward);
+            assert_eq!(cached(&cache).len(), 3, "{payload}");
+        }
+    }
+
+    #[test]
+    fn connections_going_offline_leave_the_focus_cache() {
+        let cache = cache_of_two_connections();
+        let payload = json!({ "sidecar": "offline", "connectionId": "conn-1" });
+        let offline = json!({ "v": 1, "type": "presence.status", "payload": payload });
+        let handled = presence(&offline, &cache).unwrap();
+        assert!(handled.forward);
+        assert!(handled.reply.is_none());
+        assert_eq!(cached(&cache), [key("conn-2", 1)]);
+
+        let cache = cache_of_two_connections();
+        for payload in [
+            json!({ "sidecar": "online", "connectionId": "conn-1" }),
+            json!({ "sidecar": "offline" }),
+            json!({ "sidecar": "offline", "connectionId": "conn-3" }),
+        ] {
+            let message = json!({ "v": 1, "type": "presence.status", "payload": payload });
+            assert!(presence(&message, &cache).unwrap().forward);
+            assert_eq!(cached(&cache).len(), 3, "{payload}");
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use super::{platform_backend, CacheSource, FocusBackend, WindowCache, WindowHandle, WINDOW_CACHE};
+
+/// Shorter markers could turn up in ordinary page titles.
+const MIN_MARKER_LEN: usize = 8;
+/// Browsers update the native title a little after `document.title` changes.
+const ATTEMPTS: u32 = 10;
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// `window.mark`: the extension has put `marker` into the title of browser window `windowId`
+/// and asks which native window now shows it.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowMarkPayload {
+    pub window_id: i32,
+    pub marker: String,
+    pub connection_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarkStatus {
+    /// Exactly one window carried the marker; it is cached for `windowId`.
+    Mapped,
+    /// No window showed the marker within the retry window.
+    NotFound,
+    /// Several windows carried the marker, so none was cached.
+    Ambiguous,
+    /// The payload was invalid or the backend is unavailable.
+    Failed,
+}
+
+/// What happened to one `window.mark`, reported back as `window.mark.result`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkOutcome {
+    pub status: MarkStatus,
+    pub backend: Option<&'static str>,
+    pub window: Option<WindowHandle>,
+    pub title: Option<String>,
+    pub attempts: u32,
+    pub error: Option<String>,
+}
+
+impl MarkOutcome {
+    fn new(backend: Option<&'static str>) -> Self {
+        Self {
+            status: MarkStatus::Failed,
+            backend,
+            window: None,
+            title: None,
+            attempts: 0,
+            error: None,
+        }
+    }
+
+    /// A request that never reached a backend, e.g. because its payload did not parse.
+    pub fn rejected(err: anyhow::Error) -> Self {
+        Self::new(None).failed(MarkStatus::Failed, err)
+    }
+
+    fn failed(mut self, status: MarkStatus, err: anyhow::Error) -> Self {
+        eprintln!("[sidecar] window.mark failed: {err:#}");
+        self.status = status;
+        self.error = Some(format!("{err:#}"));
+        self
+    }
+}
+
+pub fn map_marked_window(payload: &WindowMarkPayload) -> MarkOutcome {
+    match platform_backend() {
+        Ok(backend) => map_marked_window_with(backend.as_ref(), &WINDOW_CACHE, payload),
+        Err(err) => MarkOutcome::rejected(err),
+    }
+}
+
+/// Looks for the one window whose title carries the payload's marker, retrying while the
+/// browser catches up, and caches it for `(connectionId, windowId)`.
+pub fn map_marked_window_with(
+    backend: &dyn FocusBackend,
+    cache: &Mutex<WindowCache>,
+    payload: &WindowMarkPayload,
+) -> MarkOutcome {
+    let mut outcome = MarkOutcome::new(Some(backend.name()));
+    if payload.marker.trim().chars().count() < MIN_MARKER_LEN {
+        let err = anyhow!("marker must be at least {MIN_MARKER_LEN} characters");
+        return outcome.failed(MarkStatus::Failed, err);
+    }
+
+    for attempt in 1..=ATTEMPTS {
+        outcome.attempts = attempt;
+        let marked = match marked_windows(backend, &payload.marker) {
+            Ok(marked) => marked,
+            Err(err) => return outcome.failed(MarkStatus::Failed, err),
+        };
+        match marked.as_slice() {
+            [] if attempt < ATTEMPTS => thread::sleep(RETRY_DELAY),
+            [] => break,
+            [(window, title)] => {
+                let key = (payload.connection_id.clone().unwrap_or_default(), payload.window_id);
+                eprintln!("[sidecar] window.mark mapped window {} of {} to 0x{window:X}", key.1, key.0);
+                cache
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .insert(key, *window, CacheSource::Marker);
+                outcome.status = MarkStatus::Mapped;
+                outcome.window = Some(*window);
+                outcome.title = Some(title.clone());
+                return outcome;
+            }
+            _ => {
+                let handles: Vec<String> = marked.iter().map(|(window, _)| format!("0x{window:X}")).collect();
+                let err = anyhow!("marker found on several windows: {}", handles.join(", "));
+                return outcome.failed(MarkStatus::Ambiguous, err);
+            }
+        }
+    }
+
+    let err = anyhow!("no window title contains the marker after {ATTEMPTS} attempts");
+    outcome.failed(MarkStatus::NotFound, err)
+}
+
+fn marked_windows(backend: &dyn FocusBackend, marker: &str) -> Result<Vec<(WindowHandle, String)>> {
+    let windows = backend.windows()?;
+    if windows.is_empty() {
+        bail!("the backend lists no windows");
+    }
+    Ok(windows
+        .into_iter()
+        .filter_map(|window| {
+            let title = backend.title(window)?;
+            title.contains(marker).then_some((window, title))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::focus::mock::{MockBackend, MockWindow};
+    use std::cell::Cell;
+
+    const MARKER: &str = "[bridge-mark-1f3a]";
+
+    fn window(handle: WindowHandle, title: &str) -> MockWindow {
+        MockWindow {
+            handle,
+            process: Some("chrome".to_string()),
+            title: title.to_string(),
+            closed: false,
+            fail_activate: false,
+        }
+    }
+
+    fn payload(marker: &str) -> WindowMarkPayload {
+        WindowMarkPayload {
+            window_id: 7,
+            marker: marker.to_string(),
+            connection_id: Some("conn-1".to_string()),
+        }
+    }
+
+    fn cached(cache: &Mutex<WindowCache>) -> Option<WindowHandle> {
+        cache.lock().unwrap().get(&("conn-1".to_string(), 7))
+    }
+
+    /// A browser that updates the native title only after the window was listed `lag` times.
+    struct LaggingTitle {
+        window: WindowHandle,
+        lag: u32,
+        listed: Cell<u32>,
+    }
+
+    impl FocusBackend for LaggingTitle {
+        fn name(&self) -> &'static str {
+            "lagging"
+        }
+
+        fn windows(&self) -> Result<Vec<WindowHandle>> {
+            self.listed.set(self.listed.get() + 1);
+            Ok(vec![self.window])
+        }
+
+        fn is_window(&self, window: WindowHandle) -> bool {
+            window == self.window
+        }
+
+        fn process_name(&self, _: WindowHandle) -> Option<String> {
+            Some("chrome".to_string())
+        }
+
+        fn title(&self, _: WindowHandle) -> Option<String> {
+            let marked = self.listed.get() > self.lag;
+            Some(if marked { format!("{MARKER} Inbox - Google Chrome") } else { "Inbox - Google Chrome".to_string() })
+        }
+
+        fn activate(&self, _: WindowHandle) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn maps_and_caches_the_marked_window() {
+        let backend = MockBackend::new(vec![
+            window(0x10, "Calendar - Google Chrome"),
+            window(0x20, &format!("{MARKER} Inbox - Google Chrome")),
+        ]);
+        let cache = Mutex::new(WindowCache::default());
+
+        let outcome = map_marked_window_with(&backend, &cache, &payload(MARKER));
+        assert_eq!(outcome.status, MarkStatus::Mapped);
+        assert_eq!(outcome.backend, Some("mock"));
+        assert_eq!(outcome.window, Some(0x20));
+        assert_eq!(outcome.attempts, 1);
+        assert_eq!(outcome.error, None);
+        assert_eq!(cached(&cache), Some(0x20));
+        assert_eq!(cache.lock().unwrap().entries()[0].source, CacheSource::Marker);
+    }
+
+    #[test]
+    fn retries_until_the_title_shows_the_marker() {
+        let backend = LaggingTitle {
+            window: 0x30,
+            lag: 2,
+            listed: Cell::new(0),
+        };
+        let cache = Mutex::new(WindowCache::default());
+
+        let outcome = map_marked_window_with(&backend, &cache, &payload(MARKER));
+        assert_eq!(outcome.status, MarkStatus::Mapped);
+        assert_eq!(outcome.attempts, 3);
+        assert_eq!(cached(&cache), Some(0x30));
+    }
+
+    #[test]
+    fn refuses_to_pick_between_windows_carrying_the_marker() {
+        let backend = MockBackend::new(vec![
+            window(0x10, &format!("{MARKER} Inbox - Google Chrome")),
+            window(0x20, &format!("Re: {MARKER} - Google Chrome")),
+        ]);
+        let cache = Mutex::new(WindowCache::default());
+        cache.lock().unwrap().insert(("conn-1".to_string(), 7), 0x99, CacheSource::Search);
+
+        let outcome = map_marked_window_with(&backend, &cache, &payload(MARKER));
+        assert_eq!(outcome.status, MarkStatus::Ambiguous);
+        assert_eq!(outcome.window, None);
+        assert!(outcome.error.as_deref().is_some_and(|error| error.contains("0x10, 0x20")));
+        // The earlier guess is left alone
+        assert_eq!(cached(&cache), Some(0x99));
+    }
+
+    #[test]
+    fn gives_up_when_no_title_shows_the_marker() {
+        let backend = MockBackend::new(vec![window(0x10, "Inbox - Google Chrome")]);
+        let cache = Mutex::new(WindowCache::default());
+
+        let outcome = map_marked_window_with(&backend, &cache, &payload(MARKER));
+        assert_eq!(outcome.status, MarkStatus::NotFound);
+        assert_eq!(outcome.attempts, ATTEMPTS);
+        assert_eq!(cached(&cache), None);
+    }
+
+    #[test]
+    fn rejects_short_markers_and_empty_desktops() {
+        let cache = Mutex::new(WindowCache::default());
+        let backend = MockBackend::new(vec![window(0x10, "[mark] Inbox")]);
+        let outcome = map_marked_window_with(&backend, &cache, &payload(" [mark] "));
+        assert_eq!(outcome.status, MarkStatus::Failed);
+        assert_eq!(outcome.attempts, 0);
+
+        let outcome = map_marked_window_with(&MockBackend::default(), &cache, &payload(MARKER));
+        assert_eq!(outcome.status, MarkStatus::Failed);
+        assert_eq!(outcome.attempts, 1);
+        assert_eq!(cached(&cache), None);
+    }
+}
@@ -0,0 +1,81 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+use super::{FocusBackend, WindowHandle};
+
+/// One window of the mock desktop.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MockWindow {
+    pub handle: WindowHandle,
+    /// Executable name as a real backend reports it, e.g. `chrome.exe` or `firefox`.
+    pub process: Option<String>,
+    #[serde(default)]
+    pub title: String,
+    /// Closed windows are treated as gone, which turns a cached handle to one stale.
+    #[serde(default)]
+    pub closed: bool,
+    /// Makes activating this window fail, like a refused foreground change.
+    #[serde(default)]
+    pub fail_activate: bool,
+}
+
+/// An in-memory desktop for exercising window selection, caching and errors without a
+/// windowing system. Windows are listed most recently active first, in file order.
+#[derive(Debug, Default)]
+pub struct MockBackend {
+    windows: Vec<MockWindow>,
+}
+
+impl MockBackend {
+    pub fn new(windows: Vec<MockWindow>) -> Self {
+        Self { windows }
+    }
+
+    /// Reads a JSON array of `MockWindow`s, e.g.
+    /// `[{"handle": 1, "process": "chrome", "title": "Inbox"}]`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let raw = fs::read_to_string(path).with_context(|| format!("reading mock windows {}", path.display()))?;
+        let windows = serde_json::from_str(&raw).with_context(|| format!("parsing mock windows {}", path.display()))?;
+        Ok(Self::new(windows))
+    }
+
+    fn window(&self, handle: WindowHandle) -> Option<&MockWindow> {
+        self.windows.iter().find(|window| window.handle == handle && !window.closed)
+    }
+}
+
+impl FocusBackend for MockBackend {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn windows(&self) -> Result<Vec<WindowHandle>> {
+        Ok(self.windows.iter().filter(|window| !window.closed).map(|window| window.handle).collect())
+    }
+
+    fn is_window(&self, window: WindowHandle) -> bool {
+        self.window(window).is_some()
+    }
+
+    fn process_name(&self, window: WindowHandle) -> Option<String> {
+        self.window(window)?.process.as_ref().map(|name| name.to_lowercase())
+    }
+
+    fn title(&self, window: WindowHandle) -> Option<String> {
+        self.window(window).map(|window| window.title.clone())
+    }
+
+    fn activate(&self, window: WindowHandle) -> Result<()> {
+        let Some(mock) = self.window(window) else {
+            bail!("mock window 0x{window:X} does not exist");
+        };
+        if mock.fail_activate {
+            bail!("mock window 0x{window:X} refused activation");
+        }
+        eprintln!("[sidecar] mock activated window=0x{window:X} ({})", mock.title);
+        Ok(())
+    }
+}
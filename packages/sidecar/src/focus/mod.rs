@@ -0,0 +1,377 @@
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::sync::Mutex;
+
+mod cache;
+mod handlers;
+mod marker;
+mod mock;
+pub mod scoring;
+#[cfg(target_os = "windows")]
+mod win32;
+#[cfg(target_os = "linux")]
+mod x11;
+
+pub use cache::{CacheSource, WindowCache};
+pub use handlers::register as register_control_handlers;
+pub use marker::{map_marked_window, MarkOutcome, WindowMarkPayload};
+pub use mock::MockBackend;
+use scoring::{Candidate, Ranked, ScoringWeights};
+
+#[derive(Debug, Deserialize)]
+pub struct FocusWindowPayload {
+    #[serde(rename = "windowId")]
+    pub window_id: Option<i32>,
+    pub title: Option<String>,
+    pub url: Option<String>,
+    pub browser: Option<String>,
+    #[serde(rename = "connectionId")]
+    pub connection_id: Option<String>,
+}
+
+/// A top-level window, as the backend identifies it (`HWND` on Windows, X window id on X11).
+pub type WindowHandle = u64;
+
+/// What `focus_window` needs from the windowing system. Selection and caching live in
+/// `focus_window_with`, so they behave the same on every backend, including `MockBackend`.
+pub trait FocusBackend {
+    /// Reported as `backend` in `focus.result`.
+    fn name(&self) -> &'static str;
+
+    /// Visible top-level windows, most recently active first when the platform knows the order.
+    fn windows(&self) -> Result<Vec<WindowHandle>>;
+
+    /// Whether a cached handle still refers to a live window.
+    fn is_window(&self, window: WindowHandle) -> bool;
+
+    /// Lowercase executable name of the process owning `window`, e.g. `chrome.exe` or `firefox`.
+    fn process_name(&self, window: WindowHandle) -> Option<String>;
+
+    fn title(&self, window: WindowHandle) -> Option<String>;
+
+    /// Brings `window` to the foreground.
+    fn activate(&self, window: WindowHandle) -> Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FocusStatus {
+    Focused,
+    /// No window of the expected browser was found.
+    NotFound,
+    /// A window was chosen but could not be activated, or the backend is unavailable.
+    Failed,
+}
+
+/// How the window was chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FocusStrategy {
+    /// The cached window for `windowId` was still alive.
+    Cache,
+    /// Windows were scored against the payload.
+    Search,
+}
+
+/// What happened to one `focus.window` request, reported back as `focus.result`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FocusOutcome {
+    pub status: FocusStatus,
+    pub backend: Option<&'static str>,
+    pub strategy: FocusStrategy,
+    pub cache_hit: bool,
+    pub window: Option<WindowHandle>,
+    pub title: Option<String>,
+    pub process: Option<String>,
+    /// Score of the chosen window; `None` when it came from the cache.
+    pub score: Option<i32>,
+    /// Points each scoring rule gave the chosen window; empty when it came from the cache.
+    pub score_breakdown: Vec<scoring::ScoreItem>,
+    pub error: Option<String>,
+}
+
+impl FocusOutcome {
+    fn new(backend: Option<&'static str>) -> Self {
+        Self {
+            status: FocusStatus::Failed,
+            backend,
+            strategy: FocusStrategy::Search,
+            cache_hit: false,
+            window: None,
+            title: None,
+            process: None,
+            score: None,
+            score_breakdown: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// A request that never reached a backend, e.g. because its payload did not parse.
+    pub fn rejected(err: anyhow::Error) -> Self {
+        Self::new(None).failed(FocusStatus::Failed, err)
+    }
+
+    fn failed(mut self, status: FocusStatus, err: anyhow::Error) -> Self {
+        self.status = status;
+        self.error = Some(format!("{err:#}"));
+        self
+    }
+}
+
+static WINDOW_CACHE: Lazy<Mutex<WindowCache>> = Lazy::new(|| Mutex::new(WindowCache::default()));
+static SCORING_WEIGHTS: Lazy<ScoringWeights> = Lazy::new(ScoringWeights::from_env);
+
+pub fn focus_window(payload: &FocusWindowPayload) -> FocusOutcome {
+    match platform_backend() {
+        Ok(backend) => focus_window_with(backend.as_ref(), &WINDOW_CACHE, payload),
+        Err(err) => FocusOutcome::rejected(err),
+    }
+}
+
+/// Forgets the handle cached for a browser window, e.g. because the extension saw it close.
+pub fn forget_window(cache: &Mutex<WindowCache>, connection_id: &str, window_id: i32) -> bool {
+    let removed = lock(cache).remove(&(connection_id.to_string(), window_id)).is_some();
+    if removed {
+        eprintln!("[sidecar] focus cache dropped window {window_id} of {connection_id}");
+    }
+    removed
+}
+
+/// Forgets every handle cached for a connection that went offline.
+pub fn forget_connection(cache: &Mutex<WindowCache>, connection_id: &str) -> usize {
+    let removed = lock(cache).forget_connection(connection_id);
+    if removed > 0 {
+        eprintln!("[sidecar] focus cache dropped {removed} window(s) of {connection_id}");
+    }
+    removed
+}
+
+/// Contents of the cache for `focus.cache.state`; `clear` empties it afterwards.
+pub fn cache_state(clear: bool) -> serde_json::Value {
+    let mut cache = lock(&WINDOW_CACHE);
+    let state = serde_json::json!({
+        "size": cache.len(),
+        "capacity": cache.capacity(),
+        "entries": cache.entries(),
+    });
+    if clear {
+        let removed = cache.clear();
+        eprintln!("[sidecar] focus cache cleared ({removed} window(s))");
+    }
+    state
+}
+
+/// The cache only holds plain handles, so a panic while it was locked cannot leave it
+/// inconsistent.
+fn lock(cache: &Mutex<WindowCache>) -> std::sync::MutexGuard<'_, WindowCache> {
+    cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// `BRIDGE_FOCUS_MOCK` swaps the windowing system for the windows listed in a JSON file, see
+/// `MockBackend::load`.
+fn platform_backend() -> Result<Box<dyn FocusBackend>> {
+    if let Ok(path) = env::var("BRIDGE_FOCUS_MOCK") {
+        return Ok(Box::new(MockBackend::load(path.as_ref())?));
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        Ok(Box::new(win32::Win32Backend))
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        Ok(Box::new(x11::X11Backend::connect()?))
+    }
+
+    #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+    {
+        Err(anyhow::anyhow!("focus.window is not supported on this platform"))
+    }
+}
+
+/// Focuses the window for `payload`: the cached window for `(connectionId, windowId)` if it is
+/// still alive, otherwise the best scoring window of the expected browser, which is then cached.
+pub fn focus_window_with(
+    backend: &dyn FocusBackend,
+    cache: &Mutex<WindowCache>,
+    payload: &FocusWindowPayload,
+) -> FocusOutcome {
+    let mut outcome = FocusOutcome::new(Some(backend.name()));
+    let key = payload
+        .window_id
+        .map(|window_id| (payload.connection_id.clone().unwrap_or_default(), window_id));
+    if let Some(key) = &key {
+        let mut guard = cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(window) = guard.get(key) {
+            if backend.is_window(window) {
+                drop(guard);
+                outcome.strategy = FocusStrategy::Cache;
+                outcome.cache_hit = true;
+                return activate(backend, outcome, window);
+            }
+            guard.remove(key);
+        }
+    }
+
+    match search(backend, payload) {
+        Ok(best) => {
+            if let Some(key) = key {
+                cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(key, best.window, CacheSource::Search);
+            }
+            outcome.score = Some(best.score);
+            outcome.score_breakdown = best.breakdown;
+            activate(backend, outcome, best.window)
+        }
+        Err(err) => outcome.failed(FocusStatus::NotFound, err),
+    }
+}
+
+fn activate(backend: &dyn FocusBackend, mut outcome: FocusOutcome, window: WindowHandle) -> FocusOutcome {
+    outcome.window = Some(window);
+    outcome.title = backend.title(window);
+    outcome.process = backend.process_name(window);
+    match backend.activate(window) {
+        Ok(()) => {
+            outcome.status = FocusStatus::Focused;
+            outcome
+        }
+        Err(err) => outcome.failed(FocusStatus::Failed, err),
+    }
+}
+
+/// Scores every window with `scoring::rank`; the breakdown of the winner explains the choice.
+fn search(backend: &dyn FocusBackend, payload: &FocusWindowPayload) -> Result<Ranked> {
+    let candidates: Vec<Candidate> = backend
+        .windows()?
+        .into_iter()
+        .enumerate()
+        .map(|(position, window)| Candidate {
+            window,
+            process: backend.process_name(window),
+            title: backend.title(window).unwrap_or_default(),
+            recency: Some(position),
+        })
+        .collect();
+
+    scoring::rank(&candidates, payload, &SCORING_WEIGHTS)
+        .into_iter()
+        .next()
+        .context("No suitable browser window found")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock::MockWindow;
+
+    fn window(handle: WindowHandle, process: &str, title: &str) -> MockWindow {
+        MockWindow {
+            handle,
+            process: Some(process.to_string()),
+            title: title.to_string(),
+            closed: false,
+            fail_activate: false,
+        }
+    }
+
+    fn payload(window_id: Option<i32>, title: &str) -> FocusWindowPayload {
+        FocusWindowPayload {
+            window_id,
+            title: Some(title.to_string()),
+            url: None,
+            browser: Some("chrome".to_string()),
+            connection_id: Some("conn-1".to_string()),
+        }
+    }
+
+    fn key(window_id: i32) -> (String, i32) {
+        ("conn-1".to_string(), window_id)
+    }
+
+    #[test]
+    fn focuses_and_caches_the_best_matching_window() {
+        let backend = MockBackend::new(vec![
+            window(0x10, "firefox", "Inbox - Mozilla Firefox"),
+            window(0x20, "chrome.exe", "Docs - Google Chrome"),
+            window(0x30, "chrome.exe", "Inbox - Google Chrome"),
+        ]);
+        let cache = Mutex::new(WindowCache::default());
+
+        let outcome = focus_window_with(&backend, &cache, &payload(Some(7), "Inbox"));
+        assert_eq!(outcome.status, FocusStatus::Focused);
+        assert_eq!(outcome.backend, Some("mock"));
+        assert_eq!(outcome.strategy, FocusStrategy::Search);
+        assert!(!outcome.cache_hit);
+        assert_eq!(outcome.window, Some(0x30));
+        assert_eq!(outcome.title.as_deref(), Some("Inbox - Google Chrome"));
+        assert_eq!(outcome.process.as_deref(), Some("chrome.exe"));
+        assert!(outcome.score_breakdown.iter().any(|item| item.rule == "titleExact"));
+        assert_eq!(outcome.score, Some(outcome.score_breakdown.iter().map(|item| item.points).sum()));
+        assert_eq!(outcome.error, None);
+        assert_eq!(cache.lock().unwrap().get(&key(7)), Some(0x30));
+    }
+
+    #[test]
+    fn reports_not_found_without_a_window_of_the_browser() {
+        let backend = MockBackend::new(vec![window(0x10, "firefox", "Inbox - Mozilla Firefox")]);
+        let cache = Mutex::new(WindowCache::default());
+
+        let outcome = focus_window_with(&backend, &cache, &payload(Some(7), "Inbox"));
+        assert_eq!(outcome.status, FocusStatus::NotFound);
+        assert_eq!(outcome.window, None);
+        assert!(outcome.error.is_some());
+        assert_eq!(cache.lock().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn reports_a_refused_activation_as_failed() {
+        let mut refusing = window(0x20, "chrome", "Inbox - Google Chrome");
+        refusing.fail_activate = true;
+        let backend = MockBackend::new(vec![refusing]);
+        let cache = Mutex::new(WindowCache::default());
+
+        let outcome = focus_window_with(&backend, &cache, &payload(None, "Inbox"));
+        assert_eq!(outcome.status, FocusStatus::Failed);
+        assert_eq!(outcome.window, Some(0x20));
+        assert!(outcome.error.as_deref().is_some_and(|error| error.contains("refused activation")));
+    }
+
+    #[test]
+    fn reuses_a_live_cached_window_without_searching() {
+        // The cached window no longer matches the title, so only the cache can pick it
+        let backend = MockBackend::new(vec![
+            window(0x20, "chrome", "Inbox - Google Chrome"),
+            window(0x30, "chrome", "Calendar - Google Chrome"),
+        ]);
+        let cache = Mutex::new(WindowCache::default());
+        cache.lock().unwrap().insert(key(7), 0x30, CacheSource::Marker);
+
+        let outcome = focus_window_with(&backend, &cache, &payload(Some(7), "Inbox"));
+        assert_eq!(outcome.status, FocusStatus::Focused);
+        assert_eq!(outcome.strategy, FocusStrategy::Cache);
+        assert!(outcome.cache_hit);
+        assert_eq!(outcome.window, Some(0x30));
+        assert_eq!(outcome.score, None);
+        assert!(outcome.score_breakdown.is_empty());
+    }
+
+    #[test]
+    fn replaces_a_cached_window_that_closed() {
+        let mut closed = window(0x30, "chrome", "Calendar - Google Chrome");
+        closed.closed = true;
+        let backend = MockBackend::new(vec![window(0x20, "chrome", "Inbox - Google Chrome"), closed]);
+        let cache = Mutex::new(WindowCache::default());
+        cache.lock().unwrap().insert(key(7), 0x30, CacheSource::Marker);
+
+        let outcome = focus_window_with(&backend, &cache, &payload(Some(7), "Inbox"));
+        assert_eq!(outcome.strategy, FocusStrategy::Search);
+        assert!(!outcome.cache_hit);
+        assert_eq!(outcome.window, Some(0x20));
+        assert_eq!(cache.lock().unwrap().entries()[0].source, CacheSource::Search);
+        assert_eq!(cache.lock().unwrap().get(&key(7)), Some(0x20));
+    }
+}
@@ -0,0 +1,420 @@
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::env;
+
+use super::{FocusWindowPayload, WindowHandle};
+
+/// Share of the hint's words that must appear in a title for `titleFuzzy` to apply.
+const FUZZY_THRESHOLD: f64 = 0.5;
+
+/// Suffixes browsers append to the active tab's title in the window title.
+const TITLE_SUFFIXES: &[&str] = &[
+    " - google chrome",
+    " - chromium",
+    " - microsoft edge",
+    " - microsoft\u{200b} edge",
+    " - brave",
+    " - vivaldi",
+    " - comet",
+    " — mozilla firefox",
+    " - mozilla firefox",
+];
+
+/// Points awarded by each rule. Overridable with `BRIDGE_FOCUS_WEIGHTS`, a comma separated list
+/// of `rule=points` entries, e.g. `titleFuzzy=60,recency=0`.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScoringWeights {
+    /// The window belongs to the expected browser; other windows are never candidates.
+    pub process: i32,
+    /// The window title is the hinted title, once the browser suffix is removed.
+    pub title_exact: i32,
+    /// The window title contains the hinted title.
+    pub title_contains: i32,
+    /// Most of the hinted title's words appear in the window title; scaled by the share found.
+    pub title_fuzzy: i32,
+    /// The window title mentions the hinted URL's host or site name.
+    pub url_host: i32,
+    /// Full points for the most recently active window, halved for the next and so on.
+    pub recency: i32,
+    pub has_title: i32,
+}
+
+impl Default for ScoringWeights {
+    fn default() -> Self {
+        Self {
+            process: 100,
+            title_exact: 80,
+            title_contains: 50,
+            title_fuzzy: 40,
+            url_host: 30,
+            recency: 10,
+            has_title: 5,
+        }
+    }
+}
+
+impl ScoringWeights {
+    pub fn from_env() -> Self {
+        env::var("BRIDGE_FOCUS_WEIGHTS").map_or_else(|_| Self::default(), |spec| Self::parse(&spec))
+    }
+
+    /// The defaults with the `rule=points` overrides in `spec` applied.
+    fn parse(spec: &str) -> Self {
+        let mut weights = Self::default();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let parsed = entry
+                .split_once('=')
+                .and_then(|(key, value)| Some((key.trim(), value.trim().parse::<i32>().ok()?)));
+            let Some((key, points)) = parsed else {
+                eprintln!("[sidecar] ignoring malformed focus weight: {entry}");
+                continue;
+            };
+            let slot = match key {
+                "process" => &mut weights.process,
+                "titleExact" => &mut weights.title_exact,
+                "titleContains" => &mut weights.title_contains,
+                "titleFuzzy" => &mut weights.title_fuzzy,
+                "urlHost" => &mut weights.url_host,
+                "recency" => &mut weights.recency,
+                "hasTitle" => &mut weights.has_title,
+                _ => {
+                    eprintln!("[sidecar] ignoring unknown focus weight: {entry}");
+                    continue;
+                }
+            };
+            *slot = points;
+        }
+
+        weights
+    }
+}
+
+/// A window as the scoring engine sees it, independent of the platform.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub window: WindowHandle,
+    /// Lowercase executable name, with or without `.exe`.
+    pub process: Option<String>,
+    pub title: String,
+    /// Position in activation order, `0` for the most recently active window.
+    pub recency: Option<usize>,
+}
+
+/// Points one rule contributed to a score.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScoreItem {
+    pub rule: &'static str,
+    pub points: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Ranked {
+    pub window: WindowHandle,
+    pub score: i32,
+    pub breakdown: Vec<ScoreItem>,
+}
+
+/// Ranks the candidates of the expected browser, best first; ties keep the candidates' order.
+pub fn rank(candidates: &[Candidate], payload: &FocusWindowPayload, weights: &ScoringWeights) -> Vec<Ranked> {
+    let process_names = expected_process_names(payload.browser.as_deref());
+    let title_hint = payload.title.as_deref().map(normalize).filter(|hint| !hint.is_empty());
+    let url_hints = payload.url.as_deref().map(url_hints).unwrap_or_default();
+
+    let mut ranked: Vec<Ranked> = candidates
+        .iter()
+        .filter_map(|candidate| {
+            let process = candidate.process.as_deref()?;
+            let process = process.strip_suffix(".exe").unwrap_or(process);
+            if !process_names.iter().any(|expected| expected == process) {
+                return None;
+            }
+
+            let mut breakdown = vec![ScoreItem {
+                rule: "process",
+                points: weights.process,
+                detail: Some(process.to_string()),
+            }];
+            let title = normalize(&candidate.title);
+            if let Some(hint) = &title_hint {
+                breakdown.extend(score_title(&title, hint, weights));
+            }
+            if let Some(host) = url_hints.iter().find(|host| title.contains(host.as_str())) {
+                breakdown.push(ScoreItem {
+                    rule: "urlHost",
+                    points: weights.url_host,
+                    detail: Some(host.clone()),
+                });
+            }
+            if let Some(position) = candidate.recency {
+                let points = weights.recency >> position.min(31);
+                if points != 0 {
+                    breakdown.push(ScoreItem {
+                        rule: "recency",
+                        points,
+                        detail: Some(format!("#{}", position + 1)),
+                    });
+                }
+            }
+            if !title.is_empty() {
+                breakdown.push(ScoreItem {
+                    rule: "hasTitle",
+                    points: weights.has_title,
+                    detail: None,
+                });
+            }
+
+            Some(Ranked {
+                window: candidate.window,
+                score: breakdown.iter().map(|item| item.points).sum(),
+                breakdown,
+            })
+        })
+        .collect();
+
+    ranked.sort_by_key(|ranked| Reverse(ranked.score));
+    ranked
+}
+
+/// The strongest of `titleExact`, `titleContains` and `titleFuzzy` that applies.
+fn score_title(title: &str, hint: &str, weights: &ScoringWeights) -> Option<ScoreItem> {
+    if title == hint {
+        return Some(ScoreItem {
+            rule: "titleExact",
+            points: weights.title_exact,
+            detail: None,
+        });
+    }
+    if title.contains(hint) {
+        return Some(ScoreItem {
+            rule: "titleContains",
+            points: weights.title_contains,
+            detail: None,
+        });
+    }
+
+    let title_words: HashSet<&str> = words(title).collect();
+    let hint_words: Vec<&str> = words(hint).collect();
+    if hint_words.is_empty() {
+        return None;
+    }
+    let found = hint_words.iter().filter(|word| title_words.contains(*word)).count();
+    let share = found as f64 / hint_words.len() as f64;
+    (share >= FUZZY_THRESHOLD).then(|| ScoreItem {
+        rule: "titleFuzzy",
+        points: (weights.title_fuzzy as f64 * share).round() as i32,
+        detail: Some(format!("{found}/{} words", hint_words.len())),
+    })
+}
+
+/// Lowercases a title and removes the browser's suffix.
+fn normalize(title: &str) -> String {
+    let lower = title.trim().to_lowercase();
+    TITLE_SUFFIXES
+        .iter()
+        .find_map(|suffix| lower.strip_suffix(suffix))
+        .unwrap_or(&lower)
+        .trim()
+        .to_string()
+}
+
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric()).filter(|word| word.len() > 1)
+}
+
+/// Host of `url` without `www.`, and its site name (`github` for `github.com`), which is what
+/// page titles tend to mention.
+fn url_hints(url: &str) -> Vec<String> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = rest
+        .split(['/', '?', '#'])
+        .next()
+        .unwrap_or_default()
+        .rsplit('@')
+        .next()
+        .unwrap_or_default()
+        .split(':')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host).to_string();
+    if host.is_empty() {
+        return Vec::new();
+    }
+
+    let mut hints = vec![host.clone()];
+    let labels: Vec<&str> = host.split('.').collect();
+    if labels.len() >= 2 {
+        let site = labels[labels.len() - 2];
+        if site.len() > 2 {
+            hints.push(site.to_string());
+        }
+    }
+    hints
+}
+
+/// Executable names, without `.exe`, of the browser named in the payload, or of every known
+/// browser when it names none.
+fn expected_process_names(browser: Option<&str>) -> Vec<String> {
+    const CHROME: &[&str] = &["chrome", "google-chrome", "google-chrome-stable", "google-chrome-beta", "google-chrome-unstable"];
+    const CHROMIUM: &[&str] = &["chromium", "chromium-browser"];
+    const EDGE: &[&str] = &["msedge", "microsoft-edge", "microsoft-edge-stable", "microsoft-edge-beta", "microsoft-edge-dev"];
+    const BRAVE: &[&str] = &["brave", "brave-browser", "brave-browser-stable", "brave-browser-beta", "brave-browser-nightly"];
+    const VIVALDI: &[&str] = &["vivaldi", "vivaldi-bin"];
+    const FIREFOX: &[&str] = &["firefox", "firefox-bin", "firefox-esr"];
+    const COMET: &[&str] = &["comet", "chrome"];
+
+    let names: Vec<&str> = match browser.map(str::to_lowercase) {
+        Some(lower) if lower.contains("chromium") => CHROMIUM.to_vec(),
+        Some(lower) if lower.contains("chrome") => CHROME.to_vec(),
+        Some(lower) if lower.contains("edge") => EDGE.to_vec(),
+        Some(lower) if lower.contains("brave") => BRAVE.to_vec(),
+        Some(lower) if lower.contains("vivaldi") => VIVALDI.to_vec(),
+        Some(lower) if lower.contains("firefox") => FIREFOX.to_vec(),
+        Some(lower) if lower.contains("comet") || lower.contains("perplexity") => COMET.to_vec(),
+        Some(lower) if lower != "unknown" => return vec![lower.strip_suffix(".exe").unwrap_or(&lower).to_string()],
+        _ => [CHROME, CHROMIUM, EDGE, BRAVE, VIVALDI, FIREFOX, COMET].concat(),
+    };
+    names.into_iter().map(str::to_string).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(window: WindowHandle, process: &str, title: &str) -> Candidate {
+        Candidate {
+            window,
+            process: Some(process.to_string()),
+            title: title.to_string(),
+            recency: None,
+        }
+    }
+
+    fn payload(browser: Option<&str>, title: Option<&str>, url: Option<&str>) -> FocusWindowPayload {
+        FocusWindowPayload {
+            window_id: None,
+            title: title.map(str::to_string),
+            url: url.map(str::to_string),
+            browser: browser.map(str::to_string),
+            connection_id: None,
+        }
+    }
+
+    fn rules(ranked: &Ranked) -> Vec<(&'static str, i32)> {
+        ranked.breakdown.iter().map(|item| (item.rule, item.points)).collect()
+    }
+
+    #[test]
+    fn each_rule_scores_its_points() {
+        let weights = ScoringWeights::default();
+        // Window title, title hint, URL hint, expected breakdown
+        type Case<'a> = (&'a str, Option<&'a str>, Option<&'a str>, &'a [(&'a str, i32)]);
+        let cases: &[Case] = &[
+            ("", None, None, &[("process", 100)]),
+            ("New Tab - Google Chrome", None, None, &[("process", 100), ("hasTitle", 5)]),
+            ("Inbox - Google Chrome", Some("Inbox"), None, &[("process", 100), ("titleExact", 80), ("hasTitle", 5)]),
+            ("Inbox (3) - Google Chrome", Some("inbox"), None, &[("process", 100), ("titleContains", 50), ("hasTitle", 5)]),
+            ("Quarterly report draft", Some("quarterly sales report"), None, &[("process", 100), ("titleFuzzy", 27), ("hasTitle", 5)]),
+            ("Weekly notes", Some("quarterly sales report"), None, &[("process", 100), ("hasTitle", 5)]),
+            ("Pull requests · GitHub", None, Some("https://www.github.com/pulls"), &[("process", 100), ("urlHost", 30), ("hasTitle", 5)]),
+            ("docs.rs - Google Chrome", None, Some("https://user@docs.rs:443/serde?x#y"), &[("process", 100), ("urlHost", 30), ("hasTitle", 5)]),
+        ];
+
+        for (title, hint, url, expected) in cases {
+            let ranked = rank(&[candidate(1, "chrome", title)], &payload(Some("chrome"), *hint, *url), &weights);
+            assert_eq!(ranked.len(), 1, "{title:?}");
+            assert_eq!(rules(&ranked[0]), *expected, "{title:?} for {hint:?} / {url:?}");
+            assert_eq!(ranked[0].score, expected.iter().map(|(_, points)| points).sum::<i32>());
+        }
+    }
+
+    #[test]
+    fn recency_halves_with_each_position() {
+        let weights = ScoringWeights::default();
+        let candidates: Vec<Candidate> = (0..5)
+            .map(|position| Candidate {
+                recency: Some(position),
+                ..candidate(position as WindowHandle, "chrome", "")
+            })
+            .collect();
+
+        let ranked = rank(&candidates, &payload(Some("chrome"), None, None), &weights);
+        let scores: Vec<(WindowHandle, i32)> = ranked.iter().map(|ranked| (ranked.window, ranked.score)).collect();
+        assert_eq!(scores, vec![(0, 110), (1, 105), (2, 102), (3, 101), (4, 100)]);
+        assert_eq!(ranked[0].breakdown[1].detail.as_deref(), Some("#1"));
+        // Out of points: no recency item at all
+        assert_eq!(rules(&ranked[4]), vec![("process", 100)]);
+    }
+
+    #[test]
+    fn only_windows_of_the_expected_browser_are_ranked() {
+        let weights = ScoringWeights::default();
+        let candidates = [
+            candidate(1, "chrome.exe", ""),
+            candidate(2, "firefox", ""),
+            candidate(3, "msedge.exe", ""),
+            candidate(4, "code", ""),
+            Candidate {
+                process: None,
+                ..candidate(5, "", "")
+            },
+        ];
+        let cases: &[(Option<&str>, &[WindowHandle])] = &[
+            (Some("Google Chrome"), &[1]),
+            (Some("Firefox"), &[2]),
+            (Some("Microsoft Edge"), &[3]),
+            (Some("code.exe"), &[4]),
+            (Some("unknown"), &[1, 2, 3]),
+            (None, &[1, 2, 3]),
+        ];
+
+        for (browser, expected) in cases {
+            let windows: Vec<WindowHandle> = rank(&candidates, &payload(*browser, None, None), &weights)
+                .iter()
+                .map(|ranked| ranked.window)
+                .collect();
+            assert_eq!(windows, *expected, "{browser:?}");
+        }
+    }
+
+    #[test]
+    fn ties_keep_the_candidates_order() {
+        let weights = ScoringWeights::default();
+        let candidates = [
+            candidate(3, "chrome", "Inbox - Google Chrome"),
+            candidate(1, "chrome", "Calendar - Google Chrome"),
+            candidate(2, "chrome", "Inbox - Google Chrome"),
+        ];
+
+        let ranked = rank(&candidates, &payload(Some("chrome"), Some("Inbox"), None), &weights);
+        let windows: Vec<WindowHandle> = ranked.iter().map(|ranked| ranked.window).collect();
+        assert_eq!(windows, vec![3, 2, 1]);
+        assert_eq!(ranked[0].score, ranked[1].score);
+    }
+
+    #[test]
+    fn overrides_replace_the_default_weights() {
+        let weights = ScoringWeights::parse(" titleFuzzy=60 ,recency=0,,bogus=3,hasTitle=x,process");
+        assert_eq!(weights.title_fuzzy, 60);
+        assert_eq!(weights.recency, 0);
+        assert_eq!(weights.has_title, ScoringWeights::default().has_title);
+        assert_eq!(weights.process, ScoringWeights::default().process);
+
+        let candidates = [
+            Candidate {
+                recency: Some(0),
+                ..candidate(1, "firefox", "Quarterly report — Mozilla Firefox")
+            },
+            candidate(2, "firefox", "Quarterly report draft"),
+        ];
+        let ranked = rank(&candidates, &payload(Some("firefox"), Some("quarterly sales report"), None), &weights);
+        assert_eq!(rules(&ranked[0]), vec![("process", 100), ("titleFuzzy", 40), ("hasTitle", 5)]);
+        // Without recency the windows tie, so the first one stays ahead
+        assert_eq!(ranked[0].window, 1);
+        assert_eq!(ranked[0].score, ranked[1].score);
+    }
+}
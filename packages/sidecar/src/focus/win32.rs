@@ -0,0 +1,139 @@
+use anyhow::Result;
+use std::ffi::OsString;
+use std::os::windows::ffi::OsStringExt;
+
+use windows::Win32::Foundation::{CloseHandle, BOOL, HWND, LPARAM};
+use windows::Win32::System::ProcessStatus::K32GetModuleBaseNameW;
+use windows::Win32::System::Threading::{OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION};
+use windows::Win32::UI::WindowsAndMessaging::{
+    AllowSetForegroundWindow, BringWindowToTop, EnumWindows,
+    GetWindowTextLengthW, GetWindowTextW, GetWindowThreadProcessId, IsWindow, IsWindowVisible,
+    SetForegroundWindow, SetWindowPos, ShowWindow, HWND_NOTOPMOST, HWND_TOPMOST,
+    SWP_NOMOVE, SWP_NOSIZE, SW_RESTORE, ASFW_ANY,
+};
+
+use super::{FocusBackend, WindowHandle};
+
+/// Win32 windows, found with `EnumWindows` and raised with the foreground sequence below.
+pub struct Win32Backend;
+
+impl FocusBackend for Win32Backend {
+    fn name(&self) -> &'static str {
+        "win32"
+    }
+
+    /// `EnumWindows` walks top-level windows in Z order, so the foreground window comes first.
+    fn windows(&self) -> Result<Vec<WindowHandle>> {
+        let mut windows: Vec<WindowHandle> = Vec::new();
+        unsafe {
+            let _ = EnumWindows(
+                Some(enum_windows_proc),
+                LPARAM(&mut windows as *mut _ as isize),
+            );
+        }
+        Ok(windows)
+    }
+
+    fn is_window(&self, window: WindowHandle) -> bool {
+        unsafe { IsWindow(hwnd(window)).as_bool() }
+    }
+
+    fn process_name(&self, window: WindowHandle) -> Option<String> {
+        let mut pid = 0;
+        unsafe {
+            GetWindowThreadProcessId(hwnd(window), Some(&mut pid));
+        }
+        get_process_name(pid)
+    }
+
+    fn title(&self, window: WindowHandle) -> Option<String> {
+        unsafe {
+            let length = GetWindowTextLengthW(hwnd(window));
+            if length <= 0 {
+                return None;
+            }
+            let mut buffer = vec![0u16; (length + 1) as usize];
+            let read = GetWindowTextW(hwnd(window), &mut buffer) as usize;
+            if read == 0 {
+                return None;
+            }
+            buffer.truncate(read);
+            Some(OsString::from_wide(&buffer).to_string_lossy().to_string())
+        }
+    }
+
+    fn activate(&self, window: WindowHandle) -> Result<()> {
+        bring_window_to_front(hwnd(window))
+    }
+}
+
+fn hwnd(window: WindowHandle) -> HWND {
+    HWND(window as isize)
+}
+
+fn bring_window_to_front(hwnd: HWND) -> Result<()> {
+    eprintln!("[sidecar] bring_window_to_front hwnd=0x{:X}", hwnd.0 as usize);
+
+    unsafe {
+        let mut pid = 0;
+        GetWindowThreadProcessId(hwnd, Some(&mut pid));
+
+        let _ = AllowSetForegroundWindow(ASFW_ANY);
+        let _ = AllowSetForegroundWindow(pid);
+
+        let _ = ShowWindow(hwnd, SW_RESTORE);
+
+        let _ = SetWindowPos(
+            hwnd,
+            HWND_TOPMOST,
+            0,
+            0,
+            0,
+            0,
+            SWP_NOMOVE | SWP_NOSIZE,
+        );
+        let _ = SetWindowPos(
+            hwnd,
+            HWND_NOTOPMOST,
+            0,
+            0,
+            0,
+            0,
+            SWP_NOMOVE | SWP_NOSIZE,
+        );
+        let _ = BringWindowToTop(hwnd);
+        let _ = SetForegroundWindow(hwnd);
+    }
+
+    eprintln!("[sidecar] bring_window_to_front completed for hwnd=0x{:X}", hwnd.0 as usize);
+    Ok(())
+}
+
+/// Collects visible top-level windows into the `Vec<WindowHandle>` behind `lparam`.
+unsafe extern "system" fn enum_windows_proc(hwnd: HWND, lparam: LPARAM) -> BOOL {
+    let windows = &mut *(lparam.0 as *mut Vec<WindowHandle>);
+
+    if IsWindow(hwnd).as_bool() && IsWindowVisible(hwnd).as_bool() {
+        windows.push(hwnd.0 as WindowHandle);
+    }
+
+    BOOL(1)
+}
+
+fn get_process_name(pid: u32) -> Option<String> {
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
+
+        let mut buffer = vec![0u16; 260];
+        let len = K32GetModuleBaseNameW(handle, None, &mut buffer) as usize;
+        let _ = CloseHandle(handle);
+
+        if len == 0 {
+            return None;
+        }
+
+        buffer.truncate(len);
+        let name = OsString::from_wide(&buffer).to_string_lossy().to_string();
+        Some(name.to_lowercase())
+    }
+}
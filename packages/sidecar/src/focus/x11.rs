@@ -0,0 +1,300 @@
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{AtomEnum, ClientMessageEvent, ConnectionExt as _, EventMask, Window};
+use x11rb::rust_connection::RustConnection;
+
+use super::{FocusBackend, WindowHandle};
+use crate::browser;
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        _NET_CLIENT_LIST,
+        _NET_CLIENT_LIST_STACKING,
+        _NET_ACTIVE_WINDOW,
+        _NET_CURRENT_DESKTOP,
+        _NET_WM_DESKTOP,
+        _NET_WM_PID,
+        _NET_WM_NAME,
+        UTF8_STRING,
+    }
+}
+
+/// `_NET_WM_DESKTOP` of windows shown on every desktop.
+const ALL_DESKTOPS: u32 = 0xFFFF_FFFF;
+/// EWMH source indication for pagers and taskbars; window managers apply their focus stealing
+/// prevention to requests from applications (`1`), which would leave the browser in the background.
+const SOURCE_PAGER: u32 = 2;
+
+/// Windows managed by an EWMH window manager. Needs `DISPLAY`; Wayland sessions only work
+/// through XWayland.
+pub struct X11Backend {
+    conn: RustConnection,
+    root: Window,
+    atoms: Atoms,
+    proc_root: PathBuf,
+}
+
+impl X11Backend {
+    pub fn connect() -> Result<Self> {
+        Self::connect_to(None)
+    }
+
+    /// Connects to `display`, e.g. `:99`; `None` means `DISPLAY`.
+    pub fn connect_to(display: Option<&str>) -> Result<Self> {
+        let (conn, screen) = x11rb::connect(display).context("connecting to the X server")?;
+        let root = conn.setup().roots[screen].root;
+        let atoms = Atoms::new(&conn)?.reply()?;
+        Ok(Self {
+            conn,
+            root,
+            atoms,
+            proc_root: browser::proc_root(),
+        })
+    }
+
+    fn client_list(&self) -> Result<Vec<Window>> {
+        self.property_u32s(self.root, self.atoms._NET_CLIENT_LIST, AtomEnum::WINDOW.into())?
+            .context("window manager does not publish _NET_CLIENT_LIST (EWMH)")
+    }
+
+    /// `None` when the property is not set.
+    fn property_u32s(&self, window: Window, property: u32, kind: u32) -> Result<Option<Vec<u32>>> {
+        let reply = self.conn.get_property(false, window, property, kind, 0, u32::MAX)?.reply()?;
+        Ok(reply.value32().map(Iterator::collect))
+    }
+
+    fn property_u32(&self, window: Window, property: u32) -> Option<u32> {
+        self.property_u32s(window, property, AtomEnum::CARDINAL.into())
+            .ok()
+            .flatten()
+            .and_then(|values| values.first().copied())
+    }
+
+    fn property_string(&self, window: Window, property: u32, kind: u32) -> Option<String> {
+        let reply = self.conn.get_property(false, window, property, kind, 0, u32::MAX).ok()?.reply().ok()?;
+        property_text(&reply.value)
+    }
+
+    /// Sends an EWMH request to the window manager, which listens for client messages on the root.
+    fn send_root_message(&self, window: Window, kind: u32, data: [u32; 5]) -> Result<()> {
+        let event = ClientMessageEvent::new(32, window, kind, data);
+        self.conn
+            .send_event(
+                false,
+                self.root,
+                EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY,
+                event,
+            )?
+            .check()?;
+        Ok(())
+    }
+}
+
+impl FocusBackend for X11Backend {
+    fn name(&self) -> &'static str {
+        "x11"
+    }
+
+    /// `_NET_CLIENT_LIST_STACKING` reversed, so the topmost window comes first; the unordered
+    /// `_NET_CLIENT_LIST` for window managers that do not publish it.
+    fn windows(&self) -> Result<Vec<WindowHandle>> {
+        let stacking = self
+            .property_u32s(self.root, self.atoms._NET_CLIENT_LIST_STACKING, AtomEnum::WINDOW.into())
+            .ok()
+            .flatten();
+        topmost_first(stacking, || self.client_list())
+    }
+
+    /// Windows leave `_NET_CLIENT_LIST` when they are closed.
+    fn is_window(&self, window: WindowHandle) -> bool {
+        self.client_list().is_ok_and(|clients| is_listed(&clients, window))
+    }
+
+    fn process_name(&self, window: WindowHandle) -> Option<String> {
+        let pid = self.property_u32(window as Window, self.atoms._NET_WM_PID)?;
+        browser::executable_name(&self.proc_root, pid)
+    }
+
+    /// `_NET_WM_NAME`, falling back to the legacy `WM_NAME`.
+    fn title(&self, window: WindowHandle) -> Option<String> {
+        let window = window as Window;
+        self.property_string(window, self.atoms._NET_WM_NAME, self.atoms.UTF8_STRING)
+            .or_else(|| self.property_string(window, AtomEnum::WM_NAME.into(), AtomEnum::STRING.into()))
+    }
+
+    fn activate(&self, window: WindowHandle) -> Result<()> {
+        let window = window as Window;
+        eprintln!("[sidecar] activate_x11_window window=0x{window:X}");
+
+        // Windows on another desktop are only raised once that desktop is shown
+        let desktop = self.property_u32(window, self.atoms._NET_WM_DESKTOP);
+        let current = self.property_u32(self.root, self.atoms._NET_CURRENT_DESKTOP);
+        if let Some(desktop) = desktop_to_show(desktop, current) {
+            eprintln!("[sidecar] switching to desktop {desktop}");
+            self.send_root_message(self.root, self.atoms._NET_CURRENT_DESKTOP, [desktop, x11rb::CURRENT_TIME, 0, 0, 0])?;
+        }
+
+        self.send_root_message(window, self.atoms._NET_ACTIVE_WINDOW, [SOURCE_PAGER, x11rb::CURRENT_TIME, 0, 0, 0])?;
+        self.conn.flush()?;
+
+        eprintln!("[sidecar] activate_x11_window completed for window=0x{window:X}");
+        Ok(())
+    }
+}
+
+/// Candidates topmost first: `_NET_CLIENT_LIST_STACKING` is bottom to top, so it is reversed;
+/// `clients` (the unordered `_NET_CLIENT_LIST`) is only asked for when there is no stacking order.
+fn topmost_first(stacking: Option<Vec<Window>>, clients: impl FnOnce() -> Result<Vec<Window>>) -> Result<Vec<WindowHandle>> {
+    let windows = match stacking {
+        Some(stacking) => stacking.into_iter().rev().collect(),
+        None => clients()?,
+    };
+    Ok(windows.into_iter().map(WindowHandle::from).collect())
+}
+
+fn is_listed(clients: &[Window], window: WindowHandle) -> bool {
+    clients.iter().any(|&client| WindowHandle::from(client) == window)
+}
+
+/// A title property's bytes; unset and empty properties both mean no title.
+fn property_text(value: &[u8]) -> Option<String> {
+    let value = value.strip_suffix(&[0]).unwrap_or(value);
+    (!value.is_empty()).then(|| String::from_utf8_lossy(value).into_owned())
+}
+
+/// The desktop to switch to before activating a window on `window_desktop`, if any: windows on
+/// every desktop, on the current one, or without the hints need no switch.
+fn desktop_to_show(window_desktop: Option<u32>, current: Option<u32>) -> Option<u32> {
+    let desktop = window_desktop?;
+    (desktop != current? && desktop != ALL_DESKTOPS).then_some(desktop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stacking_order_is_reversed_to_put_the_topmost_window_first() {
+        let listed = topmost_first(Some(vec![0x10, 0x20, 0x30]), || panic!("stacking order was published")).unwrap();
+        assert_eq!(listed, vec![0x30, 0x20, 0x10]);
+    }
+
+    #[test]
+    fn client_list_is_the_fallback_without_a_stacking_order() {
+        assert_eq!(topmost_first(None, || Ok(vec![0x10, 0x20])).unwrap(), vec![0x10, 0x20]);
+        assert!(topmost_first(None, || anyhow::bail!("no EWMH")).is_err());
+        assert!(topmost_first(Some(Vec::new()), || Ok(vec![0x10])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn closed_windows_are_not_listed() {
+        assert!(is_listed(&[0x10, 0x20], 0x20));
+        assert!(!is_listed(&[0x10, 0x20], 0x30));
+        assert!(!is_listed(&[], 0x10));
+    }
+
+    #[test]
+    fn titles_are_decoded_leniently() {
+        assert_eq!(property_text(b"Inbox - Mozilla Firefox").as_deref(), Some("Inbox - Mozilla Firefox"));
+        assert_eq!(property_text(b"Legacy WM_NAME\0").as_deref(), Some("Legacy WM_NAME"));
+        assert_eq!(property_text(b"caf\xC3\xA9 \xFF").as_deref(), Some("caf\u{e9} \u{fffd}"));
+        assert_eq!(property_text(b""), None);
+        assert_eq!(property_text(b"\0"), None);
+    }
+
+    #[test]
+    fn switches_desktop_only_when_the_window_is_elsewhere() {
+        assert_eq!(desktop_to_show(Some(2), Some(0)), Some(2));
+        assert_eq!(desktop_to_show(Some(1), Some(1)), None);
+        assert_eq!(desktop_to_show(Some(ALL_DESKTOPS), Some(0)), None);
+        assert_eq!(desktop_to_show(None, Some(0)), None);
+        assert_eq!(desktop_to_show(Some(2), None), None);
+    }
+
+    /// Runs the backend against a real X server, with the test acting as the window manager.
+    /// Needs `Xvfb` on the `PATH`: `cargo test -- --ignored x11_backend_against_xvfb`.
+    #[test]
+    #[ignore = "needs Xvfb"]
+    fn x11_backend_against_xvfb() {
+        use std::process::{Command, Stdio};
+        use std::time::{Duration, Instant};
+        use x11rb::protocol::xproto::{
+            ChangeWindowAttributesAux, CreateWindowAux, PropMode, WindowClass,
+        };
+        use x11rb::protocol::Event;
+        use x11rb::wrapper::ConnectionExt as _;
+
+        let display = std::env::var("BRIDGE_TEST_DISPLAY").unwrap_or_else(|_| ":97".to_string());
+        struct Kill(std::process::Child);
+        impl Drop for Kill {
+            fn drop(&mut self) {
+                let _ = self.0.kill();
+                let _ = self.0.wait();
+            }
+        }
+        let _xvfb = Kill(Command::new("Xvfb")
+            .args([display.as_str(), "-screen", "0", "640x480x24", "-nolisten", "tcp"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("starting Xvfb"));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let (wm, screen) = loop {
+            match x11rb::connect(Some(&display)) {
+                Ok(connected) => break connected,
+                Err(_) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(50)),
+                Err(err) => panic!("Xvfb did not come up on {display}: {err}"),
+            }
+        };
+        let root = wm.setup().roots[screen].root;
+        let atoms = Atoms::new(&wm).unwrap().reply().unwrap();
+
+        // Two client windows, as a window manager would list them
+        let mut windows = Vec::new();
+        for title in ["Inbox - Mozilla Firefox", "Docs - Chromium"] {
+            let window = wm.generate_id().unwrap();
+            wm.create_window(0, window, root, 0, 0, 100, 100, 0, WindowClass::INPUT_OUTPUT, 0, &CreateWindowAux::new())
+                .unwrap();
+            wm.change_property8(PropMode::REPLACE, window, atoms._NET_WM_NAME, atoms.UTF8_STRING, title.as_bytes())
+                .unwrap();
+            wm.change_property32(PropMode::REPLACE, window, atoms._NET_WM_PID, AtomEnum::CARDINAL, &[std::process::id()])
+                .unwrap();
+            wm.change_property32(PropMode::REPLACE, window, atoms._NET_WM_DESKTOP, AtomEnum::CARDINAL, &[1])
+                .unwrap();
+            windows.push(window);
+        }
+        wm.change_property32(PropMode::REPLACE, root, atoms._NET_CLIENT_LIST, AtomEnum::WINDOW, &windows)
+            .unwrap();
+        wm.change_property32(PropMode::REPLACE, root, atoms._NET_CLIENT_LIST_STACKING, AtomEnum::WINDOW, &windows)
+            .unwrap();
+        wm.change_property32(PropMode::REPLACE, root, atoms._NET_CURRENT_DESKTOP, AtomEnum::CARDINAL, &[0])
+            .unwrap();
+        // Receive the backend's requests like a window manager
+        wm.change_window_attributes(root, &ChangeWindowAttributesAux::new().event_mask(EventMask::SUBSTRUCTURE_REDIRECT))
+            .unwrap()
+            .check()
+            .unwrap();
+        wm.flush().unwrap();
+
+        let backend = X11Backend::connect_to(Some(&display)).unwrap();
+        let listed = backend.windows().unwrap();
+        assert_eq!(listed, vec![WindowHandle::from(windows[1]), WindowHandle::from(windows[0])]);
+        assert_eq!(backend.title(listed[0]).as_deref(), Some("Docs - Chromium"));
+        assert!(backend.is_window(listed[1]));
+        assert!(!backend.is_window(0xDEAD));
+        assert!(backend.process_name(listed[0]).is_some());
+
+        backend.activate(listed[0]).unwrap();
+        let mut requests = Vec::new();
+        while requests.len() < 2 {
+            match wm.wait_for_event().unwrap() {
+                Event::ClientMessage(message) => requests.push((message.type_, message.window, message.data.as_data32()[0])),
+                _ => continue,
+            }
+        }
+        assert_eq!(requests[0], (atoms._NET_CURRENT_DESKTOP, root, 1));
+        assert_eq!(requests[1], (atoms._NET_ACTIVE_WINDOW, windows[1], SOURCE_PAGER));
+    }
+}
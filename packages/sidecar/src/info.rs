@@ -0,0 +1,175 @@
+use serde_json::{json, Value};
+use std::env;
+use std::time::Instant;
+use tokio::sync::mpsc::WeakSender;
+
+use crate::caller::Caller;
+use crate::control::{self, ControlRegistry, Handled, ReplyTo, ANY_SOURCE};
+use crate::AppLink;
+
+/// Environment variables the sidecar reads. Only the names of those set are reported, since
+/// values such as `BRIDGE_SECRET` must not leave the machine.
+const CONFIG_ENV: &[&str] = &[
+    "APP_WS",
+    "BRIDGE_BROWSER",
+    "BRIDGE_PROC_ROOT",
+    "BRIDGE_FOCUS_MOCK",
+    "BRIDGE_FOCUS_WEIGHTS",
+    "BRIDGE_ALLOWED_EXTENSIONS",
+    "BRIDGE_ALLOWLIST",
+    "BRIDGE_DEBUG_INDEX",
+    "BRIDGE_SECRET",
+    "SIDE_CAR_DEBUG_WS",
+    "DEBUG_WS_PORT",
+    "DEBUG_WS_FORMAT",
+    "DEBUG_WS_HISTORY",
+    "DEBUG_WS_BUFFER",
+    "DEBUG_FORWARD",
+    "BRIDGE_CAPTURE",
+    "BRIDGE_CAPTURE_DIR",
+    "BRIDGE_CAPTURE_MAX_BYTES",
+    "BRIDGE_CAPTURE_MAX_FILES",
+    "BRIDGE_CAPTURE_REDACT",
+    "BRIDGE_CAPTURE_REDACT_POINTERS",
+];
+
+/// What `sidecar.info` reports: fixed at startup apart from the app link and the queues.
+pub struct SidecarInfo {
+    pub connection_id: String,
+    pub browser: String,
+    pub caller: Option<Caller>,
+    pub started: Instant,
+    pub app_link: AppLink,
+    pub debug_port: Option<u16>,
+    /// Where the extension allow-list came from; empty when none is configured.
+    pub allow_list: Vec<String>,
+    /// Weak, so answering `sidecar.info` never keeps a queue open during shutdown.
+    pub to_app: WeakSender<String>,
+    pub to_extension: WeakSender<String>,
+}
+
+/// Answers `sidecar.info` from any side with `sidecar.info.result`.
+pub fn register(registry: &mut ControlRegistry, info: SidecarInfo) {
+    registry.register("sidecar.info", ANY_SOURCE, move |context, request| {
+        let reply = control::reply_envelope("sidecar.info.result", request, info.snapshot());
+        Ok(Handled::reply(reply, ReplyTo::sender(context.source)))
+    });
+}
+
+impl SidecarInfo {
+    fn snapshot(&self) -> Value {
+        self.snapshot_from(|name| env::var(name).ok())
+    }
+
+    fn snapshot_from(&self, var: impl Fn(&str) -> Option<String>) -> Value {
+        let link = self.app_link.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        let env: Vec<&str> = CONFIG_ENV.iter().copied().filter(|name| var(name).is_some()).collect();
+        json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "profile": if cfg!(debug_assertions) { "debug" } else { "release" },
+            "pid": std::process::id(),
+            "uptimeMs": self.started.elapsed().as_millis(),
+            "connectionId": self.connection_id,
+            "browser": self.browser,
+            "caller": self.caller,
+            "app": link,
+            "debugPort": self.debug_port,
+            "queues": {
+                "toApp": depth(&self.to_app),
+                "toExtension": depth(&self.to_extension),
+            },
+            "config": {
+                "env": env,
+                "allowList": (!self.allow_list.is_empty()).then_some(&self.allow_list),
+            },
+        })
+    }
+}
+
+/// Messages waiting in a queue; `None` once it has closed.
+fn depth(queue: &WeakSender<String>) -> Option<usize> {
+    let queue = queue.upgrade()?;
+    Some(queue.max_capacity() - queue.capacity())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppLinkStatus;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::mpsc;
+
+    fn info(to_app: WeakSender<String>, to_extension: WeakSender<String>) -> SidecarInfo {
+        let args = ["chrome-extension://abcdefghijklmnopabcdefghijklmnop/".to_string()];
+        SidecarInfo {
+            connection_id: "conn-1".to_string(),
+            browser: "chrome".to_string(),
+            caller: Caller::from_args(&args),
+            started: Instant::now(),
+            app_link: Arc::new(Mutex::new(AppLinkStatus {
+                url: "ws://127.0.0.1:17342".to_string(),
+                connected: true,
+                connected_at: Some(1_700_000_000_000),
+                reconnects: 2,
+                app_shutting_down: false,
+            })),
+            debug_port: Some(9234),
+            allow_list: vec!["BRIDGE_ALLOWED_EXTENSIONS".to_string()],
+            to_app,
+            to_extension,
+        }
+    }
+
+    #[test]
+    fn snapshot_reports_the_link_and_queues() {
+        let (to_app, _app_rx) = mpsc::channel::<String>(8);
+        to_app.try_send("one".to_string()).unwrap();
+        to_app.try_send("two".to_string()).unwrap();
+        let (to_extension, _extension_rx) = mpsc::channel::<String>(8);
+        let info = info(to_app.downgrade(), to_extension.downgrade());
+        // A queue whose senders are gone has closed
+        drop(to_extension);
+
+        let snapshot = info.snapshot_from(|_| None);
+        assert_eq!(snapshot["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(snapshot["pid"], std::process::id());
+        assert_eq!(snapshot["connectionId"], "conn-1");
+        assert_eq!(snapshot["browser"], "chrome");
+        assert_eq!(snapshot["caller"]["extensionId"], "abcdefghijklmnopabcdefghijklmnop");
+        assert_eq!(snapshot["debugPort"], 9234);
+        assert_eq!(
+            snapshot["app"],
+            json!({
+                "url": "ws://127.0.0.1:17342",
+                "connected": true,
+                "connectedAt": 1_700_000_000_000u64,
+                "reconnects": 2,
+            })
+        );
+        assert_eq!(snapshot["queues"], json!({ "toApp": 2, "toExtension": null }));
+        assert_eq!(snapshot["config"], json!({ "env": [], "allowList": ["BRIDGE_ALLOWED_EXTENSIONS"] }));
+    }
+
+    #[test]
+    fn snapshot_names_configured_variables_without_their_values() {
+        let (to_app, _app_rx) = mpsc::channel::<String>(8);
+        let (to_extension, _extension_rx) = mpsc::channel::<String>(8);
+        let mut info = info(to_app.downgrade(), to_extension.downgrade());
+        info.allow_list.clear();
+        let vars = [
+            ("BRIDGE_SECRET", "hunter2-secret"),
+            ("APP_WS", "ws://10.0.0.7:4000"),
+            ("NOT_SIDECAR_CONFIG", "unrelated"),
+        ];
+
+        let snapshot = info.snapshot_from(|name| {
+            vars.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_string())
+        });
+        assert_eq!(snapshot["queues"], json!({ "toApp": 0, "toExtension": 0 }));
+        assert_eq!(snapshot["config"], json!({ "env": ["APP_WS", "BRIDGE_SECRET"], "allowList": null }));
+        let text = snapshot.to_string();
+        for (_, value) in vars {
+            assert!(!text.contains(value), "{value} leaked into {text}");
+        }
+    }
+}
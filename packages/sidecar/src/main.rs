@@ -1,25 +1,62 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
 use serde_json::json;
 use std::env;
 use std::io::{Read, Write};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use tokio::net::TcpListener;
-use tokio::sync::mpsc;
-use tokio_tungstenite::{accept_async, connect_async, tungstenite::Message};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
 
 const DEFAULT_APP_WS: &str = "ws://127.0.0.1:17342";
-const DEFAULT_DEBUG_PORT: u16 = 17888;
+/// Upper bound for flushing queues and saying goodbye to the app once shutdown starts.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+/// Messages waiting for a slow control handler before the stdin reader waits for room.
+const SLOW_QUEUE: usize = 16;
 
+#[cfg(target_os = "linux")]
+mod browser;
+mod caller;
+mod control;
 mod focus;
+mod info;
 
-fn detect_browser() -> String {
+use bridge_debug::capture::{CaptureConfig, FieldRedactor};
+use bridge_debug::debug_http::{self, HttpRequest, HttpResponse};
+use bridge_debug::debug_hub::{
+    self, ClientMode, DebugClientOptions, DebugFilter, DebugHub, DebugSubscription, FrameFormat, HistoryQuery, Hop,
+};
+use bridge_debug::debug_index;
+use bridge_debug::metrics::{Counter, Histogram};
+use bridge_debug::{unix_ms, Role};
+use caller::{AllowList, Caller, CallerFamily};
+use control::{ControlRegistry, Handled, ReplyTo, Source};
+use info::SidecarInfo;
+
+fn detect_browser(caller: Option<&Caller>) -> String {
     // Try environment variable first
     if let Ok(browser) = env::var("BRIDGE_BROWSER") {
         return browser;
     }
-    
+
+    // Only Firefox passes a manifest path and add-on id, so that settles it; a Chromium caller
+    // only narrows it down to the family
+    let family = caller.map(|caller| caller.family);
+    if family == Some(CallerFamily::Firefox) {
+        return "Firefox".to_string();
+    }
+    let detected = detect_browser_from_parents();
+    if family == Some(CallerFamily::Chromium) && (detected == "Unknown" || detected == "Firefox") {
+        return "Chromium".to_string();
+    }
+    detected
+}
+
+/// Names the browser from the processes that launched this host.
+fn detect_browser_from_parents() -> String {
     // Try to detect from parent process name
     #[cfg(target_os = "windows")]
     {
@@ -37,6 +74,13 @@ fn detect_browser() -> String {
             return parent;
         }
     }
+
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(browser) = browser::detect_from_procfs(&browser::proc_root()) {
+            return browser.to_string();
+        }
+    }
     
     "Unknown".to_string()
 }
@@ -112,6 +156,130 @@ fn get_parent_process_name() -> Option<String> {
     None
 }
 
+/// State of the link to the app, as reported on the debug port.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AppLinkStatus {
+    url: String,
+    connected: bool,
+    /// Unix timestamp (ms) at which the current connection was made.
+    connected_at: Option<u128>,
+    /// Successful connections after the first one.
+    reconnects: u64,
+    /// Set once the app announces `bridge.shutdown`, to keep reconnect attempts quiet.
+    #[serde(skip)]
+    app_shutting_down: bool,
+}
+
+type AppLink = Arc<Mutex<AppLinkStatus>>;
+
+/// Who this sidecar is, as announced in `presence.status`.
+#[derive(Debug, Clone)]
+struct Identity {
+    connection_id: String,
+    browser: String,
+    /// Extension that launched the host, verified from the caller arguments.
+    extension_id: Option<String>,
+}
+
+/// What the debug port's HTTP endpoints know about this sidecar.
+#[derive(Clone)]
+struct DebugContext {
+    connection_id: String,
+    browser: String,
+    caller: Option<Caller>,
+    started: Instant,
+    app_link: AppLink,
+    inject_enabled: bool,
+    registry: Arc<ControlRegistry>,
+}
+
+/// Routes messages from the extension: through their control handler, if any, then on to the
+/// app.
+#[derive(Clone)]
+struct ExtensionRouter {
+    connection_id: String,
+    hub: DebugHub,
+    registry: Arc<ControlRegistry>,
+    to_app_tx: mpsc::Sender<String>,
+    to_extension_tx: mpsc::Sender<String>,
+}
+
+impl ExtensionRouter {
+    /// Blocks while queues are full; returns `false` once the app queue is closed.
+    fn route(&self, msg: String) -> bool {
+        let handled = self.registry.dispatch(Source::Extension, false, &self.connection_id, &self.hub, &msg);
+        self.hub.broadcast(Hop::ExtensionToSidecar, &msg);
+
+        // Handled here; the reply goes wherever the handler asked
+        if let Some(handled) = handled {
+            if let Some(reply) = handled.reply {
+                self.hub.broadcast(Hop::Sidecar, &reply);
+                if handled.reply_to.extension {
+                    let _ = self.to_extension_tx.blocking_send(reply.clone());
+                }
+                if handled.reply_to.app && self.to_app_tx.blocking_send(reply).is_err() {
+                    return self.app_queue_closed();
+                }
+            }
+            if !handled.forward {
+                return true;
+            }
+        }
+
+        if self.to_app_tx.blocking_send(msg).is_err() {
+            return self.app_queue_closed();
+        }
+        true
+    }
+
+    /// Routes messages for slow handlers, like the window.mark handshake, in order on a thread
+    /// of their own so the messages behind them keep flowing. The thread ends once the sender
+    /// is dropped or the app queue closes; the reader then sees its sends fail.
+    fn spawn_slow_worker(&self) -> Result<(std::sync::mpsc::SyncSender<String>, std::thread::JoinHandle<()>)> {
+        let (slow_tx, slow_rx) = std::sync::mpsc::sync_channel::<String>(SLOW_QUEUE);
+        let router = self.clone();
+        let worker = std::thread::Builder::new()
+            .name("slow-handlers".to_string())
+            .spawn(move || {
+                for msg in slow_rx {
+                    if !router.route(msg) {
+                        break;
+                    }
+                }
+            })
+            .context("spawning the slow handler thread")?;
+        Ok((slow_tx, worker))
+    }
+
+    /// Routes native messages from stdin until EOF or until the app queue closes.
+    fn read_stdin(&self, slow_tx: &std::sync::mpsc::SyncSender<String>) -> Result<()> {
+        while let Some(msg) = read_native_message()? {
+            self.hub
+                .metrics()
+                .observe(Histogram::NativeFrameBytes, ("direction", "in"), msg.len() as f64);
+            if self.registry.blocks(&msg) {
+                // Waits while the slow queue is full; fails once the worker stopped
+                if slow_tx.send(msg).is_err() {
+                    break;
+                }
+                continue;
+            }
+            if !self.route(msg) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn app_queue_closed(&self) -> bool {
+        self.hub
+            .metrics()
+            .increment(Counter::RoutingFailures, Some(("reason", "app_queue_closed")));
+        false
+    }
+}
+
 fn generate_connection_id() -> String {
     use std::time::{SystemTime, UNIX_EPOCH};
     let timestamp = SystemTime::now()
@@ -122,92 +290,166 @@ fn generate_connection_id() -> String {
     format!("{:x}-{:x}", timestamp, random_part)
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let result = runtime.block_on(run());
+    // After a signal the blocking stdin reader is still parked in `read`; it cannot be
+    // cancelled, so leave it to process exit instead of waiting for it
+    runtime.shutdown_background();
+    result
+}
+
+async fn run() -> Result<()> {
+    let started = Instant::now();
+    let signal = shutdown_signal();
     let app_ws = env::var("APP_WS").unwrap_or_else(|_| DEFAULT_APP_WS.to_string());
     let connection_id = generate_connection_id();
-    let browser = detect_browser();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let caller = Caller::from_args(&args);
+    let browser = detect_browser(caller.as_ref());
     
     eprintln!("[sidecar] Connection ID: {}", connection_id);
     eprintln!("[sidecar] Browser: {}", browser);
+    match &caller {
+        Some(caller) => eprintln!("[sidecar] Caller: {} ({:?})", caller.extension_id, caller.family),
+        None => eprintln!("[sidecar] Caller: unknown (args: {:?})", args),
+    }
+    let allow_list = check_allow_list(caller.as_ref())?;
     
     let (to_app_tx, to_app_rx) = mpsc::channel::<String>(256);
     let (to_extension_tx, to_extension_rx) = mpsc::channel::<String>(256);
 
-    let hub = DebugHub::default();
+    let mut hub = DebugHub::new(Role::Sidecar).with_identity(&connection_id, &browser);
+    if let Some(redactor) = FieldRedactor::from_env() {
+        hub = hub.with_redactor(Arc::new(redactor));
+    }
+    if env::var("BRIDGE_CAPTURE").map(|v| v == "1").unwrap_or(false) {
+        if let Err(err) = hub.start_capture(CaptureConfig::default()) {
+            eprintln!("[sidecar] failed to start traffic capture: {err:#}");
+        }
+    }
+    let (shutdown_tx, shutdown_rx) = watch::channel::<Option<String>>(None);
+    let app_link: AppLink = Arc::new(Mutex::new(AppLinkStatus {
+        url: app_ws,
+        connected: false,
+        connected_at: None,
+        reconnects: 0,
+        app_shutting_down: false,
+    }));
+
+    // Bind the debug WebSocket mirror in debug builds (optional in release via env toggle)
+    let debug_enabled = cfg!(debug_assertions) || env::var("SIDE_CAR_DEBUG_WS").map(|v| v == "1").unwrap_or(false);
+    // Every sidecar gets its own port (ephemeral unless DEBUG_WS_PORT pins one) and lists it in
+    // the debug index so tools can find all of them
+    let mut debug_registration = None;
+    let mut debug_listener = None;
+    if debug_enabled {
+        let port = env::var("DEBUG_WS_PORT")
+            .ok()
+            .and_then(|p| p.parse::<u16>().ok())
+            .unwrap_or(0);
+        match TcpListener::bind(("127.0.0.1", port)).await {
+            Ok(listener) => {
+                let port = listener.local_addr()?.port();
+                eprintln!("[sidecar] debug ws listening on ws://127.0.0.1:{port}/");
+                let endpoint = debug_index::DebugEndpoint::new("sidecar", port, Some(&connection_id), Some(&browser));
+                match debug_index::register(&endpoint) {
+                    Ok(registration) => debug_registration = Some(registration),
+                    Err(err) => eprintln!("[sidecar] failed to register debug endpoint: {err:#}"),
+                }
+                debug_listener = Some((listener, port));
+            }
+            Err(err) => eprintln!("[sidecar] binding debug ws on 127.0.0.1:{port} failed: {err:#}"),
+        }
+    }
+
+    let registry = Arc::new(control_registry(SidecarInfo {
+        connection_id: connection_id.clone(),
+        browser: browser.clone(),
+        caller: caller.clone(),
+        started,
+        app_link: app_link.clone(),
+        debug_port: debug_listener.as_ref().map(|(_, port)| *port),
+        allow_list,
+        to_app: to_app_tx.downgrade(),
+        to_extension: to_extension_tx.downgrade(),
+    }));
 
     // Spawn bridge loop (sidecar <-> app ws)
     let hub_for_bridge = hub.clone();
     let to_extension_tx_for_bridge = to_extension_tx.clone();
-    let connection_id_for_bridge = connection_id.clone();
-    let browser_for_bridge = browser.clone();
-    tokio::spawn(async move {
+    let identity = Identity {
+        connection_id: connection_id.clone(),
+        browser: browser.clone(),
+        extension_id: caller.as_ref().map(|caller| caller.extension_id.clone()),
+    };
+    let app_link_for_bridge = app_link.clone();
+    let registry_for_bridge = registry.clone();
+    let bridge_task = tokio::spawn(async move {
         if let Err(err) = bridge_to_app(
-            app_ws,
+            app_link_for_bridge,
             to_app_rx,
             to_extension_tx_for_bridge,
             hub_for_bridge,
-            connection_id_for_bridge,
-            browser_for_bridge
+            registry_for_bridge,
+            identity,
+            shutdown_rx,
         ).await {
             eprintln!("[sidecar] app bridge exited: {err:#}");
         }
     });
 
-    // Spawn debug WebSocket mirror in debug builds (optional in release via env toggle)
-    let debug_enabled = cfg!(debug_assertions) || env::var("SIDE_CAR_DEBUG_WS").map(|v| v == "1").unwrap_or(false);
-    if debug_enabled {
-        let port = env::var("DEBUG_WS_PORT")
-            .ok()
-            .and_then(|p| p.parse::<u16>().ok())
-            .unwrap_or(DEFAULT_DEBUG_PORT);
+    // Serve the debug WebSocket mirror
+    if let Some((listener, _)) = debug_listener {
         let hub_for_debug = hub.clone();
         let to_app_tx_for_debug = to_app_tx.clone();
+        let context = DebugContext {
+            connection_id: connection_id.clone(),
+            browser: browser.clone(),
+            caller: caller.clone(),
+            started,
+            app_link: app_link.clone(),
+            inject_enabled: env::var("BRIDGE_SECRET").is_ok_and(|s| !s.is_empty()),
+            registry: registry.clone(),
+        };
         tokio::spawn(async move {
-            if let Err(err) = spawn_debug_ws(port, hub_for_debug, to_app_tx_for_debug).await {
+            if let Err(err) = spawn_debug_ws(listener, hub_for_debug, to_app_tx_for_debug, context).await {
                 eprintln!("[sidecar] debug ws failed: {err:#}");
             }
         });
     }
 
     // Read stdin (extension -> sidecar)
-    let hub_for_stdin = hub.clone();
-    let to_app_tx_for_stdin = to_app_tx.clone();
-    let stdin_task = tokio::task::spawn_blocking(move || -> Result<()> {
-        loop {
-            match read_native_message()? {
-                Some(msg) => {
-                    let handled = match handle_control_message(&msg) {
-                        Ok(value) => value,
-                        Err(err) => {
-                            eprintln!("[sidecar] control message error: {err:#}");
-                            false
-                        }
-                    };
-
-                    hub_for_stdin.broadcast(&msg);
-
-                    if handled {
-                        continue;
-                    }
-
-                    if to_app_tx_for_stdin.blocking_send(msg).is_err() {
-                        break;
-                    }
-                }
-                None => break,
-            }
-        }
-        Ok(())
+    let router = ExtensionRouter {
+        connection_id: connection_id.clone(),
+        hub: hub.clone(),
+        registry: registry.clone(),
+        to_app_tx: to_app_tx.clone(),
+        to_extension_tx: to_extension_tx.clone(),
+    };
+    let mut stdin_task = tokio::task::spawn_blocking(move || -> Result<()> {
+        let (slow_tx, slow_worker) = router.spawn_slow_worker()?;
+        let read = router.read_stdin(&slow_tx);
+        // Let the worker finish what it was handed, so its replies are queued before shutdown
+        drop(slow_tx);
+        let joined = slow_worker
+            .join()
+            .map_err(|_| anyhow::anyhow!("slow control handler thread panicked"));
+        read.and(joined)
     });
 
     // Write stdout (sidecar -> extension)
+    let metrics_for_stdout = hub.metrics().clone();
     let stdout_task = tokio::spawn(async move {
         let mut rx = to_extension_rx;
         while let Some(msg) = rx.recv().await {
             let msg_clone = msg.clone();
+            let started = Instant::now();
             match tokio::task::spawn_blocking(move || write_native_message(&msg_clone)).await {
-                Ok(Ok(())) => {}
+                Ok(Ok(())) => {
+                    metrics_for_stdout.observe(Histogram::NativeFrameBytes, ("direction", "out"), msg.len() as f64);
+                    metrics_for_stdout.observe_since(Histogram::SendLatency, ("link", "extension"), started);
+                }
                 Ok(Err(err)) => {
                     eprintln!("[sidecar] stdout write failed: {err:#}");
                     break;
@@ -221,42 +463,208 @@ async fn main() -> Result<()> {
         Result::<()>::Ok(())
     });
 
-    if let Err(err) = stdin_task.await? {
-        eprintln!("[sidecar] stdin reader error: {err:#}");
-    }
+    let reason = tokio::select! {
+        result = &mut stdin_task => {
+            match result? {
+                Ok(()) => "stdin-eof",
+                Err(err) => {
+                    eprintln!("[sidecar] stdin reader error: {err:#}");
+                    "stdin-error"
+                }
+            }
+        }
+        signal = signal => signal,
+    };
+    eprintln!("[sidecar] shutting down ({reason})");
 
+    // Let the bridge drain the outbox, report offline and close the app socket
+    let _ = shutdown_tx.send(Some(reason.to_string()));
     drop(to_app_tx);
-    drop(to_extension_tx);
+    if tokio::time::timeout(SHUTDOWN_GRACE, bridge_task).await.is_err() {
+        eprintln!("[sidecar] app bridge did not finish within {SHUTDOWN_GRACE:?}");
+    }
 
-    let _ = stdout_task.await;
+    drop(to_extension_tx);
+    let _ = tokio::time::timeout(SHUTDOWN_GRACE, stdout_task).await;
+    drop(debug_registration);
 
     Ok(())
 }
 
+/// Sidecar-local handlers for control messages; every other message is bridged as is.
+fn control_registry(info: SidecarInfo) -> ControlRegistry {
+    let mut registry = ControlRegistry::default();
+    // Still forwarded, so the extension learns about it too
+    let app_link = info.app_link.clone();
+    registry.register("bridge.shutdown", &[Source::App], move |_, message| {
+        let reason = message.pointer("/payload/reason").and_then(|v| v.as_str()).unwrap_or("unknown");
+        eprintln!("[sidecar] app announced shutdown ({reason})");
+        app_link.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).app_shutting_down = true;
+        Ok(Handled::forward())
+    });
+    control::register_debug_handlers(&mut registry);
+    focus::register_control_handlers(&mut registry);
+    info::register(&mut registry, info);
+    registry
+}
+
+/// Resolves with the name of the first termination signal received.
+/// The handlers are installed right away rather than on first poll, so a signal that arrives
+/// while the sidecar is still starting up is not left to the default action.
+fn shutdown_signal() -> impl std::future::Future<Output = &'static str> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let handlers = signal(SignalKind::terminate()).and_then(|term| Ok((term, signal(SignalKind::interrupt())?)));
+        async move {
+            match handlers {
+                Ok((mut term, mut interrupt)) => tokio::select! {
+                    _ = term.recv() => "sigterm",
+                    _ = interrupt.recv() => "sigint",
+                },
+                Err(err) => {
+                    eprintln!("[sidecar] unable to install signal handlers: {err:#}");
+                    let _ = tokio::signal::ctrl_c().await;
+                    "sigint"
+                }
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        async {
+            let _ = tokio::signal::ctrl_c().await;
+            "ctrl-c"
+        }
+    }
+}
+
+/// Refuses to bridge for callers missing from the configured allow-list. The extension gets an
+/// `error.caller_not_allowed` before the host exits, since the browser only reports a closed port.
+/// Returns where the allow-list came from, empty when none is configured.
+fn check_allow_list(caller: Option<&Caller>) -> Result<Vec<String>> {
+    caller::vet_caller(AllowList::load(), caller).or_else(|refusal| {
+        eprintln!("[sidecar] refusing to bridge: {refusal}");
+        let _ = write_native_message(&caller::caller_not_allowed(caller, &refusal));
+        bail!("refusing to bridge: {refusal}")
+    })
+}
+
+fn presence_envelope(state: &str, identity: &Identity, reason: Option<&str>) -> String {
+    let mut payload = json!({
+        "sidecar": state,
+        "timestamp": unix_ms(),
+        "connectionId": identity.connection_id,
+        "browser": identity.browser
+    });
+    if let Some(extension_id) = &identity.extension_id {
+        payload["extensionId"] = json!(extension_id);
+    }
+    if let Some(reason) = reason {
+        payload["reason"] = json!(reason);
+    }
+    json!({ "v": 1, "type": "presence.status", "payload": payload }).to_string()
+}
+
+/// Subscribes to the debug events the app cannot observe itself: `sidecar->app` and
+/// `app->sidecar` traffic already shows up in the app's own hub.
+fn forward_subscription(hub: &DebugHub) -> DebugSubscription {
+    hub.register(&DebugClientOptions {
+        history: HistoryQuery {
+            since_ms: None,
+            limit: Some(0),
+        },
+        filter: DebugFilter {
+            hops: vec![Hop::ExtensionToSidecar, Hop::DebugToSidecar, Hop::Sidecar],
+            ..DebugFilter::default()
+        },
+        format: FrameFormat::Annotated,
+        mode: ClientMode::Observe,
+    })
+}
+
+async fn next_forwarded(forward: &mut Option<DebugSubscription>) -> Option<Arc<str>> {
+    match forward {
+        Some(subscription) => subscription.rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Handles `debug.forward` from the app: `{"enabled": true}` starts sending this sidecar's
+/// debug events over the app link, `{"enabled": false}` stops it.
+fn handle_forward_command(
+    hub: &DebugHub,
+    forward: &mut Option<DebugSubscription>,
+    connection_id: &str,
+    message: &str,
+) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(message).ok()?;
+    if value.get("type").and_then(|t| t.as_str()) != Some("debug.forward") {
+        return None;
+    }
+    let enabled = value
+        .pointer("/payload/enabled")
+        .and_then(|e| e.as_bool())
+        .unwrap_or(true);
+    if !enabled {
+        *forward = None;
+    } else if forward.is_none() {
+        *forward = Some(forward_subscription(hub));
+    }
+    eprintln!("[sidecar] debug forwarding {}", if enabled { "enabled" } else { "disabled" });
+    Some(
+        json!({
+            "v": 1,
+            "type": "debug.forward.status",
+            "payload": { "enabled": enabled, "connectionId": connection_id }
+        })
+        .to_string(),
+    )
+}
+
 async fn bridge_to_app(
-    app_ws: String,
+    app_link: AppLink,
     mut to_app_rx: mpsc::Receiver<String>,
     to_extension_tx: mpsc::Sender<String>,
     hub: DebugHub,
-    connection_id: String,
-    browser: String,
+    registry: Arc<ControlRegistry>,
+    identity: Identity,
+    mut shutdown: watch::Receiver<Option<String>>,
 ) -> Result<()> {
+    let app_ws = app_link.lock().unwrap().url.clone();
+    // Debug events mirrored to the app's hub, see `forward_subscription`
+    let mut forward = env::var("DEBUG_FORWARD")
+        .map(|v| v == "1")
+        .unwrap_or(false)
+        .then(|| forward_subscription(&hub));
+
     loop {
-        match connect_async(&app_ws).await {
+        if shutdown.borrow().is_some() {
+            return Ok(());
+        }
+
+        let connected = tokio::select! {
+            result = connect_async(&app_ws) => result,
+            _ = shutdown.changed() => return Ok(()),
+        };
+
+        match connected {
             Ok((ws_stream, _)) => {
-                let presence_msg = json!({
-                    "v": 1,
-                    "type": "presence.status",
-                    "payload": {
-                        "sidecar": "online",
-                        "timestamp": unix_ms(),
-                        "connectionId": connection_id,
-                        "browser": browser
+                {
+                    let mut link = app_link.lock().unwrap();
+                    link.app_shutting_down = false;
+                    if link.connected_at.is_some() {
+                        link.reconnects += 1;
+                        hub.metrics().increment(Counter::Reconnects, None);
                     }
-                })
-                .to_string();
+                    link.connected = true;
+                    link.connected_at = Some(unix_ms());
+                }
+                let presence_msg = presence_envelope("online", &identity, None);
 
-                hub.broadcast(&presence_msg);
+                hub.broadcast(Hop::Sidecar, &presence_msg);
                 let _ = to_extension_tx.send(presence_msg.clone()).await;
 
                 let (mut write, mut read) = ws_stream.split();
@@ -264,30 +672,92 @@ async fn bridge_to_app(
                 // Send presence to the Tauri app immediately after connection
                 if write.send(Message::Text(presence_msg)).await.is_err() {
                     eprintln!("[sidecar] Failed to send presence message to app");
+                    app_link.lock().unwrap().connected = false;
                     continue;
                 }
 
                 loop {
                     tokio::select! {
+                        // Forwarded events go out before the traffic they describe; each one is
+                        // caused by a message on another branch, so this cannot starve them
+                        biased;
+                        Some(frame) = next_forwarded(&mut forward) => {
+                            // Lag notices only concern this side; the app merges frames
+                            if debug_hub::is_debug_frame(&frame) && write.send(Message::Text(frame.to_string())).await.is_err() {
+                                break;
+                            }
+                        }
                         Some(outgoing) = to_app_rx.recv() => {
+                            let started = Instant::now();
                             if write.send(Message::Text(outgoing.clone())).await.is_err() {
                                 break;
                             }
-                            hub.broadcast(&outgoing);
+                            hub.metrics().observe_since(Histogram::SendLatency, ("link", "app"), started);
+                            hub.broadcast(Hop::SidecarToApp, &outgoing);
+                        }
+                        Ok(()) = shutdown.changed() => {
+                            let reason = shutdown.borrow().clone().unwrap_or_default();
+
+                            // Flush what the extension already handed us before going offline
+                            while let Ok(outgoing) = to_app_rx.try_recv() {
+                                if write.send(Message::Text(outgoing.clone())).await.is_err() {
+                                    break;
+                                }
+                                hub.broadcast(Hop::SidecarToApp, &outgoing);
+                            }
+
+                            let offline_msg = presence_envelope("offline", &identity, Some(&reason));
+                            hub.broadcast(Hop::Sidecar, &offline_msg);
+                            let _ = to_extension_tx.try_send(offline_msg.clone());
+                            let _ = write.send(Message::Text(offline_msg)).await;
+
+                            let frame = CloseFrame {
+                                code: CloseCode::Away,
+                                reason: reason.into(),
+                            };
+                            let _ = write.send(Message::Close(Some(frame))).await;
+                            return Ok(());
                         }
                         incoming = read.next() => {
                             match incoming {
                                 Some(Ok(Message::Text(txt))) => {
-                                    hub.broadcast(&txt);
+                                    hub.broadcast(Hop::AppToSidecar, &txt);
+                                    // debug.forward owns this link's forwarding state, so it is not a registry handler
+                                    let handled = registry
+                                        .dispatch(Source::App, false, &identity.connection_id, &hub, &txt)
+                                        .or_else(|| {
+                                            handle_forward_command(&hub, &mut forward, &identity.connection_id, &txt)
+                                                .map(|reply| Handled::reply(reply, ReplyTo::sender(Source::App)))
+                                        });
+                                    if let Some(handled) = handled {
+                                        if let Some(reply) = handled.reply {
+                                            if handled.reply_to.extension {
+                                                hub.broadcast(Hop::Sidecar, &reply);
+                                                let _ = to_extension_tx.send(reply.clone()).await;
+                                            }
+                                            if handled.reply_to.app {
+                                                hub.broadcast(Hop::SidecarToApp, &reply);
+                                                if write.send(Message::Text(reply)).await.is_err() {
+                                                    break;
+                                                }
+                                            }
+                                        }
+                                        if !handled.forward {
+                                            continue;
+                                        }
+                                    }
                                     if to_extension_tx.send(txt).await.is_err() {
+                                        hub.metrics()
+                                            .increment(Counter::RoutingFailures, Some(("reason", "extension_queue_closed")));
                                         break;
                                     }
                                 }
                                 Some(Ok(Message::Binary(bin))) => {
-                                    hub.broadcast(&format!(r#"{{"v":1,"type":"error.binary","payload":{{"bytes":{}}}}}"#, bin.len()));
+                                    hub.broadcast(Hop::Sidecar, &format!(r#"{{"v":1,"type":"error.binary","payload":{{"bytes":{}}}}}"#, bin.len()));
                                 }
                                 Some(Ok(Message::Ping(payload))) => {
                                     hub.broadcast(
+                                        Hop::Sidecar,
                                         &json!({
                                             "v": 1,
                                             "type": "debug.ping",
@@ -301,6 +771,7 @@ async fn bridge_to_app(
                                 }
                                 Some(Ok(Message::Pong(payload))) => {
                                     hub.broadcast(
+                                        Hop::Sidecar,
                                         &json!({
                                             "v": 1,
                                             "type": "debug.pong",
@@ -321,6 +792,7 @@ async fn bridge_to_app(
                                     });
 
                                     hub.broadcast(
+                                        Hop::Sidecar,
                                         &json!({
                                             "v": 1,
                                             "type": "debug.close",
@@ -342,149 +814,237 @@ async fn bridge_to_app(
                         }
                     }
                 }
+                app_link.lock().unwrap().connected = false;
             }
             Err(err) => {
-                eprintln!("[sidecar] unable to connect to app ws {app_ws}: {err:#}");
-                tokio::time::sleep(Duration::from_secs(1)).await;
+                if !app_link.lock().unwrap().app_shutting_down {
+                    eprintln!("[sidecar] unable to connect to app ws {app_ws}: {err:#}");
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+                    _ = shutdown.changed() => return Ok(()),
+                }
             }
         }
     }
 }
 
-fn handle_control_message(message: &str) -> Result<bool> {
-    let value: serde_json::Value = match serde_json::from_str(message) {
-        Ok(val) => val,
-        Err(_) => return Ok(false),
-    };
-
-    let Some(message_type) = value.get("type").and_then(|v| v.as_str()) else {
-        return Ok(false);
-    };
-
-    if message_type != "focus.window" {
-        return Ok(false);
-    }
-
-    if let Some(payload_value) = value.get("payload") {
-        match serde_json::from_value::<focus::FocusWindowPayload>(payload_value.clone()) {
-            Ok(payload) => {
-                println!("[sidecar] focus.window request: {payload:?}");
-                if let Err(err) = focus::focus_window(&payload) {
-                    eprintln!("[sidecar] focus.window failed: {err:#}");
-                }
-            }
-            Err(err) => {
-                eprintln!("[sidecar] focus.window payload parse error: {err:#}");
+/// Serves `/health`, `/connections`, `/config` and `/metrics` on the debug port.
+fn debug_http_response(
+    hub: &DebugHub,
+    context: &DebugContext,
+    port: u16,
+    outbox_depth: usize,
+    request: &HttpRequest,
+) -> HttpResponse {
+    let link = context.app_link.lock().unwrap().clone();
+    match request.path.as_str() {
+        "/health" => {
+            let response = HttpResponse::json(&json!({
+                "status": if link.connected { "ok" } else { "disconnected" },
+                "uptimeMs": context.started.elapsed().as_millis(),
+                "appConnected": link.connected,
+            }));
+            // Lets `curl -f` health checks fail while the app link is down
+            if link.connected {
+                response
+            } else {
+                response.with_status(debug_http::StatusCode::SERVICE_UNAVAILABLE)
             }
         }
+        "/connections" => HttpResponse::json(&json!([{
+            "connectionId": context.connection_id,
+            "browser": context.browser,
+            "extensionId": context.caller.as_ref().map(|caller| &caller.extension_id),
+            "connectedAt": link.connected_at,
+            "app": link,
+            "counters": hub.stats().hops,
+        }])),
+        "/config" => {
+            let stats = hub.stats();
+            HttpResponse::json(&json!({
+                "connectionId": context.connection_id,
+                "browser": context.browser,
+                "caller": context.caller,
+                "pid": std::process::id(),
+                "appWs": link.url,
+                "debugPort": port,
+                "debugFormat": env::var("DEBUG_WS_FORMAT").unwrap_or_else(|_| "annotated".to_string()),
+                "historyDepth": stats.history_depth,
+                "peerBuffer": stats.peer_buffer,
+                "injectEnabled": context.inject_enabled,
+                "forward": env::var("DEBUG_FORWARD").is_ok_and(|v| v == "1"),
+                "capture": stats.capture,
+            }))
+        }
+        "/metrics" => {
+            let mut body = String::new();
+            hub.stats().write_prometheus(&mut body);
+            hub.metrics().write_prometheus(&mut body);
+            body.push_str("# HELP bridge_app_connected Whether the link to the app is up.\n");
+            body.push_str("# TYPE bridge_app_connected gauge\n");
+            body.push_str(&format!("bridge_app_connected {}\n", u8::from(link.connected)));
+            body.push_str("# HELP bridge_outbox_depth Messages queued for the app.\n");
+            body.push_str("# TYPE bridge_outbox_depth gauge\n");
+            body.push_str(&format!("bridge_outbox_depth {}\n", outbox_depth));
+            HttpResponse::text(debug_http::PROMETHEUS_TEXT, body)
+        }
+        path => HttpResponse::not_found(path),
     }
-
-    Ok(true)
 }
 
-async fn spawn_debug_ws(port: u16, hub: DebugHub, to_app_tx: mpsc::Sender<String>) -> Result<()> {
-    let listener = TcpListener::bind(("127.0.0.1", port))
-        .await
-        .with_context(|| format!("binding debug ws on 127.0.0.1:{port}"))?;
+async fn spawn_debug_ws(
+    listener: TcpListener,
+    hub: DebugHub,
+    to_app_tx: mpsc::Sender<String>,
+    context: DebugContext,
+) -> Result<()> {
+    let secret: Option<Arc<str>> = env::var("BRIDGE_SECRET").ok().filter(|s| !s.is_empty()).map(Into::into);
+    let port = listener.local_addr()?.port();
+    let context = Arc::new(context);
 
     loop {
         let (stream, _) = listener.accept().await?;
-        let ws = accept_async(stream).await?;
-        let hub_for_client = hub.clone();
-        let to_app_tx_for_client = to_app_tx.clone();
-        tokio::spawn(async move {
-            let (mut write, mut read) = ws.split();
-            let mut rx = hub_for_client.register();
+        // Served on its own task so a slow HTTP request or handshake never holds up the next accept
+        tokio::spawn(serve_debug_connection(
+            stream,
+            hub.clone(),
+            to_app_tx.clone(),
+            context.clone(),
+            secret.clone(),
+            port,
+        ));
+    }
+}
 
-            loop {
-                tokio::select! {
-                    Some(outbound) = rx.recv() => {
-                        if write.send(Message::Text(outbound)).await.is_err() {
-                            break;
-                        }
-                    }
-                    incoming = read.next() => {
-                        match incoming {
-                            Some(Ok(Message::Text(txt))) => {
-                                hub_for_client.broadcast(&txt);
-                                if to_app_tx_for_client.send(txt).await.is_err() {
-                                    break;
-                                }
-                            }
-                            Some(Ok(Message::Binary(bin))) => {
-                                let txt = json!({
-                                    "v": 1,
-                                    "type": "debug.binary",
-                                    "payload": { "bytes": bin.len() }
-                                })
-                                .to_string();
-                                hub_for_client.broadcast(&txt);
-                            }
-                            Some(Ok(Message::Ping(payload))) => {
-                                let info = json!({
-                                    "v": 1,
-                                    "type": "debug.ping",
-                                    "payload": { "bytes": payload.len() }
-                                })
-                                .to_string();
-                                hub_for_client.broadcast(&info);
-                                if write.send(Message::Pong(payload)).await.is_err() {
-                                    break;
-                                }
-                            }
-                            Some(Ok(Message::Pong(payload))) => {
-                                let info = json!({
-                                    "v": 1,
-                                    "type": "debug.pong",
-                                    "payload": { "bytes": payload.len() }
-                                })
-                                .to_string();
-                                hub_for_client.broadcast(&info);
-                            }
-                            Some(Ok(Message::Close(frame))) => {
-                                let code = frame.as_ref().map(|f| u16::from(f.code));
-                                let reason = frame.as_ref().and_then(|f| {
-                                    let text = f.reason.to_string();
-                                    if text.is_empty() { None } else { Some(text) }
-                                });
-                                let info = json!({
-                                    "v": 1,
-                                    "type": "debug.close",
-                                    "payload": { "code": code, "reason": reason }
-                                })
-                                .to_string();
-                                hub_for_client.broadcast(&info);
+/// Answers one connection to the debug port: an HTTP request, or a debug client for as long as
+/// it stays connected.
+async fn serve_debug_connection(
+    stream: TcpStream,
+    hub: DebugHub,
+    to_app_tx: mpsc::Sender<String>,
+    context: Arc<DebugContext>,
+    secret: Option<Arc<str>>,
+    port: u16,
+) {
+    // Plain HTTP requests get the introspection endpoints; upgrades continue as debug clients
+    let (stream, request) = match debug_http::read_request(stream).await {
+        Ok(read) => read,
+        Err(_) => return,
+    };
+    if let Some(request) = request {
+        let outbox_depth = to_app_tx.max_capacity() - to_app_tx.capacity();
+        let response = debug_http_response(&hub, &context, port, outbox_depth, &request);
+        if let Err(err) = debug_http::respond(stream, &request, response).await {
+            eprintln!("[sidecar] debug http {} failed: {err:#}", request.path);
+        }
+        return;
+    }
+    let (ws, options) = match debug_hub::accept_debug_client(Role::Sidecar, stream, secret.as_deref()).await {
+        Ok(accepted) => accepted,
+        Err(err) => {
+            // Discovery probes connect and hang up without a handshake; only log refusals
+            if debug_hub::is_refused_handshake(&err) {
+                hub.metrics().increment(Counter::HandshakeRejections, None);
+                eprintln!("[sidecar] debug client rejected: {err:#}");
+            }
+            return;
+        }
+    };
+    let (mut write, mut read) = ws.split();
+    let mut subscription = hub.register(&options);
+
+    loop {
+        tokio::select! {
+            Some(outbound) = subscription.rx.recv() => {
+                if write.send(Message::Text(outbound.to_string())).await.is_err() {
+                    break;
+                }
+            }
+            incoming = read.next() => {
+                match incoming {
+                    Some(Ok(Message::Text(txt))) => {
+                        // Debug clients get every reply themselves
+                        let reply = debug_hub::handle_subscription_command(&hub, subscription.id, &txt)
+                            .or_else(|| {
+                                context.registry
+                                    .dispatch(Source::Debug, options.mode == ClientMode::Inject, &context.connection_id, &hub, &txt)
+                                    .and_then(|handled| handled.reply)
+                            });
+                        if let Some(reply) = reply {
+                            if write.send(Message::Text(reply)).await.is_err() {
                                 break;
                             }
-                            Some(Ok(Message::Frame(_))) => { /* ignore */ }
-                            Some(Err(err)) => {
-                                eprintln!("[sidecar] debug ws client error: {err:#}");
+                            continue;
+                        }
+                        if options.mode != ClientMode::Inject {
+                            if write.send(Message::Text(debug_hub::read_only_notice())).await.is_err() {
                                 break;
                             }
-                            None => break,
+                            continue;
+                        }
+                        hub.broadcast_injected(subscription.id, &txt);
+                        if to_app_tx.send(txt).await.is_err() {
+                            hub
+                                .metrics()
+                                .increment(Counter::RoutingFailures, Some(("reason", "app_queue_closed")));
+                            break;
+                        }
+                    }
+                    Some(Ok(Message::Binary(bin))) => {
+                        let txt = json!({
+                            "v": 1,
+                            "type": "debug.binary",
+                            "payload": { "bytes": bin.len() }
+                        })
+                        .to_string();
+                        hub.broadcast(Hop::Sidecar, &txt);
+                    }
+                    Some(Ok(Message::Ping(payload))) => {
+                        let info = json!({
+                            "v": 1,
+                            "type": "debug.ping",
+                            "payload": { "bytes": payload.len() }
+                        })
+                        .to_string();
+                        hub.broadcast(Hop::Sidecar, &info);
+                        if write.send(Message::Pong(payload)).await.is_err() {
+                            break;
                         }
                     }
+                    Some(Ok(Message::Pong(payload))) => {
+                        let info = json!({
+                            "v": 1,
+                            "type": "debug.pong",
+                            "payload": { "bytes": payload.len() }
+                        })
+                        .to_string();
+                        hub.broadcast(Hop::Sidecar, &info);
+                    }
+                    Some(Ok(Message::Close(frame))) => {
+                        let code = frame.as_ref().map(|f| u16::from(f.code));
+                        let reason = frame.as_ref().and_then(|f| {
+                            let text = f.reason.to_string();
+                            if text.is_empty() { None } else { Some(text) }
+                        });
+                        let info = json!({
+                            "v": 1,
+                            "type": "debug.close",
+                            "payload": { "code": code, "reason": reason }
+                        })
+                        .to_string();
+                        hub.broadcast(Hop::Sidecar, &info);
+                        break;
+                    }
+                    Some(Ok(Message::Frame(_))) => { /* ignore */ }
+                    Some(Err(err)) => {
+                        eprintln!("[sidecar] debug ws client error: {err:#}");
+                        break;
+                    }
+                    None => break,
                 }
             }
-        });
-    }
-}
-
-#[derive(Clone, Default)]
-struct DebugHub {
-    peers: Arc<Mutex<Vec<mpsc::UnboundedSender<String>>>>,
-}
-
-impl DebugHub {
-    fn broadcast(&self, message: &str) {
-        let mut peers = self.peers.lock().unwrap();
-        peers.retain(|tx| tx.send(message.to_owned()).is_ok());
-    }
-
-    fn register(&self) -> mpsc::UnboundedReceiver<String> {
-        let (tx, rx) = mpsc::unbounded_channel();
-        self.peers.lock().unwrap().push(tx);
-        rx
+        }
     }
 }
 
@@ -511,11 +1071,3 @@ fn write_native_message(msg: &str) -> Result<()> {
     stdout.flush()?;
     Ok(())
 }
-
-fn unix_ms() -> u128 {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map(|d| d.as_millis())
-        .unwrap_or_default()
-}
@@ -0,0 +1,148 @@
+//! Connects debug clients to a running sidecar and checks that only clients holding the bridge
+//! secret can send messages on to the app.
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const SECRET: &str = "debug-clients-secret";
+
+type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// A sidecar with its debug socket enabled and guarded by `SECRET`.
+struct Sidecar {
+    child: Child,
+    index_dir: PathBuf,
+}
+
+impl Sidecar {
+    fn spawn(app_port: u16, name: &str) -> Self {
+        let index_dir = std::env::temp_dir().join(format!("bridge-debug-clients-{name}-{}", std::process::id()));
+        let child = Command::new(env!("CARGO_BIN_EXE_bridge-sidecar"))
+            .env("APP_WS", format!("ws://127.0.0.1:{app_port}"))
+            .env("BRIDGE_DEBUG_INDEX", &index_dir)
+            .env("BRIDGE_SECRET", SECRET)
+            .env("SIDE_CAR_DEBUG_WS", "1")
+            .env_remove("DEBUG_WS_PORT")
+            .env_remove("BRIDGE_ALLOWED_EXTENSIONS")
+            .env_remove("BRIDGE_ALLOWLIST")
+            .env_remove("BRIDGE_CAPTURE")
+            .env_remove("DEBUG_FORWARD")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("spawning the sidecar");
+        Self { child, index_dir }
+    }
+
+    /// Port of the debug socket, read from the sidecar's own index entry.
+    async fn debug_port(&self) -> u16 {
+        let entry = self.index_dir.join(format!("sidecar-{}.json", self.child.id()));
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            if let Ok(bytes) = std::fs::read(&entry) {
+                let endpoint: Value = serde_json::from_slice(&bytes).unwrap();
+                return endpoint["port"].as_u64().unwrap() as u16;
+            }
+            assert!(Instant::now() < deadline, "sidecar did not register {}", entry.display());
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+}
+
+impl Drop for Sidecar {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.index_dir);
+    }
+}
+
+async fn accept_app(listener: &TcpListener) -> WebSocketStream<TcpStream> {
+    let (stream, _) = tokio::time::timeout(TIMEOUT, listener.accept())
+        .await
+        .expect("sidecar connects to the app")
+        .unwrap();
+    tokio_tungstenite::accept_async(stream).await.unwrap()
+}
+
+/// Next message the app got that is not presence or keepalive traffic, if one arrives in time.
+async fn next_app_frame(app: &mut WebSocketStream<TcpStream>, wait: Duration) -> Option<Value> {
+    let deadline = tokio::time::Instant::now() + wait;
+    loop {
+        let message = tokio::time::timeout_at(deadline, app.next()).await.ok()??.ok()?;
+        if let Message::Text(text) = message {
+            let frame: Value = serde_json::from_str(&text).unwrap();
+            if frame["type"] != "presence.status" {
+                return Some(frame);
+            }
+        }
+    }
+}
+
+async fn connect(port: u16, query: &str, secret: Option<&str>) -> Client {
+    let mut request = format!("ws://127.0.0.1:{port}/{query}").into_client_request().unwrap();
+    if let Some(secret) = secret {
+        request.headers_mut().insert("X-Bridge-Secret", secret.parse().unwrap());
+    }
+    tokio_tungstenite::connect_async(request).await.expect("debug handshake").0
+}
+
+/// Next `debug.error` sent to a debug client, skipping mirrored frames and status messages.
+async fn next_debug_error(client: &mut Client) -> Value {
+    loop {
+        let message = tokio::time::timeout(TIMEOUT, client.next())
+            .await
+            .expect("reply from the sidecar")
+            .expect("debug socket open")
+            .unwrap();
+        if let Message::Text(text) = message {
+            let frame: Value = serde_json::from_str(&text).unwrap();
+            if frame["type"] == "debug.error" {
+                return frame;
+            }
+        }
+    }
+}
+
+fn probe(id: &str) -> Message {
+    Message::Text(format!(r#"{{"v":1,"type":"test.probe","payload":{{"id":"{id}"}}}}"#))
+}
+
+#[tokio::test]
+async fn observers_are_refused_and_only_the_secret_holder_reaches_the_app() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let sidecar = Sidecar::spawn(listener.local_addr().unwrap().port(), "observe");
+    let mut app = accept_app(&listener).await;
+    let port = sidecar.debug_port().await;
+
+    // The secret alone does not make a client an injector
+    let mut observer = connect(port, "?secret=debug-clients-secret", None).await;
+    observer.send(probe("observer")).await.unwrap();
+    let notice = next_debug_error(&mut observer).await;
+    assert!(notice["payload"]["message"].as_str().unwrap().contains("read-only"));
+
+    // Asking for inject without the secret fails the handshake outright
+    let refused = tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{port}/?mode=inject")).await;
+    match refused {
+        Err(tokio_tungstenite::tungstenite::Error::Http(response)) => assert_eq!(response.status(), 403),
+        other => panic!("expected a 403, got {:?}", other.map(|_| ())),
+    }
+
+    let mut injector = connect(port, "?mode=inject", Some(SECRET)).await;
+    injector.send(probe("injector")).await.unwrap();
+
+    // Only the injected probe reaches the app; the observer's never does
+    let forwarded = next_app_frame(&mut app, TIMEOUT).await.expect("injected frame");
+    assert_eq!(forwarded["type"], "test.probe");
+    assert_eq!(forwarded["payload"]["id"], "injector");
+    assert_eq!(next_app_frame(&mut app, Duration::from_millis(300)).await, None);
+}
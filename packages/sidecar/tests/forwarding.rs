@@ -0,0 +1,133 @@
+//! Checks that a sidecar sends its own debug events over the app link only while the app has
+//! asked for them with `debug.forward`.
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A sidecar whose native messaging input the test writes.
+struct Sidecar {
+    child: Child,
+    stdin: ChildStdin,
+    index_dir: PathBuf,
+}
+
+impl Sidecar {
+    fn spawn(app_port: u16, name: &str) -> Self {
+        let index_dir = std::env::temp_dir().join(format!("bridge-forwarding-{name}-{}", std::process::id()));
+        let mut child = Command::new(env!("CARGO_BIN_EXE_bridge-sidecar"))
+            .env("APP_WS", format!("ws://127.0.0.1:{app_port}"))
+            .env("BRIDGE_DEBUG_INDEX", &index_dir)
+            .env_remove("BRIDGE_ALLOWED_EXTENSIONS")
+            .env_remove("BRIDGE_ALLOWLIST")
+            .env_remove("BRIDGE_CAPTURE")
+            .env_remove("DEBUG_FORWARD")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("spawning the sidecar");
+        Self {
+            stdin: child.stdin.take().unwrap(),
+            child,
+            index_dir,
+        }
+    }
+
+    /// Sends `message` as the extension would over native messaging.
+    fn send_from_extension(&mut self, message: &str) {
+        self.stdin.write_all(&(message.len() as u32).to_le_bytes()).unwrap();
+        self.stdin.write_all(message.as_bytes()).unwrap();
+        self.stdin.flush().unwrap();
+    }
+}
+
+impl Drop for Sidecar {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.index_dir);
+    }
+}
+
+async fn accept_app(listener: &TcpListener) -> WebSocketStream<TcpStream> {
+    let (stream, _) = tokio::time::timeout(TIMEOUT, listener.accept())
+        .await
+        .expect("sidecar connects to the app")
+        .unwrap();
+    tokio_tungstenite::accept_async(stream).await.unwrap()
+}
+
+async fn next_app_frame(app: &mut WebSocketStream<TcpStream>) -> Value {
+    loop {
+        let message = tokio::time::timeout(TIMEOUT, app.next())
+            .await
+            .expect("message from the sidecar")
+            .expect("app socket open")
+            .unwrap();
+        if let Message::Text(text) = message {
+            return serde_json::from_str(&text).unwrap();
+        }
+    }
+}
+
+/// Sends a probe from the extension and returns every `debug.frame` the app got up to and
+/// including the probe itself.
+async fn probe(sidecar: &mut Sidecar, app: &mut WebSocketStream<TcpStream>, id: &str) -> Vec<Value> {
+    sidecar.send_from_extension(&format!(r#"{{"v":1,"type":"test.probe","payload":{{"id":"{id}"}}}}"#));
+    let mut frames = Vec::new();
+    loop {
+        let frame = next_app_frame(app).await;
+        if frame["type"] == "test.probe" {
+            assert_eq!(frame["payload"]["id"], id);
+            return frames;
+        }
+        if frame["type"] == "debug.frame" {
+            frames.push(frame);
+        }
+    }
+}
+
+async fn set_forwarding(app: &mut WebSocketStream<TcpStream>, enabled: bool) {
+    let command = format!(r#"{{"v":1,"type":"debug.forward","payload":{{"enabled":{enabled}}}}}"#);
+    app.send(Message::Text(command)).await.unwrap();
+    loop {
+        let reply = next_app_frame(app).await;
+        if reply["type"] == "debug.forward.status" {
+            assert_eq!(reply["payload"]["enabled"], enabled);
+            return;
+        }
+    }
+}
+
+#[tokio::test]
+async fn debug_frames_reach_the_app_only_while_forwarding_is_on() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut sidecar = Sidecar::spawn(listener.local_addr().unwrap().port(), "toggle");
+    let mut app = accept_app(&listener).await;
+    assert_eq!(next_app_frame(&mut app).await["payload"]["sidecar"], "online");
+
+    assert!(probe(&mut sidecar, &mut app, "before").await.is_empty());
+
+    set_forwarding(&mut app, true).await;
+    let frames = probe(&mut sidecar, &mut app, "during").await;
+    let probe_frame = frames
+        .iter()
+        .find(|frame| frame["payload"]["type"] == "test.probe")
+        .expect("the probe is forwarded as a debug.frame");
+    assert_eq!(probe_frame["payload"]["hop"], "extension->sidecar");
+    assert_eq!(probe_frame["payload"]["message"]["payload"]["id"], "during");
+    // What the app already sees on its own link is not sent twice
+    assert!(frames.iter().all(|frame| frame["payload"]["hop"] != "sidecar->app"));
+
+    set_forwarding(&mut app, false).await;
+    assert!(probe(&mut sidecar, &mut app, "after").await.is_empty());
+}
@@ -0,0 +1,238 @@
+//! Runs the sidecar binary against a stand-in app socket and checks which presence frames each
+//! way of ending a session produces.
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A sidecar process with the extension side of native messaging in the test's hands.
+struct Sidecar {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    to_extension: mpsc::Receiver<Value>,
+    index_dir: PathBuf,
+}
+
+impl Sidecar {
+    fn spawn(app_port: u16, name: &str) -> Self {
+        let index_dir = std::env::temp_dir().join(format!("bridge-sidecar-{name}-{}", std::process::id()));
+        let mut child = Command::new(env!("CARGO_BIN_EXE_bridge-sidecar"))
+            .env("APP_WS", format!("ws://127.0.0.1:{app_port}"))
+            .env("BRIDGE_DEBUG_INDEX", &index_dir)
+            .env_remove("BRIDGE_ALLOWED_EXTENSIONS")
+            .env_remove("BRIDGE_ALLOWLIST")
+            .env_remove("BRIDGE_CAPTURE")
+            .env_remove("DEBUG_FORWARD")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("spawning the sidecar");
+
+        let mut stdout = child.stdout.take().unwrap();
+        let (tx, to_extension) = mpsc::channel();
+        thread::spawn(move || {
+            let mut len = [0u8; 4];
+            while stdout.read_exact(&mut len).is_ok() {
+                let mut body = vec![0u8; u32::from_le_bytes(len) as usize];
+                if stdout.read_exact(&mut body).is_err() {
+                    break;
+                }
+                let frame = serde_json::from_slice(&body).expect("native message is JSON");
+                if tx.send(frame).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            stdin: child.stdin.take(),
+            child,
+            to_extension,
+            index_dir,
+        }
+    }
+
+    fn next_to_extension(&self) -> Value {
+        self.to_extension.recv_timeout(TIMEOUT).expect("frame for the extension")
+    }
+
+    /// Presence frames the extension got, skipping anything else.
+    fn next_presence(&self) -> Value {
+        loop {
+            let frame = self.next_to_extension();
+            if frame["type"] == "presence.status" {
+                return frame;
+            }
+        }
+    }
+
+    fn close_stdin(&mut self) {
+        self.stdin.take();
+    }
+
+    fn wait(&mut self) -> ExitStatus {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                return status;
+            }
+            assert!(Instant::now() < deadline, "sidecar did not exit within {TIMEOUT:?}");
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+impl Drop for Sidecar {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.index_dir);
+    }
+}
+
+async fn accept_app(listener: &TcpListener) -> WebSocketStream<TcpStream> {
+    let (stream, _) = tokio::time::timeout(TIMEOUT, listener.accept())
+        .await
+        .expect("sidecar connects to the app")
+        .unwrap();
+    tokio_tungstenite::accept_async(stream).await.unwrap()
+}
+
+async fn next_app_message(app: &mut WebSocketStream<TcpStream>) -> Message {
+    loop {
+        let message = tokio::time::timeout(TIMEOUT, app.next())
+            .await
+            .expect("message from the sidecar")
+            .expect("app socket open")
+            .unwrap();
+        match &message {
+            Message::Text(text) if !is_presence(text) => continue,
+            Message::Ping(_) | Message::Pong(_) => continue,
+            _ => return message,
+        }
+    }
+}
+
+fn is_presence(text: &str) -> bool {
+    serde_json::from_str::<Value>(text).is_ok_and(|frame| frame["type"] == "presence.status")
+}
+
+fn presence(message: &Message) -> Value {
+    match message {
+        Message::Text(text) => serde_json::from_str(text).unwrap(),
+        other => panic!("expected presence.status, got {other:?}"),
+    }
+}
+
+fn assert_close(message: &Message, reason: &str) {
+    match message {
+        Message::Close(Some(frame)) => {
+            assert_eq!(frame.code, CloseCode::Away);
+            assert_eq!(frame.reason, reason);
+        }
+        other => panic!("expected a close frame, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn closing_stdin_reports_offline_and_closes_the_app_socket() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut sidecar = Sidecar::spawn(listener.local_addr().unwrap().port(), "stdin");
+    let mut app = accept_app(&listener).await;
+
+    let online = presence(&next_app_message(&mut app).await);
+    assert_eq!(online["payload"]["sidecar"], "online");
+    assert_eq!(sidecar.next_presence()["payload"]["sidecar"], "online");
+
+    sidecar.close_stdin();
+
+    let offline = presence(&next_app_message(&mut app).await);
+    assert_eq!(offline["payload"]["sidecar"], "offline");
+    assert_eq!(offline["payload"]["reason"], "stdin-eof");
+    assert_eq!(offline["payload"]["connectionId"], online["payload"]["connectionId"]);
+    assert_close(&next_app_message(&mut app).await, "stdin-eof");
+
+    let to_extension = sidecar.next_presence();
+    assert_eq!(to_extension["payload"]["sidecar"], "offline");
+    assert_eq!(to_extension["payload"]["reason"], "stdin-eof");
+    assert!(sidecar.wait().success());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn sigterm_reports_offline_and_exits_while_stdin_is_open() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut sidecar = Sidecar::spawn(listener.local_addr().unwrap().port(), "sigterm");
+    let mut app = accept_app(&listener).await;
+    assert_eq!(presence(&next_app_message(&mut app).await)["payload"]["sidecar"], "online");
+
+    let killed = Command::new("kill")
+        .args(["-TERM", &sidecar.child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+
+    let offline = presence(&next_app_message(&mut app).await);
+    assert_eq!(offline["payload"]["sidecar"], "offline");
+    assert_eq!(offline["payload"]["reason"], "sigterm");
+    assert_close(&next_app_message(&mut app).await, "sigterm");
+    // The blocking stdin reader must not keep the process alive
+    assert!(sidecar.wait().success());
+    assert!(sidecar.stdin.is_some());
+}
+
+#[tokio::test]
+async fn dropping_the_app_socket_reconnects_without_going_offline() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut sidecar = Sidecar::spawn(listener.local_addr().unwrap().port(), "drop");
+    let mut app = accept_app(&listener).await;
+    let first = presence(&next_app_message(&mut app).await);
+    assert_eq!(sidecar.next_presence()["payload"]["sidecar"], "online");
+
+    // No close handshake: the app vanished
+    drop(app);
+
+    let mut app = accept_app(&listener).await;
+    let second = presence(&next_app_message(&mut app).await);
+    assert_eq!(second["payload"]["sidecar"], "online");
+    assert_eq!(second["payload"]["connectionId"], first["payload"]["connectionId"]);
+    // The extension sees the link come back, never an offline in between
+    assert_eq!(sidecar.next_presence()["payload"]["sidecar"], "online");
+
+    sidecar.close_stdin();
+    assert_eq!(presence(&next_app_message(&mut app).await)["payload"]["sidecar"], "offline");
+    assert_eq!(sidecar.next_presence()["payload"]["sidecar"], "offline");
+    assert!(sidecar.wait().success());
+}
+
+#[tokio::test]
+async fn app_shutdown_announcement_reaches_the_extension() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut sidecar = Sidecar::spawn(listener.local_addr().unwrap().port(), "announce");
+    let mut app = accept_app(&listener).await;
+    assert_eq!(presence(&next_app_message(&mut app).await)["payload"]["sidecar"], "online");
+    assert_eq!(sidecar.next_presence()["payload"]["sidecar"], "online");
+
+    let announcement = r#"{"v":1,"type":"bridge.shutdown","payload":{"reason":"quit","timestamp":1}}"#;
+    app.send(Message::Text(announcement.to_string())).await.unwrap();
+    app.close(None).await.unwrap();
+
+    let forwarded = sidecar.next_to_extension();
+    assert_eq!(forwarded["type"], "bridge.shutdown");
+    assert_eq!(forwarded["payload"]["reason"], "quit");
+
+    sidecar.close_stdin();
+    assert!(sidecar.wait().success());
+}
@@ -7,6 +7,7 @@ import { EnvelopeSchema, type Envelope } from "@bridge/shared-proto";
 const EXT_PATH = process.env.EXT_PATH ?? path.resolve("packages/ext-plasmo/build");
 const EXT_ID = process.env.EXT_ID;
 const DEBUG_WS = process.env.DEBUG_WS ?? "ws://127.0.0.1:17888";
+const BRIDGE_SECRET = process.env.BRIDGE_SECRET;
 const TEST_URL = process.env.TEST_URL ?? "https://example.com/";
 
 if (!EXT_ID) {
@@ -23,8 +24,17 @@ async function main() {
   const page = context.pages()[0] ?? (await context.newPage());
   await page.goto("about:blank");
 
+  if (!BRIDGE_SECRET) {
+    throw new Error("Set BRIDGE_SECRET to the app's secret so the tester can inject commands");
+  }
+  // Raw frames keep envelopes unwrapped; inject mode lets the tester send commands
+  const debugUrl = new URL(DEBUG_WS);
+  debugUrl.searchParams.set("format", "raw");
+  debugUrl.searchParams.set("mode", "inject");
+  debugUrl.searchParams.set("secret", BRIDGE_SECRET);
+
   console.log("[tester] connecting to debug ws:", DEBUG_WS);
-  const ws = await openWs(DEBUG_WS);
+  const ws = await openWs(debugUrl.toString());
 
   console.log("[tester] requesting initial tabs.list from extension");
   await sendRuntimeMessage(context, EXT_ID, { type: "test.triggerTabsList" });